@@ -0,0 +1,33 @@
+use rand::Rng;
+use crate::Instruction::Instruction;
+use crate::Noise::KrausChannel;
+use crate::Precision::Precision;
+use crate::QuantumError::QuantumError;
+
+/**
+Simulator state a QuantumCircuit can run on (ex QuantumRegister for pure states or DensityMatrix for mixed states)
+The circuit takes care of classical bits and conditions, the backend only has to apply gates and noise channels, measure and reset qubits
+*/
+pub trait Backend<T: Precision> {
+    fn num_qubits(&self) -> usize;
+
+    /**
+    Apply one gate instruction (never a measure, reset or barrier) with its controls
+    Errors for qubits out of range or used twice, like Gate::apply_with_controls
+    */
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError>;
+
+    /**
+    Measure the given qubits, collapse the state onto the outcome and return the bit of each qubit in the order given
+    */
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError>;
+
+    /**
+    Apply a noise channel to the target qubits, exactly or by drawing one of its Kraus operators
+    Errors when the channel does not act on as many qubits as there are targets
+    */
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError>;
+
+    // Put a qubit back into |0>
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError>;
+}
@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use nalgebra::{Complex, DMatrix};
+use nalgebra::linalg::SymmetricEigen;
+use num_traits::{One, Zero};
+use rand::Rng;
+use crate::Backend::Backend;
+use crate::Instruction::{Control, Instruction};
+use crate::Matrix::Matrix;
+use crate::Noise::KrausChannel;
+use crate::Precision::{Precision, real};
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::{QuantumRegister, apply_local_matrix, draw_outcome, sample_indices};
+
+/**
+Mixed state of n qubits stored as its 2^n x 2^n density matrix ρ
+A pure state |ψ> is ρ = |ψ><ψ|, a mixture of pure states |ψ_i> with probabilities p_i is ρ = Σ p_i |ψ_i><ψ_i|
+Qubits are ordered like in QuantumRegister: qubit q is bit (n - 1 - q) of the row and column index
+Memory and work grow like 4^n instead of 2^n, in exchange noise and other non-unitary operations are exact
+*/
+pub struct DensityMatrix<T: Precision = f32> {
+    pub rho: DMatrix<Complex<T>>
+}
+
+impl<T: Precision> DensityMatrix<T> {
+    // Pure basis state |index><index| of a register with size basis states
+    pub fn new_from_int(index: usize, size: usize) -> Self {
+        let mut rho: DMatrix<Complex<T>> = DMatrix::zeros(size, size);
+        rho[(index, index)] = Complex::one();
+        Self {rho}
+    }
+
+    // Pure state |ψ><ψ| of a register
+    pub fn new_from_register(register: &QuantumRegister<T>) -> Self {
+        let psi = register.state.as_ref();
+        Self {rho: psi * psi.adjoint()}
+    }
+
+    /**
+    Mixture Σ p_i |ψ_i><ψ_i| of pure states, the weights are scaled so they add up to 1
+    Every register has to hold the same number of qubits and every weight has to be non negative
+    */
+    pub fn new_mixed(states: &[(T, QuantumRegister<T>)]) -> Result<Self, QuantumError> {
+        let size: usize = match states.first() {
+            Some((_, register)) => register.state.len(),
+            None => return Err(QuantumError::InvalidDensityMatrix("a mixture needs at least one state".to_string())),
+        };
+        let total: T = states.iter().fold(T::zero(), |total, (weight, _)| total + *weight);
+        let mut rho: DMatrix<Complex<T>> = DMatrix::zeros(size, size);
+        for (weight, register) in states.iter() {
+            if register.state.len() != size {
+                return Err(QuantumError::DimensionMismatch {expected: size, found: register.state.len()})
+            }
+            if *weight < T::zero() {
+                return Err(QuantumError::InvalidDensityMatrix(format!("mixture weight {} is negative", weight)))
+            }
+            rho += Self::new_from_register(register).rho * Complex::from(*weight / total);
+        }
+        Ok(Self {rho})
+    }
+
+    // I / 2^n, every basis state equally likely and no coherence left
+    pub fn new_maximally_mixed(num_qubits: usize) -> Self {
+        let size: usize = 1 << num_qubits;
+        Self {rho: DMatrix::identity(size, size) * Complex::from(T::one() / real(size as f64))}
+    }
+
+    /**
+    Build a density matrix from its rows
+    The matrix has to be 2^n x 2^n, Hermitian, have trace 1 and no negative eigenvalues (all within 1e-4)
+    */
+    pub fn new_from_matrix(rows: Vec<Vec<Complex<T>>>) -> Result<Self, QuantumError> {
+        let size: usize = rows.len();
+        if size == 0 || !size.is_power_of_two() {
+            return Err(QuantumError::DimensionMismatch {expected: size.next_power_of_two().max(2), found: size})
+        }
+        if let Some(row) = rows.iter().find(|row| row.len() != size) {
+            return Err(QuantumError::DimensionMismatch {expected: size, found: row.len()})
+        }
+        let rho: DMatrix<Complex<T>> = DMatrix::from_fn(size, size, |i, j| rows[i][j]);
+        let tolerance: T = real(1e-4);
+        if (0..size).any(|i| (0..size).any(|j| (rho[(i, j)] - rho[(j, i)].conj()).norm_sqr() > tolerance * tolerance)) {
+            return Err(QuantumError::InvalidDensityMatrix("matrix is not Hermitian".to_string()))
+        }
+        let density_matrix: Self = Self {rho};
+        if (density_matrix.trace() - T::one()).abs() > tolerance {
+            return Err(QuantumError::InvalidDensityMatrix(format!("trace is {} instead of 1", density_matrix.trace())))
+        }
+        let smallest: T = SymmetricEigen::new(density_matrix.rho.clone()).eigenvalues.iter().fold(T::one(), |smallest, &value| smallest.min(value));
+        if smallest < -tolerance {
+            return Err(QuantumError::InvalidDensityMatrix(format!("matrix has the negative eigenvalue {}", smallest)))
+        }
+        Ok(density_matrix)
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.rho.nrows().trailing_zeros() as usize
+    }
+
+    // Sum of the diagonal, stays 1 under gates, measurements and trace preserving channels
+    pub fn trace(&self) -> T {
+        (0..self.rho.nrows()).fold(T::zero(), |total, i| total + self.rho[(i, i)].re)
+    }
+
+    /**
+    tr(ρ^2), 1 for a pure state and down to 1 / 2^n for the maximally mixed state
+    Since ρ is Hermitian this is the sum of |ρ_ij|^2
+    */
+    pub fn purity(&self) -> T {
+        self.rho.iter().fold(T::zero(), |total, value| total + value.norm_sqr())
+    }
+
+    // <ψ|ρ|ψ>, the probability of finding the mixed state in the pure state of the register
+    pub fn fidelity(&self, register: &QuantumRegister<T>) -> T {
+        let psi = register.state.as_ref();
+        (psi.adjoint() * &self.rho * psi)[(0, 0)].re
+    }
+
+    // Probability of each basis state coming out of a measurement of the whole register, the diagonal of ρ
+    pub fn probabilities(&self) -> Vec<T> {
+        (0..self.rho.nrows()).map(|i| self.rho[(i, i)].re).collect()
+    }
+
+    /**
+    Probability of each qubit being measured as 1 on its own, qubit 0 first
+    */
+    pub fn qubit_probabilities(&self) -> Vec<T> {
+        let num_qubits: usize = self.num_qubits();
+        let mut ones: Vec<T> = vec![T::zero(); num_qubits];
+        for (j, probability) in self.probabilities().into_iter().enumerate() {
+            for (qubit, one) in ones.iter_mut().enumerate() {
+                if (j >> (num_qubits - 1 - qubit)) & 1 == 1 {
+                    *one += probability;
+                }
+            }
+        }
+        ones
+    }
+
+    /**
+    Measure the whole register shots times without collapsing it, like QuantumRegister::sample
+    */
+    pub fn sample(&self, shots: usize) -> BTreeMap<String, usize> {
+        self.sample_with_rng(shots, &mut rand::thread_rng())
+    }
+
+    pub fn sample_with_rng<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for index in sample_indices(&self.probabilities(), shots, rng) {
+            *counts.entry(format!("{:0width$b}", index, width = self.num_qubits())).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /**
+    Measure the given qubits together and collapse onto the outcome
+    Rows and columns of ρ that disagree with the outcome are set to 0 and the rest is divided by the outcome's probability
+    Return the classical bit of each measured qubit in the order they were given
+    */
+    pub fn measure_qubits(&mut self, qubits: &[usize]) -> Result<Vec<i32>, QuantumError> {
+        self.measure_qubits_with_rng(qubits, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        self.check_targets(qubits)?;
+        let num_qubits: usize = self.num_qubits();
+        let outcome_of = |j: usize| -> usize {
+            qubits.iter().fold(0, |outcome, qubit| (outcome << 1) | ((j >> (num_qubits - 1 - qubit)) & 1))
+        };
+        let mut probabilities: Vec<T> = vec![T::zero(); 1 << qubits.len()];
+        for (j, probability) in self.probabilities().into_iter().enumerate() {
+            probabilities[outcome_of(j)] += probability;
+        }
+        let outcome: usize = draw_outcome(&probabilities, rng);
+
+        let scale: Complex<T> = Complex::from(T::one() / probabilities[outcome]);
+        let size: usize = self.rho.nrows();
+        for j in 0..size {
+            for i in 0..size {
+                self.rho[(i, j)] = if outcome_of(i) == outcome && outcome_of(j) == outcome { self.rho[(i, j)] * scale } else { Complex::zero() };
+            }
+        }
+        Ok((0..qubits.len()).map(|i| ((outcome >> (qubits.len() - 1 - i)) & 1) as i32).collect())
+    }
+
+    pub fn measure_qubit(&mut self, qubit: usize) -> Result<i32, QuantumError> {
+        Ok(self.measure_qubits(&[qubit])?[0])
+    }
+
+    /**
+    Put a qubit into |0> without measuring it, the channel ρ -> K0 ρ K0^† + K1 ρ K1^† with K0 = |0><0| and K1 = |0><1|
+    Unlike QuantumRegister::reset nothing random happens, the rest of the register ends up in the average of both outcomes
+    */
+    pub fn reset(&mut self, qubit: usize) -> Result<(), QuantumError> {
+        let zero: Complex<T> = Complex::zero();
+        let one: Complex<T> = Complex::one();
+        let kraus: Vec<Matrix<T>> = vec![
+            Matrix::new(vec![vec![one, zero], vec![zero, zero]]),
+            Matrix::new(vec![vec![zero, one], vec![zero, zero]]),
+        ];
+        self.check_targets(&[qubit])?;
+        self.apply_kraus_matrices(&kraus, &[qubit]);
+        Ok(())
+    }
+
+    /**
+    Apply a noise channel ρ -> Σ K_i ρ K_i^† exactly, the first target being the left most factor of the Kraus operators
+    */
+    pub fn apply_channel(&mut self, channel: &KrausChannel<T>, targets: &[usize]) -> Result<(), QuantumError> {
+        channel.check_placement(targets, self.num_qubits())?;
+        self.apply_kraus_matrices(channel.matrices(), targets);
+        Ok(())
+    }
+
+    /**
+    Trace out the given qubits and return the density matrix of the remaining ones, which keep their order
+    The result describes everything that can be measured on the remaining qubits alone
+    */
+    pub fn partial_trace(&self, qubits: &[usize]) -> Result<DensityMatrix<T>, QuantumError> {
+        self.check_targets(qubits)?;
+        let num_qubits: usize = self.num_qubits();
+        let kept: Vec<usize> = (0..num_qubits).filter(|qubit| !qubits.contains(qubit)).collect();
+        // Full index of a basis state given the bits of the kept qubits and of the traced out qubits
+        let embed = |kept_bits: usize, traced_bits: usize| -> usize {
+            let place = |index: usize, bits: usize, group: &[usize]| group.iter().enumerate()
+                .fold(index, |index, (i, qubit)| index | (((bits >> (group.len() - 1 - i)) & 1) << (num_qubits - 1 - qubit)));
+            place(place(0, kept_bits, &kept), traced_bits, qubits)
+        };
+        let size: usize = 1 << kept.len();
+        let rho: DMatrix<Complex<T>> = DMatrix::from_fn(size, size, |i, j| {
+            (0..1 << qubits.len()).fold(Complex::zero(), |total, t| total + self.rho[(embed(i, t), embed(j, t))])
+        });
+        Ok(Self {rho})
+    }
+
+    /**
+    ρ -> U ρ U^† for a small matrix U on the target qubits
+    U is applied to every column of ρ, then to every column of the adjoint of the result, which gives U (U ρ)^† = (U ρ U^†)^†
+    */
+    pub(crate) fn apply_local_matrix(&mut self, matrix: &Matrix<T>, targets: &[usize], controls: &[Control]) {
+        let num_qubits: usize = self.num_qubits();
+        let size: usize = self.rho.nrows();
+        for _ in 0..2 {
+            for column in self.rho.as_mut_slice().chunks_mut(size) {
+                apply_local_matrix(column, num_qubits, matrix, targets, controls);
+            }
+            self.rho = self.rho.adjoint();
+        }
+    }
+
+    // Σ K_i ρ K_i^† without any checks
+    pub(crate) fn apply_kraus_matrices(&mut self, operators: &[Matrix<T>], targets: &[usize]) {
+        let size: usize = self.rho.nrows();
+        let mut rho: DMatrix<Complex<T>> = DMatrix::zeros(size, size);
+        for operator in operators.iter() {
+            let mut term: Self = Self {rho: self.rho.clone()};
+            term.apply_local_matrix(operator, targets, &[]);
+            rho += term.rho;
+        }
+        self.rho = rho;
+    }
+
+    // Every qubit has to be in range and used only once
+    fn check_targets(&self, qubits: &[usize]) -> Result<(), QuantumError> {
+        let num_qubits: usize = self.num_qubits();
+        for (i, &qubit) in qubits.iter().enumerate() {
+            if qubit >= num_qubits {
+                return Err(QuantumError::QubitOutOfRange {qubit: qubit as i32, num_qubits})
+            }
+            if qubits[..i].contains(&qubit) {
+                return Err(QuantumError::DuplicateQubit(qubit as i32))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Precision> Backend<T> for DensityMatrix<T> {
+    fn num_qubits(&self) -> usize {
+        DensityMatrix::num_qubits(self)
+    }
+
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+        let gate = instruction.gate()?;
+        gate.check_placement(&instruction.controls, &instruction.targets, self.num_qubits())?;
+        self.apply_local_matrix(gate.matrix(), &instruction.targets, &instruction.controls);
+        Ok(())
+    }
+
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        DensityMatrix::measure_qubits_with_rng(self, qubits, rng)
+    }
+
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], _rng: &mut R) -> Result<(), QuantumError> {
+        self.apply_channel(channel, targets)
+    }
+
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, _rng: &mut R) -> Result<(), QuantumError> {
+        self.reset(qubit)
+    }
+}
+
+impl<T: Precision> Clone for DensityMatrix<T> {
+    fn clone(&self) -> Self {
+        Self {rho: self.rho.clone()}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Gates::Gate;
+    use crate::QuantumCircuit::QuantumCircuit;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn assert_close(expected: &DMatrix<Complex<f64>>, found: &DMatrix<Complex<f64>>) {
+        assert_eq!(expected.shape(), found.shape());
+        for (a, b) in expected.iter().zip(found.iter()) {
+            assert!((a - b).norm_sqr() < 1e-20, "expected\n{}found\n{}", expected, found);
+        }
+    }
+
+    #[test]
+    fn test_circuit_matches_register() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        circuit.h(0).rx(1, 0.4).cx(0, 2).mcx(&[Control::new_negated(1)], 0).u(2, 0.3, 0.2, 0.1).iswap(1, 2).qft(&[0, 1, 2]);
+
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 8);
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 8);
+        circuit.run(&mut register).unwrap();
+        circuit.run(&mut rho).unwrap();
+        assert_close(&DensityMatrix::new_from_register(&register).rho, &rho.rho);
+        assert!((rho.purity() - 1.).abs() < 1e-9);
+        assert!((rho.fidelity(&register) - 1.).abs() < 1e-9);
+
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 3, found: 2}), circuit.run(&mut DensityMatrix::<f64>::new_from_int(0, 4)));
+    }
+
+    #[test]
+    fn test_partial_trace() {
+        // Each half of a bell pair on its own is maximally mixed
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        circuit.h(0).cx(0, 2).x(1);
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 8);
+        circuit.run(&mut rho).unwrap();
+
+        let half: DensityMatrix<f64> = rho.partial_trace(&[1, 2]).unwrap();
+        assert_close(&DensityMatrix::new_maximally_mixed(1).rho, &half.rho);
+        assert!((half.purity() - 0.5).abs() < 1e-12);
+
+        // Qubit 1 is not entangled so tracing out the pair leaves it in |1>
+        assert_close(&DensityMatrix::new_from_int(1, 2).rho, &rho.partial_trace(&[2, 0]).unwrap().rho);
+        assert_eq!(4, rho.partial_trace(&[1]).unwrap().rho.nrows());
+        assert_eq!(Some(QuantumError::DuplicateQubit(1)), rho.partial_trace(&[1, 1]).err());
+    }
+
+    #[test]
+    fn test_mixed_state() {
+        let zero: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 2);
+        let one: QuantumRegister<f64> = QuantumRegister::new_from_int(1, 2);
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_mixed(&[(3., zero.clone()), (1., one)]).unwrap();
+        assert!((rho.purity() - 0.625).abs() < 1e-12);
+
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(1);
+        circuit.x(0);
+        circuit.run(&mut rho).unwrap();
+        assert!((rho.probabilities()[0] - 0.25).abs() < 1e-12);
+        assert!((rho.qubit_probabilities()[0] - 0.75).abs() < 1e-12);
+
+        let counts: BTreeMap<String, usize> = rho.sample_with_rng(4000, &mut StdRng::seed_from_u64(1));
+        assert!((counts["1"] as f64 / 4000. - 0.75).abs() < 0.03);
+
+        assert!(matches!(DensityMatrix::<f64>::new_mixed(&[]), Err(QuantumError::InvalidDensityMatrix(_))));
+        assert!(matches!(DensityMatrix::new_mixed(&[(-1., zero)]), Err(QuantumError::InvalidDensityMatrix(_))));
+    }
+
+    #[test]
+    fn test_new_from_matrix() {
+        let c = |re: f64, im: f64| Complex::new(re, im);
+        assert!(DensityMatrix::new_from_matrix(vec![vec![c(0.5, 0.), c(0., -0.5)], vec![c(0., 0.5), c(0.5, 0.)]]).is_ok());
+        // Not Hermitian, wrong trace and a negative eigenvalue
+        assert!(matches!(DensityMatrix::new_from_matrix(vec![vec![c(0.5, 0.), c(0.5, 0.)], vec![c(0., 0.), c(0.5, 0.)]]), Err(QuantumError::InvalidDensityMatrix(_))));
+        assert!(matches!(DensityMatrix::new_from_matrix(vec![vec![c(1., 0.), c(0., 0.)], vec![c(0., 0.), c(1., 0.)]]), Err(QuantumError::InvalidDensityMatrix(_))));
+        assert!(matches!(DensityMatrix::new_from_matrix(vec![vec![c(0.5, 0.), c(1., 0.)], vec![c(1., 0.), c(0.5, 0.)]]), Err(QuantumError::InvalidDensityMatrix(_))));
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 4, found: 3}), DensityMatrix::<f64>::new_from_matrix(vec![vec![c(1., 0.); 3]; 3]).err());
+    }
+
+    #[test]
+    fn test_non_unitary_operations() {
+        // Reset |+> on qubit 0 of a bell pair: no randomness, qubit 1 is left as an even mixture
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).cx(0, 1).reset(0);
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 4);
+        circuit.run(&mut rho).unwrap();
+        let expected: DensityMatrix<f64> = DensityMatrix::new_mixed(&[(1., QuantumRegister::new_from_int(0, 4)), (1., QuantumRegister::new_from_int(1, 4))]).unwrap();
+        assert_close(&expected.rho, &rho.rho);
+
+        // Kraus operators |0><0| and |0><1| send everything to |0>
+        let c = |re: f64| Complex::new(re, 0.);
+        let damping: KrausChannel<f64> = KrausChannel::new(vec![vec![vec![c(1.), c(0.)], vec![c(0.), c(0.)]], vec![vec![c(0.), c(1.)], vec![c(0.), c(0.)]]]).unwrap();
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_maximally_mixed(2);
+        rho.apply_channel(&damping, &[1]).unwrap();
+        assert!((rho.qubit_probabilities()[1]).abs() < 1e-12);
+        assert!((rho.trace() - 1.).abs() < 1e-12);
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 4, found: 2}), rho.apply_channel(&damping, &[0, 1]));
+        assert_eq!(Err(QuantumError::DuplicateQubit(1)), rho.apply_channel(&KrausChannel::new_depolarizing(0.1, 2).unwrap(), &[1, 1]));
+    }
+
+    #[test]
+    fn test_measurement_and_conditions() {
+        // Teleportation run on a density matrix reproduces RY(θ)|0> on qubit 2 whatever the measurements give
+        let theta: f64 = 0.8;
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        circuit.add_classical_register("m0", 1).unwrap();
+        circuit.add_classical_register("m1", 1).unwrap();
+        circuit.ry(0, theta).h(1).cx(1, 2).cx(0, 1).h(0).measure(0, 0).measure(1, 1);
+        circuit.x(2).c_if("m1", 1).z(2).c_if("m0", 1);
+
+        let mut target: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 2);
+        Gate::new_ry(theta).apply_to_qubit(0, &mut target).unwrap();
+        let mut rng: StdRng = StdRng::seed_from_u64(4);
+        for _ in 0..10 {
+            let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 8);
+            circuit.run_with_rng(&mut rho, &mut rng).unwrap();
+            assert!((rho.partial_trace(&[0, 1]).unwrap().fidelity(&target) - 1.).abs() < 1e-9);
+            assert!((rho.trace() - 1.).abs() < 1e-9);
+        }
+    }
+}
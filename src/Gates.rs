@@ -1,24 +1,27 @@
-use nalgebra::{Complex, ComplexField, RealField};
+use nalgebra::Complex;
 use num_traits::One;
+use crate::Instruction::Control;
 use crate::Matrix::Matrix;
+use crate::Precision::{Precision, real};
+use crate::QuantumError::QuantumError;
 use crate::QuantumRegister::QuantumRegister;
 use crate::Qubit::Qubit;
 use num_traits::Zero;
 
-pub struct Gate {
-    matrix: Matrix
+pub struct Gate<T: Precision = f32> {
+    matrix: Matrix<T>
 }
 
-impl Gate {
+impl<T: Precision> Gate<T> {
     // Pauli I gate (or classical identity)
     pub fn new_identity() -> Self {
-        let matrix: Matrix = Matrix::new_identity(2);
+        let matrix: Matrix<T> = Matrix::new_identity(2);
         Self {matrix}
     }
 
     // Pauli X gate (or classical NOT gate)
     pub fn new_not() -> Self {
-        let mut matrix: Matrix = Matrix::new_zero(2);
+        let mut matrix: Matrix<T> = Matrix::new_zero(2);
         matrix.rows[0][1] = Complex::one();
         matrix.rows[1][0] = Complex::one();
         Self {matrix}
@@ -26,60 +29,217 @@ impl Gate {
 
     // Pauli Y gate
     pub fn new_y() -> Self {
-        let mut matrix: Matrix = Matrix::new_zero(2);
-        matrix.rows[0][1] = Complex::i() * (-1.);
+        let mut matrix: Matrix<T> = Matrix::new_zero(2);
+        matrix.rows[0][1] = -Complex::i();
         matrix.rows[1][0] = Complex::i();
         Self {matrix}
     }
     // Pauli Z gate
     pub fn new_z() -> Self {
-        let mut matrix: Matrix = Matrix::new_zero(2);
+        let mut matrix: Matrix<T> = Matrix::new_zero(2);
         matrix.rows[0][0] = Complex::one();
-        matrix.rows[1][1] = Complex::one() * (-1.);
+        matrix.rows[1][1] = -Complex::one();
         Self {matrix}
     }
 
     // Hadamard gate
     pub fn new_h() -> Self {
-        let matrix: Matrix = Matrix::new_h();
+        let matrix: Matrix<T> = Matrix::new_h();
         Self {matrix}
     }
     // pi/8 gate. Called pi/8 because of a different written form
     pub fn new_t() -> Self{
-        Gate::new_phase_rotation(0.125)
+        Gate::new_phase_rotation(real(0.125))
     }
 
-    // Phase rotation gate
-    pub fn new_phase_rotation(phase: f32) -> Self {
-        let mut matrix: Matrix = Matrix::new_zero(2);
+    // Phase rotation gate. The phase is a fraction of a full turn, so the bottom right entry is e^(2πi * phase)
+    pub fn new_phase_rotation(phase: T) -> Self {
+        let mut matrix: Matrix<T> = Matrix::new_zero(2);
+        let angle: T = T::two_pi() * phase;
         matrix.rows[0][0] = Complex::one();
-        matrix.rows[1][1] = (Complex::one() * f32::e()).powc(Complex::i()*2.*f32::pi()*phase);
+        matrix.rows[1][1] = Complex::new(angle.cos(), angle.sin());
         Self {matrix}
     }
 
+    // Inverse of the pi/8 gate
+    pub fn new_tdg() -> Self {
+        Gate::new_phase_rotation(real(-0.125))
+    }
+
+    // S gate (square root of Z), a quarter turn phase rotation
+    pub fn new_s() -> Self {
+        Gate::new_phase_rotation(real(0.25))
+    }
+
+    // Inverse of the S gate
+    pub fn new_sdg() -> Self {
+        Gate::new_phase_rotation(real(-0.25))
+    }
+
+    // Square root of the not gate, applying it twice gives X
+    pub fn new_sx() -> Self {
+        let half: T = real(0.5);
+        let plus: Complex<T> = Complex::new(half, half);
+        let minus: Complex<T> = Complex::new(half, -half);
+        let matrix: Matrix<T> = Matrix::new(vec![vec![plus, minus], vec![minus, plus]]);
+        Self {matrix}
+    }
+
+    // Inverse of the square root of not gate
+    pub fn new_sxdg() -> Self {
+        Gate::new_sx().inverse()
+    }
+
+    /**
+    Rotation by theta radians around the X axis of the bloch sphere
+    RX(θ) = [cos(θ/2), -i sin(θ/2); -i sin(θ/2), cos(θ/2)]
+    */
+    pub fn new_rx(theta: T) -> Self {
+        let half: T = theta / real(2.);
+        let cos: Complex<T> = Complex::from(half.cos());
+        let sin: Complex<T> = Complex::new(T::zero(), -half.sin());
+        let matrix: Matrix<T> = Matrix::new(vec![vec![cos, sin], vec![sin, cos]]);
+        Self {matrix}
+    }
+
+    /**
+    Rotation by theta radians around the Y axis of the bloch sphere
+    RY(θ) = [cos(θ/2), -sin(θ/2); sin(θ/2), cos(θ/2)]
+    */
+    pub fn new_ry(theta: T) -> Self {
+        let half: T = theta / real(2.);
+        let cos: Complex<T> = Complex::from(half.cos());
+        let sin: Complex<T> = Complex::from(half.sin());
+        let matrix: Matrix<T> = Matrix::new(vec![vec![cos, -sin], vec![sin, cos]]);
+        Self {matrix}
+    }
+
+    /**
+    Rotation by theta radians around the Z axis of the bloch sphere
+    RZ(θ) = diag(e^(-iθ/2), e^(iθ/2)), equal to a phase rotation up to a global phase
+    */
+    pub fn new_rz(theta: T) -> Self {
+        let half: T = theta / real(2.);
+        let mut matrix: Matrix<T> = Matrix::new_zero(2);
+        matrix.rows[0][0] = Gate::phase_factor(-half);
+        matrix.rows[1][1] = Gate::phase_factor(half);
+        Self {matrix}
+    }
+
+    /**
+    General single qubit gate, every 2x2 unitary is U(θ, φ, λ) up to a global phase
+    U(θ, φ, λ) = [cos(θ/2), -e^(iλ) sin(θ/2); e^(iφ) sin(θ/2), e^(i(φ+λ)) cos(θ/2)]
+    (Ex U(π/2, 0, π) = H, U(θ, -π/2, π/2) = RX(θ), U(θ, 0, 0) = RY(θ))
+    */
+    pub fn new_u(theta: T, phi: T, lambda: T) -> Self {
+        let half: T = theta / real(2.);
+        let cos: Complex<T> = Complex::from(half.cos());
+        let sin: Complex<T> = Complex::from(half.sin());
+        let matrix: Matrix<T> = Matrix::new(vec![
+            vec![cos, -Gate::phase_factor(lambda) * sin],
+            vec![Gate::phase_factor(phi) * sin, Gate::phase_factor(phi + lambda) * cos]
+        ]);
+        Self {matrix}
+    }
+
+    /**
+    Inverse of the gate, which is the conjugate transpose of its matrix since gates are unitary
+    */
+    pub fn inverse(&self) -> Self {
+        let mut matrix: Matrix<T> = self.matrix.clone();
+        matrix.conjugate_transpose();
+        Self {matrix}
+    }
+
+    // e^(i * angle)
+    fn phase_factor(angle: T) -> Complex<T> {
+        Complex::new(angle.cos(), angle.sin())
+    }
+
     // Controlled not gate. Reversible version of the not gate.
     // All n-qubit gates can be created from the hadamard gate, pi/8 gate and cnot gate (using infinitely many combinations)
     pub fn new_cnot() -> Self{
-        let matrix: Matrix = Matrix::new(vec![vec![Complex::one(), Complex::zero(), Complex::zero(), Complex::zero()
+        let matrix: Matrix<T> = Matrix::new(vec![vec![Complex::one(), Complex::zero(), Complex::zero(), Complex::zero()
         ], vec![Complex::zero(), Complex::one(), Complex::zero(), Complex::zero()], vec![Complex::zero(), Complex::zero(), Complex::zero(), Complex::one()],
         vec![Complex::zero(), Complex::zero(), Complex::one(), Complex::zero()]]);
 
         Self {matrix}
     }
 
+    // Swap the states of two qubits
+    pub fn new_swap() -> Self {
+        Gate::new_permutation(&[0, 2, 1, 3])
+    }
+
+    // Controlled Z gate, flips the sign of |11>. Symmetric in its two qubits
+    pub fn new_cz() -> Self {
+        let mut matrix: Matrix<T> = Matrix::new_identity(4);
+        matrix.rows[3][3] = -Complex::one();
+        Self {matrix}
+    }
+
+    // Controlled phase rotation, the phase is a fraction of a full turn like in new_phase_rotation
+    pub fn new_controlled_phase(phase: T) -> Self {
+        let mut matrix: Matrix<T> = Matrix::new_identity(4);
+        matrix.rows[3][3] = Gate::phase_factor(T::two_pi() * phase);
+        Self {matrix}
+    }
+
+    // Swap two qubits and multiply |01> and |10> by i
+    pub fn new_iswap() -> Self {
+        let mut matrix: Matrix<T> = Gate::new_swap().matrix;
+        matrix.rows[1][2] = Complex::i();
+        matrix.rows[2][1] = Complex::i();
+        Self {matrix}
+    }
+
+    // Square root of the swap gate, applying it twice gives swap
+    pub fn new_sqrt_swap() -> Self {
+        let half: T = real(0.5);
+        let mut matrix: Matrix<T> = Matrix::new_identity(4);
+        matrix.rows[1][1] = Complex::new(half, half);
+        matrix.rows[1][2] = Complex::new(half, -half);
+        matrix.rows[2][1] = Complex::new(half, -half);
+        matrix.rows[2][2] = Complex::new(half, half);
+        Self {matrix}
+    }
+
+    // Toffoli gate (controlled controlled not), the first two qubits are the controls
+    pub fn new_toffoli() -> Self {
+        Gate::new_permutation(&[0, 1, 2, 3, 4, 5, 7, 6])
+    }
+
+    // Fredkin gate (controlled swap), the first qubit is the control
+    pub fn new_fredkin() -> Self {
+        Gate::new_permutation(&[0, 1, 2, 3, 4, 6, 5, 7])
+    }
+
+    /**
+    Gate that sends basis state |j> to |permutation[j]>
+    Every classical reversible gate (not, cnot, swap, toffoli, ...) is a permutation of the basis states
+    */
+    fn new_permutation(permutation: &[usize]) -> Self {
+        let mut matrix: Matrix<T> = Matrix::new_zero(permutation.len());
+        for (column, &row) in permutation.iter().enumerate() {
+            matrix.rows[row][column] = Complex::one();
+        }
+        Self {matrix}
+    }
+
     pub fn new_qft(num_qubits: i32) -> Self {
         let k: i32 = 1 << num_qubits;
-        let mut matrix: Matrix = Matrix::new_zero(k as usize);
+        let mut matrix: Matrix<T> = Matrix::new_zero(k as usize);
+        let scale: T = T::one() / real::<T>(k as f64).sqrt();
         for i in 0..k{
             let mut cur_exp: i32 = i;
             for j in 0..k{
                 if i == 0 || j == 0{
-                    matrix.rows[i as usize][j as usize] = Complex::one() * 1./(k as f32).sqrt();
+                    matrix.rows[i as usize][j as usize] = Complex::from(scale);
                 }
                 else {
-                    let phase: f32 = 2. * f32::pi() / (k as f32);
-                    let val: Complex<f32> = Complex::from(phase.cos()) + Complex::i() * 1. * Complex::from(phase.sin());
-                    matrix.rows[i as usize][j as usize] = val.powi(cur_exp) * 1./(k as f32).sqrt();
+                    let phase: T = T::two_pi() / real(k as f64);
+                    let val: Complex<T> = Complex::new(phase.cos(), phase.sin());
+                    matrix.rows[i as usize][j as usize] = val.powi(cur_exp) * scale;
                     cur_exp += i;
                     cur_exp %= k;
                 }
@@ -89,57 +249,45 @@ impl Gate {
     }
 
     pub fn new_inverse_qft(num_qubits: i32) -> Self {
-        let mut matrix: Matrix = Gate::new_qft(num_qubits).matrix;
+        let mut matrix: Matrix<T> = Gate::new_qft(num_qubits).matrix;
         matrix.conjugate_transpose();
         Self {matrix}
     }
 
-    pub fn new_multi_controlled(control_qubit: i32, target_qubit: i32, num_qubits: i32, gate: Gate)  -> Self{
-        let identity: Matrix = Matrix::new_identity(2);
-        // Outer product of a classical 0 bit in vector representation
-        let zero_state_outer_product = Matrix::new(vec![vec![Complex::one(), Complex::zero()], vec![Complex::zero(), Complex::zero()]]);
-        // Outer product of a classical 1 bit in vector representation
-        let one_state_outer_product = Matrix::new(vec![vec![Complex::zero(), Complex::zero()], vec![Complex::zero(), Complex::one()]]);
-        // Matrix of the not gate
-        let target_matrix: Matrix = gate.matrix.clone();
-
-        let mut zero_condition_matrix: Matrix = Matrix::new_zero(2);
-        let mut one_condition_matrix: Matrix = Matrix::new_zero(2);
-
-        if control_qubit == 0 {
-            zero_condition_matrix.rows[0][0] = Complex::one();
-            one_condition_matrix.rows[1][1] = Complex::one();
-        }
-        else if target_qubit == 0 {
-            zero_condition_matrix = gate.matrix.clone();
-            one_condition_matrix = gate.matrix.clone();
+    /**
+    Controlled version of a single qubit gate acting on the whole register of num_qubits qubits
+    Equal to |0><0|⊗I + |1><1|⊗U placed on the control and target qubits, with the identity on every other qubit
+    */
+    pub fn new_multi_controlled(control_qubit: i32, target_qubit: i32, num_qubits: i32, gate: Gate<T>) -> Result<Self, QuantumError> {
+        Self::check_qubit(control_qubit, num_qubits)?;
+        Self::check_qubit(target_qubit, num_qubits)?;
+        if control_qubit == target_qubit {
+            return Err(QuantumError::ControlEqualsTarget(control_qubit))
         }
-        else {
-            zero_condition_matrix.rows[0][0] = Complex::one();
-            zero_condition_matrix.rows[1][1] = Complex::one();
-            one_condition_matrix.rows[0][0] = Complex::one();
-            one_condition_matrix.rows[1][1] = Complex::one();
+        if gate.matrix.rows.len() != 2 {
+            return Err(QuantumError::DimensionMismatch {expected: 2, found: gate.matrix.rows.len()})
         }
+        Gate::new_controlled(&gate, &[true]).expand(&[control_qubit, target_qubit], num_qubits)
+    }
 
-        // Apply chain of tensors to each matrix
-        for i in 1..num_qubits {
-            if i == control_qubit{
-                zero_condition_matrix = zero_condition_matrix.tensor_product(&zero_state_outer_product);
-                one_condition_matrix = one_condition_matrix.tensor_product(&one_state_outer_product);
-            }
-            else if i == target_qubit {
-                zero_condition_matrix = zero_condition_matrix.tensor_product(&identity);
-                one_condition_matrix = one_condition_matrix.tensor_product(&target_matrix);
-            }
-            else {
-                zero_condition_matrix = zero_condition_matrix.tensor_product(&identity);
-                one_condition_matrix = one_condition_matrix.tensor_product(&identity);
+    /**
+    Controlled version of any k qubit gate, with one control qubit per entry of control_values
+    The result acts on the controls followed by the targets, the first control being the left most factor
+    A control with value true fires when its qubit is |1>, a control with value false fires when its qubit is |0>
+    The gate is applied on the block of basis states where every control matches its value, everything else is left alone
+    (Ex new_controlled(X, [true, true]) is the toffoli gate, new_controlled(X, [false]) flips the target when the control is |0>)
+    Place it on a register with apply_to_qubits or expand
+    */
+    pub fn new_controlled(gate: &Gate<T>, control_values: &[bool]) -> Self {
+        let block: usize = gate.matrix.rows.len();
+        let mut matrix: Matrix<T> = Matrix::new_identity(block << control_values.len());
+        let offset: usize = block * control_values.iter().fold(0, |index, &value| (index << 1) | value as usize);
+        for i in 0..block {
+            for j in 0..block {
+                matrix.rows[offset + i][offset + j] = gate.matrix.rows[i][j];
             }
         }
-
-        Self {
-            matrix: Matrix::matrix_addition(zero_condition_matrix, one_condition_matrix).unwrap()
-        }
+        Self {matrix}
     }
 
     /**
@@ -149,9 +297,8 @@ impl Gate {
     (Ex let control_qubit = 0, target_qubit = 2, num_qubits = 3: |0><0|⊗I⊗I + |1><1|⊗I⊗X. I is the identity matrix, X is the not matrix, |0> = (1,0), |1> = (0,1))
     This must be done twice for the zero state and the one state of the control bit
     Inputs are zero indexed
-    FIX
     */
-    pub fn new_multi_cnot(control_qubit: i32, target_qubit: i32, num_qubits: i32) -> Self{
+    pub fn new_multi_cnot(control_qubit: i32, target_qubit: i32, num_qubits: i32) -> Result<Self, QuantumError> {
         Gate::new_multi_controlled(control_qubit, target_qubit, num_qubits, Gate::new_not())
     }
 
@@ -161,12 +308,13 @@ impl Gate {
     Tensor the identity matrix with itself num_qubits - 1 times, and in that chain tensor the input gate matrix at the desired qubits position
     (Ex let target_qubit = 2, let num_qubits = 4, let gate = not: ret = I⊗I⊗X⊗I where I is the identity matrix and X is the not matrix)
     */
-    pub fn multi_single_qubit_gate(target_qubit: i32, num_qubits: i32, gate: Gate) -> Result<Self, i32> {
-        if gate.matrix.rows.len() > 2{
-            return Err(-1)
+    pub fn multi_single_qubit_gate(target_qubit: i32, num_qubits: i32, gate: Gate<T>) -> Result<Self, QuantumError> {
+        if gate.matrix.rows.len() != 2{
+            return Err(QuantumError::DimensionMismatch {expected: 2, found: gate.matrix.rows.len()})
         }
-        let mut new_gate_matrix: Matrix = Matrix::new_identity(2);
-        let identity: Matrix = Matrix::new_identity(2);
+        Self::check_qubit(target_qubit, num_qubits)?;
+        let mut new_gate_matrix: Matrix<T> = Matrix::new_identity(2);
+        let identity: Matrix<T> = Matrix::new_identity(2);
         if target_qubit == 0{
             new_gate_matrix = gate.matrix.clone();
         }
@@ -183,9 +331,42 @@ impl Gate {
         Ok(Self {matrix: new_gate_matrix})
     }
 
+    /**
+    Expand a k qubit gate into the gate acting on the whole register of num_qubits qubits
+    The first target qubit is the left most factor of the gate, so targets do not need to be adjacent or in order
+    (Ex Gate::new_toffoli().expand(&[3, 0, 1], 4) flips qubit 1 when qubits 3 and 0 are both 1)
+    Column j of the result is the gate applied to basis state |j>, which only changes the target bits of j
+    */
+    pub fn expand(&self, target_qubits: &[i32], num_qubits: i32) -> Result<Self, QuantumError> {
+        if self.matrix.rows.len() != 1 << target_qubits.len() {
+            return Err(QuantumError::DimensionMismatch {expected: 1 << target_qubits.len(), found: self.matrix.rows.len()})
+        }
+        Self::check_distinct_qubits(&[], target_qubits, num_qubits)?;
+
+        let size: usize = 1 << num_qubits;
+        let positions: Vec<usize> = target_qubits.iter().map(|&qubit| (num_qubits - 1 - qubit) as usize).collect();
+        let local_index = |j: usize| -> usize {
+            positions.iter().fold(0, |local, position| (local << 1) | ((j >> position) & 1))
+        };
+        let global_offset = |local: usize| -> usize {
+            positions.iter().enumerate().fold(0, |offset, (i, position)| offset | (((local >> (positions.len() - 1 - i)) & 1) << position))
+        };
+        let target_mask: usize = global_offset(self.matrix.rows.len() - 1);
+
+        let mut matrix: Matrix<T> = Matrix::new_zero(size);
+        for column in 0..size {
+            let base: usize = column & !target_mask;
+            let local_column: usize = local_index(column);
+            for local_row in 0..self.matrix.rows.len() {
+                matrix.rows[base | global_offset(local_row)][column] = self.matrix.rows[local_row][local_column];
+            }
+        }
+        Ok(Self {matrix})
+    }
+
     pub fn new_multi_h(num_qubits: i32) -> Self {
-        let mut matrix: Matrix = Matrix::new_h();
-        let h_clone: Matrix = matrix.clone();
+        let mut matrix: Matrix<T> = Matrix::new_h();
+        let h_clone: Matrix<T> = matrix.clone();
         for _ in 0..num_qubits-1{
             matrix = matrix.tensor_product(&h_clone);
         }
@@ -199,17 +380,17 @@ impl Gate {
     The kronecker product of n many qubits is a vector with 2^n many elements
     Apply the gate by multiplying the register state vector by the gate matrix
     */
-    pub fn apply(&self, register: &mut QuantumRegister) -> Result<i32, i32> {
+    pub fn apply(&self, register: &mut QuantumRegister<T>) -> Result<(), QuantumError> {
         let len: usize = self.matrix.rows.len();
-        let mut new_state: Vec<Complex<f32>> = vec![Complex::zero(); len];
+        let mut new_state: Vec<Complex<T>> = vec![Complex::zero(); len];
 
         // Return an error if there is a mismatch in size
         if len != register.state.len() {
-            return Err(-1)
+            return Err(QuantumError::DimensionMismatch {expected: register.state.len(), found: len})
         }
 
         for i in 0..self.matrix.rows.len(){
-            let mut new_val: Complex<f32> = Complex::zero();
+            let mut new_val: Complex<T> = Complex::zero();
             for j in 0..self.matrix.rows[i].len(){
                 new_val += self.matrix.rows[i][j] * register.state[j];
             }
@@ -217,7 +398,134 @@ impl Gate {
         }
 
         register.change_state(new_state);
-        Ok(1)
+        Ok(())
+    }
+
+    /**
+    Apply a single qubit gate to one qubit of a register in place
+    Only the 2x2 gate matrix is used, so no 2^n x 2^n matrix is ever built
+    */
+    pub fn apply_to_qubit(&self, target_qubit: i32, register: &mut QuantumRegister<T>) -> Result<(), QuantumError> {
+        self.apply_controlled(&[], &[target_qubit], register)
+    }
+
+    /**
+    Apply a k qubit gate (2^k x 2^k matrix) to the given target qubits of a register in place
+    The first target qubit plays the role of the left most factor of the gate
+    (Ex the 4x4 cnot gate applied to targets [3, 1] uses qubit 3 as the control and qubit 1 as the target)
+    */
+    pub fn apply_to_qubits(&self, target_qubits: &[i32], register: &mut QuantumRegister<T>) -> Result<(), QuantumError> {
+        self.apply_controlled(&[], target_qubits, register)
+    }
+
+    /**
+    Apply the gate to the target qubits only on the part of the state where every control qubit is 1
+    Work is linear in the size of the register instead of quadratic like Gate::apply
+    */
+    pub fn apply_controlled(&self, control_qubits: &[i32], target_qubits: &[i32], register: &mut QuantumRegister<T>) -> Result<(), QuantumError> {
+        let num_qubits: i32 = register.num_qubits() as i32;
+        Self::check_distinct_qubits(control_qubits, target_qubits, num_qubits)?;
+        let controls: Vec<Control> = control_qubits.iter().map(|&qubit| Control::new(qubit as usize)).collect();
+        let targets: Vec<usize> = target_qubits.iter().map(|&qubit| qubit as usize).collect();
+        self.apply_with_controls(&controls, &targets, register)
+    }
+
+    /**
+    Apply the gate to the target qubits only on the part of the state where every control matches its value
+    Controls can fire on |1> or on |0>, see Control
+    */
+    pub fn apply_with_controls(&self, controls: &[Control], target_qubits: &[usize], register: &mut QuantumRegister<T>) -> Result<(), QuantumError> {
+        self.check_placement(controls, target_qubits, register.num_qubits())?;
+        register.apply_local_matrix(&self.matrix, target_qubits, controls);
+        Ok(())
+    }
+
+    /**
+    Make sure the gate can act on the given targets with the given controls in a register of num_qubits qubits
+    There has to be one target per qubit of the gate, and every qubit has to be in range and used only once
+    */
+    pub(crate) fn check_placement(&self, controls: &[Control], target_qubits: &[usize], num_qubits: usize) -> Result<(), QuantumError> {
+        if self.matrix.rows.len() != 1 << target_qubits.len() {
+            return Err(QuantumError::DimensionMismatch {expected: 1 << target_qubits.len(), found: self.matrix.rows.len()})
+        }
+        let control_qubits: Vec<i32> = controls.iter().map(|control| control.qubit as i32).collect();
+        let targets: Vec<i32> = target_qubits.iter().map(|&qubit| qubit as i32).collect();
+        Self::check_distinct_qubits(&control_qubits, &targets, num_qubits as i32)
+    }
+
+    pub(crate) fn matrix(&self) -> &Matrix<T> {
+        &self.matrix
+    }
+
+    // Wrap a matrix built inside the crate whose unitarity has already been checked
+    pub(crate) fn new_unchecked(matrix: Matrix<T>) -> Self {
+        Self {matrix}
+    }
+
+    // Rows of the gate's 2^k x 2^k matrix
+    pub fn rows(&self) -> &[Vec<Complex<T>>] {
+        &self.matrix.rows
+    }
+
+    // Number of qubits the gate acts on, the matrix is 2^k x 2^k
+    pub fn num_qubits(&self) -> usize {
+        self.matrix.rows.len().trailing_zeros() as usize
+    }
+
+    /**
+    Build a gate from a user supplied square matrix
+    The matrix has to be 2^k x 2^k for some k and unitary, otherwise an error is returned
+    */
+    pub fn new_from_matrix(rows: Vec<Vec<Complex<T>>>) -> Result<Self, QuantumError> {
+        let matrix: Matrix<T> = Matrix::new(rows);
+        let len: usize = matrix.rows.len();
+        if len == 0 || !len.is_power_of_two() {
+            return Err(QuantumError::DimensionMismatch {expected: len.next_power_of_two().max(2), found: len})
+        }
+        for row in matrix.rows.iter() {
+            if row.len() != len {
+                return Err(QuantumError::DimensionMismatch {expected: len, found: row.len()})
+            }
+        }
+        if !matrix.is_unitary(real(1e-4)) {
+            return Err(QuantumError::NonUnitaryMatrix)
+        }
+        Ok(Self {matrix})
+    }
+
+    // Make sure a qubit index fits in a register of num_qubits qubits
+    fn check_qubit(qubit: i32, num_qubits: i32) -> Result<(), QuantumError> {
+        if qubit < 0 || qubit >= num_qubits {
+            return Err(QuantumError::QubitOutOfRange {qubit, num_qubits: num_qubits.max(0) as usize})
+        }
+        Ok(())
+    }
+
+    // Make sure every control and target qubit is in range and no qubit is used twice
+    pub(crate) fn check_distinct_qubits(control_qubits: &[i32], target_qubits: &[i32], num_qubits: i32) -> Result<(), QuantumError> {
+        for &qubit in control_qubits.iter().chain(target_qubits.iter()) {
+            Self::check_qubit(qubit, num_qubits)?;
+        }
+        for (i, qubit) in target_qubits.iter().enumerate() {
+            if control_qubits.contains(qubit) {
+                return Err(QuantumError::ControlEqualsTarget(*qubit))
+            }
+            if target_qubits[..i].contains(qubit) {
+                return Err(QuantumError::DuplicateQubit(*qubit))
+            }
+        }
+        for (i, qubit) in control_qubits.iter().enumerate() {
+            if control_qubits[..i].contains(qubit) {
+                return Err(QuantumError::DuplicateQubit(*qubit))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Precision> Clone for Gate<T> {
+    fn clone(&self) -> Self {
+        Self {matrix: self.matrix.clone()}
     }
 }
 
@@ -277,7 +585,7 @@ mod tests {
 
     #[test]
     fn test_multi_cnot(){
-        let gate: Gate = Gate::new_multi_cnot(0,2,3);
+        let gate: Gate = Gate::new_multi_cnot(0,2,3).unwrap();
         print_matrix(&gate.matrix);
 
         let q1: Qubit = Qubit::new_one_state();
@@ -405,8 +713,8 @@ mod tests {
 
     #[test]
     fn test_multi_controlled(){
-        let gate1: Gate = Gate::new_multi_controlled(0, 2, 3, Gate::new_not());
-        let gate2: Gate = Gate::new_multi_cnot(0, 2, 3);
+        let gate1: Gate = Gate::new_multi_controlled(0, 2, 3, Gate::new_not()).unwrap();
+        let gate2: Gate = Gate::new_multi_cnot(0, 2, 3).unwrap();
         for i in 0..gate2.matrix.rows.len(){
             for j in 0..gate2.matrix.rows.len(){
                 assert_eq!(gate1.matrix.rows[i][j], gate2.matrix.rows[i][j])
@@ -414,6 +722,269 @@ mod tests {
         }
     }
 
+    fn assert_registers_close(r1: &QuantumRegister, r2: &QuantumRegister){
+        assert_eq!(r1.state.len(), r2.state.len());
+        for i in 0..r1.state.len(){
+            assert!((r1.state[i] - r2.state[i]).norm_sqr() < 1e-10, "Mismatch at index {}: {} != {}", i, r1.state[i], r2.state[i]);
+        }
+    }
+
+    #[test]
+    fn test_apply_to_qubit_matches_full_matrix(){
+        let num_qubits: i32 = 3;
+        let mut r1: QuantumRegister = QuantumRegister::new_from_int(3, 8);
+        let mut r2: QuantumRegister = QuantumRegister::new_from_int(3, 8);
+        for target in 0..num_qubits{
+            Gate::multi_single_qubit_gate(target, num_qubits, Gate::new_h()).unwrap().apply(&mut r1).unwrap();
+            Gate::new_h().apply_to_qubit(target, &mut r2).unwrap();
+            Gate::multi_single_qubit_gate(target, num_qubits, Gate::new_t()).unwrap().apply(&mut r1).unwrap();
+            Gate::new_t().apply_to_qubit(target, &mut r2).unwrap();
+        }
+        assert_registers_close(&r1, &r2);
+    }
+
+    #[test]
+    fn test_apply_controlled_matches_multi_cnot(){
+        let num_qubits: i32 = 4;
+        let mut r1: QuantumRegister = QuantumRegister::new_from_int(0, 16);
+        let mut r2: QuantumRegister = QuantumRegister::new_from_int(0, 16);
+        Gate::new_multi_h(num_qubits).apply(&mut r1).unwrap();
+        Gate::new_multi_h(num_qubits).apply(&mut r2).unwrap();
+        Gate::multi_single_qubit_gate(2, num_qubits, Gate::new_t()).unwrap().apply(&mut r1).unwrap();
+        Gate::new_t().apply_to_qubit(2, &mut r2).unwrap();
+
+        Gate::new_multi_cnot(2, 3, num_qubits).unwrap().apply(&mut r1).unwrap();
+        Gate::new_not().apply_controlled(&[2], &[3], &mut r2).unwrap();
+        assert_registers_close(&r1, &r2);
+
+        Gate::new_cnot().apply_to_qubits(&[2, 1], &mut r2).unwrap();
+        Gate::new_multi_cnot(2, 1, num_qubits).unwrap().apply(&mut r1).unwrap();
+        assert_registers_close(&r1, &r2);
+    }
+
+    #[test]
+    fn test_apply_local_rejects_bad_qubits(){
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        assert_eq!(Err(QuantumError::QubitOutOfRange {qubit: 2, num_qubits: 2}), Gate::new_h().apply_to_qubit(2, &mut register));
+        assert_eq!(Err(QuantumError::QubitOutOfRange {qubit: -1, num_qubits: 2}), Gate::new_h().apply_to_qubit(-1, &mut register));
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 2, found: 4}), Gate::new_cnot().apply_to_qubit(0, &mut register));
+        assert_eq!(Err(QuantumError::ControlEqualsTarget(1)), Gate::new_not().apply_controlled(&[1], &[1], &mut register));
+        assert_eq!(Err(QuantumError::DuplicateQubit(0)), Gate::new_cnot().apply_to_qubits(&[0, 0], &mut register));
+    }
+
+    #[test]
+    fn test_errors(){
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 4, found: 8}), Gate::new_multi_h(3).apply(&mut register).err());
+        assert_eq!(Some(QuantumError::ControlEqualsTarget(1)), Gate::<f32>::new_multi_cnot(1, 1, 3).err());
+        assert_eq!(Some(QuantumError::QubitOutOfRange {qubit: 3, num_qubits: 3}), Gate::<f32>::new_multi_cnot(0, 3, 3).err());
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 2, found: 4}), Gate::<f32>::multi_single_qubit_gate(0, 3, Gate::new_cnot()).err());
+        assert_eq!(Some(QuantumError::NonUnitaryMatrix), Gate::<f32>::new_from_matrix(vec![vec![Complex::one(), Complex::one()], vec![Complex::zero(), Complex::one()]]).err());
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 4, found: 3}), Gate::<f32>::new_from_matrix(vec![vec![Complex::one(); 3]; 3]).err());
+        assert!(Gate::<f32>::new_from_matrix(Gate::new_y().matrix.rows).is_ok());
+    }
+
+    #[test]
+    fn test_apply_local_large_register(){
+        // A dense gate on 16 qubits would need a 65536 x 65536 matrix
+        let num_qubits: usize = 16;
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 1 << num_qubits);
+        Gate::new_h().apply_to_qubit(0, &mut register).unwrap();
+        for target in 1..num_qubits as i32{
+            Gate::new_not().apply_controlled(&[0], &[target], &mut register).unwrap();
+        }
+        let amplitude: f32 = 1. / f32::sqrt(2.);
+        assert!((register.state[0].re - amplitude).abs() < 1e-5);
+        assert!((register.state[(1 << num_qubits) - 1].re - amplitude).abs() < 1e-5);
+    }
+
+    fn assert_gates_close(g1: &Gate, g2: &Gate){
+        for i in 0..g1.matrix.rows.len(){
+            for j in 0..g1.matrix.rows.len(){
+                assert!((g1.matrix.rows[i][j] - g2.matrix.rows[i][j]).norm_sqr() < 1e-10, "Mismatch at ({}, {}): {} != {}", i, j, g1.matrix.rows[i][j], g2.matrix.rows[i][j]);
+            }
+        }
+    }
+
+    fn product(g1: &Gate, g2: &Gate) -> Gate {
+        let mut matrix: Matrix = g1.matrix.clone();
+        Gate {matrix: matrix.matrix_multiplication(g2.matrix.clone())}
+    }
+
+    #[test]
+    fn test_rotation_gates(){
+        let pi: f32 = std::f32::consts::PI;
+        // Rotations by pi are the pauli gates up to a global phase of -i
+        let minus_i: Complex<f32> = -Complex::i();
+        for (rotation, pauli) in [(Gate::new_rx(pi), Gate::new_not()), (Gate::new_ry(pi), Gate::new_y()), (Gate::new_rz(pi), Gate::new_z())]{
+            let mut scaled: Matrix = pauli.matrix.clone();
+            scaled.scalar_multiplication(minus_i);
+            assert_gates_close(&rotation, &Gate {matrix: scaled});
+            assert!(rotation.matrix.is_unitary(1e-5));
+        }
+
+        assert_gates_close(&Gate::new_u(pi / 2., 0., pi), &Gate::new_h());
+        assert_gates_close(&Gate::new_u(0.3, -pi / 2., pi / 2.), &Gate::new_rx(0.3));
+        assert_gates_close(&Gate::new_u(0.3, 0., 0.), &Gate::new_ry(0.3));
+        assert!(Gate::new_u(0.4, 1.1, -2.3).matrix.is_unitary(1e-5));
+    }
+
+    #[test]
+    fn test_phase_gates(){
+        assert_gates_close(&product(&Gate::new_s(), &Gate::new_s()), &Gate::new_z());
+        assert_gates_close(&product(&Gate::new_t(), &Gate::new_t()), &Gate::new_s());
+        assert_gates_close(&product(&Gate::new_sx(), &Gate::new_sx()), &Gate::new_not());
+        assert_gates_close(&product(&Gate::new_s(), &Gate::new_sdg()), &Gate::new_identity());
+        assert_gates_close(&product(&Gate::new_t(), &Gate::new_tdg()), &Gate::new_identity());
+        assert_gates_close(&product(&Gate::new_sx(), &Gate::new_sxdg()), &Gate::new_identity());
+        assert_gates_close(&product(&Gate::new_rx(0.7), &Gate::new_rx(0.7).inverse()), &Gate::new_identity());
+    }
+
+    // Apply the gate to every basis state of a num_qubits register and check it lands on the expected basis state
+    fn assert_truth_table(gate: &Gate, targets: &[i32], num_qubits: i32, expected: impl Fn(usize) -> usize){
+        let size: usize = 1 << num_qubits;
+        let expanded: Gate = gate.expand(targets, num_qubits).unwrap();
+        for input in 0..size{
+            let mut local: QuantumRegister = QuantumRegister::new_from_int(input, size);
+            let mut full: QuantumRegister = QuantumRegister::new_from_int(input, size);
+            gate.apply_to_qubits(targets, &mut local).unwrap();
+            expanded.apply(&mut full).unwrap();
+            for output in 0..size{
+                let probability: f32 = local.state[output].norm_sqr();
+                let expected_probability: f32 = if output == expected(input) { 1. } else { 0. };
+                assert!((probability - expected_probability).abs() < 1e-5, "Input {:b} should map to {:b}, got amplitude {} at {:b}", input, expected(input), local.state[output], output);
+            }
+            assert_registers_close(&local, &full);
+        }
+    }
+
+    // Value of qubit q in basis state j of an n qubit register
+    fn bit(j: usize, qubit: i32, num_qubits: i32) -> usize {
+        (j >> (num_qubits - 1 - qubit)) & 1
+    }
+
+    // Flip qubit q of basis state j
+    fn flip(j: usize, qubit: i32, num_qubits: i32) -> usize {
+        j ^ (1 << (num_qubits - 1 - qubit))
+    }
+
+    #[test]
+    fn test_two_qubit_truth_tables(){
+        let n: i32 = 4;
+        assert_truth_table(&Gate::new_swap(), &[3, 1], n, |j| {
+            if bit(j, 3, n) != bit(j, 1, n) { flip(flip(j, 3, n), 1, n) } else { j }
+        });
+        assert_truth_table(&Gate::new_cnot(), &[2, 0], n, |j| {
+            if bit(j, 2, n) == 1 { flip(j, 0, n) } else { j }
+        });
+        assert_truth_table(&Gate::new_iswap(), &[0, 2], n, |j| {
+            if bit(j, 0, n) != bit(j, 2, n) { flip(flip(j, 0, n), 2, n) } else { j }
+        });
+        assert_truth_table(&Gate::new_cz(), &[1, 3], n, |j| j);
+        assert_truth_table(&Gate::new_controlled_phase(0.3), &[3, 2], n, |j| j);
+    }
+
+    #[test]
+    fn test_three_qubit_truth_tables(){
+        let n: i32 = 4;
+        assert_truth_table(&Gate::new_toffoli(), &[3, 0, 1], n, |j| {
+            if bit(j, 3, n) == 1 && bit(j, 0, n) == 1 { flip(j, 1, n) } else { j }
+        });
+        assert_truth_table(&Gate::new_fredkin(), &[2, 3, 0], n, |j| {
+            if bit(j, 2, n) == 1 && bit(j, 3, n) != bit(j, 0, n) { flip(flip(j, 3, n), 0, n) } else { j }
+        });
+    }
+
+    #[test]
+    fn test_two_qubit_phases(){
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0b11, 4);
+        Gate::new_cz().apply_to_qubits(&[0, 1], &mut register).unwrap();
+        assert!((register.state[3] + Complex::one()).norm_sqr() < 1e-10);
+
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0b11, 4);
+        Gate::new_controlled_phase(0.25).apply_to_qubits(&[1, 0], &mut register).unwrap();
+        assert!((register.state[3] - Complex::i()).norm_sqr() < 1e-10);
+
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0b01, 4);
+        Gate::new_iswap().apply_to_qubits(&[0, 1], &mut register).unwrap();
+        assert!((register.state[2] - Complex::i()).norm_sqr() < 1e-10);
+
+        let sqrt_swap: Gate = Gate::new_sqrt_swap();
+        assert_gates_close(&product(&sqrt_swap, &sqrt_swap), &Gate::new_swap());
+        assert!(sqrt_swap.matrix.is_unitary(1e-5));
+    }
+
+    #[test]
+    fn test_expand_matches_multi_cnot(){
+        assert_gates_close(&Gate::new_cnot().expand(&[0, 2], 3).unwrap(), &Gate::new_multi_cnot(0, 2, 3).unwrap());
+        assert_gates_close(&Gate::new_h().expand(&[1], 3).unwrap(), &Gate::multi_single_qubit_gate(1, 3, Gate::new_h()).unwrap());
+        assert_eq!(Some(QuantumError::DuplicateQubit(1)), Gate::<f32>::new_swap().expand(&[1, 1], 3).err());
+    }
+
+    #[test]
+    fn test_multi_controlled_target_zero(){
+        // Control on qubit 2 with the target on qubit 0 used to apply the gate unconditionally
+        let n: i32 = 3;
+        assert_truth_table(&Gate::new_multi_cnot(2, 0, n).unwrap(), &[0, 1, 2], n, |j| {
+            if bit(j, 2, n) == 1 { flip(j, 0, n) } else { j }
+        });
+        assert_truth_table(&Gate::new_multi_controlled(1, 0, n, Gate::new_not()).unwrap(), &[0, 1, 2], n, |j| {
+            if bit(j, 1, n) == 1 { flip(j, 0, n) } else { j }
+        });
+    }
+
+    #[test]
+    fn test_controlled_polarity(){
+        let n: i32 = 4;
+        assert_gates_close(&Gate::new_controlled(&Gate::new_not(), &[true, true]), &Gate::new_toffoli());
+        assert_gates_close(&Gate::new_controlled(&Gate::new_swap(), &[true]), &Gate::new_fredkin());
+        assert_gates_close(&Gate::new_controlled(&Gate::new_z(), &[true]), &Gate::new_cz());
+
+        // Flip qubit 0 when qubit 3 is |0> and qubit 1 is |1>
+        let gate: Gate = Gate::new_controlled(&Gate::new_not(), &[false, true]);
+        assert_truth_table(&gate, &[3, 1, 0], n, |j| {
+            if bit(j, 3, n) == 0 && bit(j, 1, n) == 1 { flip(j, 0, n) } else { j }
+        });
+
+        // Controlled swap of qubits 0 and 2 on two negated controls
+        let gate: Gate = Gate::new_controlled(&Gate::new_swap(), &[false, false]);
+        assert_truth_table(&gate, &[3, 1, 0, 2], n, |j| {
+            if bit(j, 3, n) == 0 && bit(j, 1, n) == 0 && bit(j, 0, n) != bit(j, 2, n) { flip(flip(j, 0, n), 2, n) } else { j }
+        });
+    }
+
+    #[test]
+    fn test_apply_with_controls(){
+        let size: usize = 16;
+        for input in 0..size{
+            let mut r1: QuantumRegister = QuantumRegister::new_from_int(input, size);
+            let mut r2: QuantumRegister = QuantumRegister::new_from_int(input, size);
+            Gate::new_ry(0.8).apply_with_controls(&[Control::new_negated(2), Control::new(0)], &[3], &mut r1).unwrap();
+            Gate::new_controlled(&Gate::new_ry(0.8), &[false, true]).apply_to_qubits(&[2, 0, 3], &mut r2).unwrap();
+            assert_registers_close(&r1, &r2);
+        }
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        assert_eq!(Err(QuantumError::ControlEqualsTarget(1)), Gate::new_not().apply_with_controls(&[Control::new_negated(1)], &[1], &mut register));
+    }
+
+    #[test]
+    fn test_double_precision(){
+        let qft: Gate<f64> = Gate::new_qft(4);
+        let inv: Gate<f64> = Gate::new_inverse_qft(4);
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(5, 16);
+        for _ in 0..50{
+            qft.apply(&mut register).unwrap();
+            Gate::new_t().apply_to_qubit(1, &mut register).unwrap();
+            Gate::new_phase_rotation(-0.125).apply_to_qubit(1, &mut register).unwrap();
+            inv.apply(&mut register).unwrap();
+        }
+        for i in 0..register.state.len(){
+            let expected: f64 = if i == 5 { 1. } else { 0. };
+            assert!((register.state[i].re - expected).abs() < 1e-12);
+            assert!(register.state[i].im.abs() < 1e-12);
+        }
+    }
+
     #[test]
     fn test_qft(){
         let qft: Gate = Gate::new_qft(3);
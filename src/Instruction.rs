@@ -0,0 +1,293 @@
+use std::fmt;
+use crate::Gates::Gate;
+use crate::Noise::KrausChannel;
+use crate::Precision::Precision;
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::QuantumRegister;
+
+/**
+What a single circuit instruction does
+Named gates keep their name and parameters instead of a matrix, so a circuit can be inspected, printed and optimized
+Unitary holds a gate built by hand and acts on the instruction's targets (the first target is the left most factor of its matrix)
+Measure, Reset and Barrier are not gates: measure writes each target's outcome into the matching classical bit of the instruction,
+reset puts its targets back to |0> and barrier does nothing to the state (it only separates parts of a circuit)
+Kraus applies a noise channel to its targets, exactly on a density matrix or as a random trajectory on a state vector
+*/
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Operation<T: Precision = f32> {
+    #[cfg_attr(feature = "serde", serde(rename = "id"))]
+    I,
+    X,
+    Y,
+    Z,
+    H,
+    T,
+    Tdg,
+    S,
+    Sdg,
+    Sx,
+    Sxdg,
+    // Phase gate diag(1, e^(iλ)), params = [λ] in radians
+    #[cfg_attr(feature = "serde", serde(rename = "p"))]
+    Phase,
+    // Rotations around the X, Y and Z axes, params = [θ] in radians
+    Rx,
+    Ry,
+    Rz,
+    // General single qubit gate, params = [θ, φ, λ] in radians
+    U,
+    Swap,
+    #[cfg_attr(feature = "serde", serde(rename = "iswap"))]
+    ISwap,
+    SqrtSwap,
+    Qft,
+    #[cfg_attr(feature = "serde", serde(rename = "iqft"))]
+    InverseQft,
+    Unitary(Gate<T>),
+    Kraus(KrausChannel<T>),
+    Measure,
+    Reset,
+    Barrier,
+}
+
+impl<T: Precision> Operation<T> {
+    // Lower case name of the operation without any controls
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::I => "id",
+            Operation::X => "x",
+            Operation::Y => "y",
+            Operation::Z => "z",
+            Operation::H => "h",
+            Operation::T => "t",
+            Operation::Tdg => "tdg",
+            Operation::S => "s",
+            Operation::Sdg => "sdg",
+            Operation::Sx => "sx",
+            Operation::Sxdg => "sxdg",
+            Operation::Phase => "p",
+            Operation::Rx => "rx",
+            Operation::Ry => "ry",
+            Operation::Rz => "rz",
+            Operation::U => "u",
+            Operation::Swap => "swap",
+            Operation::ISwap => "iswap",
+            Operation::SqrtSwap => "sqrt_swap",
+            Operation::Qft => "qft",
+            Operation::InverseQft => "iqft",
+            Operation::Unitary(_) => "unitary",
+            Operation::Kraus(_) => "kraus",
+            Operation::Measure => "measure",
+            Operation::Reset => "reset",
+            Operation::Barrier => "barrier",
+        }
+    }
+
+    // Number of parameters the operation expects
+    pub fn num_params(&self) -> usize {
+        match self {
+            Operation::Phase | Operation::Rx | Operation::Ry | Operation::Rz => 1,
+            Operation::U => 3,
+            _ => 0,
+        }
+    }
+}
+
+/**
+Qubit controlling an operation
+The operation only acts on the part of the state where the qubit is |1> (on_one = true) or |0> (on_one = false)
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Control {
+    pub qubit: usize,
+    pub on_one: bool,
+}
+
+impl Control {
+    // Control firing when the qubit is |1>
+    pub fn new(qubit: usize) -> Self {
+        Self {qubit, on_one: true}
+    }
+
+    // Control firing when the qubit is |0>
+    pub fn new_negated(qubit: usize) -> Self {
+        Self {qubit, on_one: false}
+    }
+}
+
+/**
+Classical condition on an instruction: it only runs when the named classical register holds value
+The value is read with bit 0 of the register as the least significant bit
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Condition {
+    pub register: String,
+    pub value: u64,
+}
+
+/**
+One step of a quantum circuit: an operation, the qubits it acts on, the qubits controlling it and its parameters
+The operation is applied to the targets only where every control matches its value
+Measurements also list the classical bit each target is written to, and any instruction can be conditioned on a classical register
+*/
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Instruction<T: Precision = f32> {
+    pub operation: Operation<T>,
+    pub targets: Vec<usize>,
+    pub controls: Vec<Control>,
+    pub params: Vec<T>,
+    pub clbits: Vec<usize>,
+    pub condition: Option<Condition>,
+}
+
+impl<T: Precision> Instruction<T> {
+    pub fn new(operation: Operation<T>, targets: Vec<usize>, controls: Vec<Control>, params: Vec<T>) -> Self {
+        Self {operation, targets, controls, params, clbits: vec![], condition: None}
+    }
+
+    // Measure each target qubit into the classical bit at the same position
+    pub fn new_measure(qubits: Vec<usize>, clbits: Vec<usize>) -> Self {
+        Self {operation: Operation::Measure, targets: qubits, controls: vec![], params: vec![], clbits, condition: None}
+    }
+
+    // True for operations that have a matrix
+    pub fn is_unitary(&self) -> bool {
+        !matches!(self.operation, Operation::Kraus(_) | Operation::Measure | Operation::Reset | Operation::Barrier)
+    }
+
+    /**
+    Name of the instruction with one leading c per control qubit (ex cx, ccx, cp)
+    */
+    pub fn name(&self) -> String {
+        "c".repeat(self.controls.len()) + self.operation.name()
+    }
+
+    // Every qubit touched by the instruction, controls first
+    pub fn qubits(&self) -> Vec<usize> {
+        self.controls.iter().map(|control| control.qubit).chain(self.targets.iter().copied()).collect()
+    }
+
+    /**
+    Build the small gate acting on the targets only (2^k x 2^k for k targets)
+    Controls are not part of the matrix, they are handled when the gate is applied
+    */
+    pub fn gate(&self) -> Result<Gate<T>, QuantumError> {
+        if self.params.len() != self.operation.num_params() {
+            return Err(QuantumError::DimensionMismatch {expected: self.operation.num_params(), found: self.params.len()})
+        }
+        if !self.is_unitary() {
+            return Err(QuantumError::NonUnitaryOperation(self.name()))
+        }
+        let gate: Gate<T> = match &self.operation {
+            Operation::I => Gate::new_identity(),
+            Operation::X => Gate::new_not(),
+            Operation::Y => Gate::new_y(),
+            Operation::Z => Gate::new_z(),
+            Operation::H => Gate::new_h(),
+            Operation::T => Gate::new_t(),
+            Operation::Tdg => Gate::new_tdg(),
+            Operation::S => Gate::new_s(),
+            Operation::Sdg => Gate::new_sdg(),
+            Operation::Sx => Gate::new_sx(),
+            Operation::Sxdg => Gate::new_sxdg(),
+            Operation::Phase => Gate::new_phase_rotation(self.params[0] / T::two_pi()),
+            Operation::Rx => Gate::new_rx(self.params[0]),
+            Operation::Ry => Gate::new_ry(self.params[0]),
+            Operation::Rz => Gate::new_rz(self.params[0]),
+            Operation::U => Gate::new_u(self.params[0], self.params[1], self.params[2]),
+            Operation::Swap => Gate::new_swap(),
+            Operation::ISwap => Gate::new_iswap(),
+            Operation::SqrtSwap => Gate::new_sqrt_swap(),
+            Operation::Qft => Gate::new_qft(self.targets.len() as i32),
+            Operation::InverseQft => Gate::new_inverse_qft(self.targets.len() as i32),
+            Operation::Unitary(gate) => gate.clone(),
+            Operation::Kraus(_) | Operation::Measure | Operation::Reset | Operation::Barrier => unreachable!(),
+        };
+        Ok(gate)
+    }
+
+    /**
+    Apply the instruction to a register in place using the local gate kernel
+    */
+    pub fn apply(&self, register: &mut QuantumRegister<T>) -> Result<(), QuantumError> {
+        self.gate()?.apply_with_controls(&self.controls, &self.targets, register)
+    }
+}
+
+/**
+Prints the instruction as its name, parameters and qubits (ex "cx 0 3", "p(0.7854) 1")
+Controls firing on |0> are marked with a ! (ex "cx !0 3"), classical bits follow an arrow (ex "measure 0 -> 2")
+and conditions come first (ex "if(c==3) x 1")
+*/
+impl<T: Precision> fmt::Display for Instruction<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(condition) = &self.condition {
+            write!(f, "if({}=={}) ", condition.register, condition.value)?;
+        }
+        write!(f, "{}", self.name())?;
+        if !self.params.is_empty() {
+            let params: Vec<String> = self.params.iter().map(|param| format!("{:.4}", param)).collect();
+            write!(f, "({})", params.join(", "))?;
+        }
+        for control in self.controls.iter() {
+            write!(f, " {}{}", if control.on_one { "" } else { "!" }, control.qubit)?;
+        }
+        for qubit in self.targets.iter() {
+            write!(f, " {}", qubit)?;
+        }
+        if !self.clbits.is_empty() {
+            let clbits: Vec<String> = self.clbits.iter().map(|clbit| clbit.to_string()).collect();
+            write!(f, " -> {}", clbits.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Precision::real;
+
+    #[test]
+    fn test_name_and_qubits() {
+        let instruction: Instruction = Instruction::new(Operation::X, vec![3], vec![Control::new(0), Control::new_negated(1)], vec![]);
+        assert_eq!("ccx", instruction.name());
+        assert_eq!(vec![0, 1, 3], instruction.qubits());
+        assert_eq!("ccx 0 !1 3", instruction.to_string());
+
+        let phase: Instruction = Instruction::new(Operation::Phase, vec![1], vec![], vec![real(0.5)]);
+        assert_eq!("p(0.5000) 1", phase.to_string());
+    }
+
+    #[test]
+    fn test_classical_display() {
+        let measure: Instruction = Instruction::new_measure(vec![0, 1], vec![2, 0]);
+        assert_eq!("measure 0 1 -> 2 0", measure.to_string());
+        assert_eq!(Some(QuantumError::NonUnitaryOperation("measure".to_string())), measure.gate().err());
+
+        let mut conditioned: Instruction = Instruction::new(Operation::X, vec![1], vec![], vec![]);
+        conditioned.condition = Some(Condition {register: "c".to_string(), value: 3});
+        assert_eq!("if(c==3) x 1", conditioned.to_string());
+    }
+
+    #[test]
+    fn test_wrong_number_of_params() {
+        let instruction: Instruction = Instruction::new(Operation::Phase, vec![0], vec![], vec![]);
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 1, found: 0}), instruction.gate().err());
+    }
+
+    #[test]
+    fn test_phase_matches_t() {
+        let mut r1: QuantumRegister = QuantumRegister::new_from_vec(vec![nalgebra::Complex::from(1.); 4]);
+        let mut r2: QuantumRegister = r1.clone();
+        Instruction::new(Operation::Phase, vec![1], vec![], vec![std::f32::consts::FRAC_PI_4]).apply(&mut r1).unwrap();
+        Instruction::new(Operation::T, vec![1], vec![], vec![]).apply(&mut r2).unwrap();
+        for i in 0..r1.state.len(){
+            assert!((r1.state[i] - r2.state[i]).norm_sqr() < 1e-10);
+        }
+    }
+}
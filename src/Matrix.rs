@@ -1,13 +1,14 @@
 use nalgebra::{Complex};
 use num_traits::Zero;
 use num_traits::One;
+use crate::Precision::{Precision, real};
 
 
-pub(crate) struct Matrix {
-    pub rows: Vec<Vec<Complex<f32>>>
+pub(crate) struct Matrix<T: Precision = f32> {
+    pub rows: Vec<Vec<Complex<T>>>
 }
 
-impl Matrix {
+impl<T: Precision> Matrix<T> {
     pub fn new_zero(size: usize) -> Self {
         Self {
             rows: vec![vec![Complex::zero() ; size] ; size]
@@ -15,29 +16,29 @@ impl Matrix {
     }
 
     pub fn new_h() -> Self {
-        let mut matrix: Matrix = Matrix::new(vec![vec![Complex::one(), Complex::one()], vec![Complex::one(), Complex::one()*(-1.)]]);
-        matrix.scalar_multiplication(Complex::one() * (1./f32::sqrt(2.)));
+        let mut matrix: Matrix<T> = Matrix::new(vec![vec![Complex::one(), Complex::one()], vec![Complex::one(), -Complex::one()]]);
+        matrix.scalar_multiplication(Complex::from(T::one() / real::<T>(2.).sqrt()));
         Self {rows: matrix.rows}
     }
 
     pub fn new_identity(size: usize) -> Self{
-        let mut rows:Vec<Vec<Complex<f32>>> = vec![];
+        let mut rows:Vec<Vec<Complex<T>>> = vec![];
         for i in 0..size{
-            let mut row: Vec<Complex<f32>> = vec![Complex::zero() ; size];
+            let mut row: Vec<Complex<T>> = vec![Complex::zero() ; size];
             row[i] = Complex::one();
             rows.push(row);
         }
         Self {rows}
     }
 
-    pub fn new(data: Vec<Vec<Complex<f32>>>) -> Self {
+    pub fn new(data: Vec<Vec<Complex<T>>>) -> Self {
         Self {
             rows: data
         }
     }
 
     // Multiply by a scalar in place
-    pub fn scalar_multiplication(&mut self, c: Complex<f32>){
+    pub fn scalar_multiplication(&mut self, c: Complex<T>){
         for i in 0..self.rows.len(){
             for j in 0..self.rows[i].len(){
                 self.rows[i][j] *= c;
@@ -46,8 +47,8 @@ impl Matrix {
     }
 
     // Return copy of matrix after applying a scalar multiple
-    pub fn scalar_multiplication_copy(&mut self, matrix: &Matrix, c: Complex<f32>) -> Matrix {
-        let mut ret: Matrix = Matrix::new_zero(matrix.rows.len());
+    pub fn scalar_multiplication_copy(&mut self, matrix: &Matrix<T>, c: Complex<T>) -> Matrix<T> {
+        let mut ret: Matrix<T> = Matrix::new_zero(matrix.rows.len());
         for i in 0..matrix.rows.len(){
             for j in 0..matrix.rows[i].len(){
                 ret.rows[i][j] = c*matrix.rows[i][j];
@@ -56,8 +57,8 @@ impl Matrix {
         ret
     }
 
-    pub fn matrix_multiplication(&mut self, m2: Matrix) -> Matrix {
-        let mut ret: Matrix = Matrix::new_zero(self.rows[0].len());
+    pub fn matrix_multiplication(&mut self, m2: Matrix<T>) -> Matrix<T> {
+        let mut ret: Matrix<T> = Matrix::new_zero(self.rows[0].len());
         for i in 0..m2.rows[0].len(){
             for j in 0..self.rows.len(){
                 for k in 0..m2.rows.len(){
@@ -68,23 +69,6 @@ impl Matrix {
         ret
     }
     
-    pub fn matrix_addition(m1: Matrix, m2: Matrix) -> Result<Self, i32> {
-        if m1.rows.len() != m2.rows.len(){
-            return Err(-1);
-        }
-        let mut rows: Vec<Vec<Complex<f32>>> = vec![];
-        for i in 0..m1.rows.len() {
-            let mut row: Vec<Complex<f32>> = vec![Complex::zero() ; m1.rows.len()];
-            for j in 0..m1.rows.len() {
-                row[j] = m1.rows[i][j] + m2.rows[i][j];
-            }
-            rows.push(row);
-        }
-        Ok(Self {
-            rows
-        })
-    }
-
     /**
     Tensor product of 2 matrices
     A tensor B where A is self and B is another matrix
@@ -93,14 +77,14 @@ impl Matrix {
         [a21B a22B]
     where anm is a scalar at index(n, m) in matrix A and B is the 2nd matrix
      */
-    pub fn tensor_product(&mut self, m2: &Matrix) -> Matrix {
+    pub fn tensor_product(&mut self, m2: &Matrix<T>) -> Matrix<T> {
         let num_rows: usize = self.rows.len()*m2.rows.len();
-        let mut new_matrix: Matrix = Matrix::new_zero(num_rows);
+        let mut new_matrix: Matrix<T> = Matrix::new_zero(num_rows);
 
         for i in 0..self.rows.len(){
             for j in 0..self.rows[i].len(){
                 // Current scalar multiple of m2 where it is being scaled by a factor of Aij
-                let current: Matrix = self.scalar_multiplication_copy(&m2, self.rows[i][j]);
+                let current: Matrix<T> = self.scalar_multiplication_copy(&m2, self.rows[i][j]);
                 for k in 0..current.rows.len(){
                     for l in 0..current.rows[k].len(){
                         new_matrix.rows[i*current.rows.len() + k][j*current.rows[k].len() + l] = current.rows[k][l];
@@ -112,17 +96,37 @@ impl Matrix {
     }
 
     pub fn conjugate_transpose(&mut self){
-        let mut transpose: Matrix = Matrix::new_zero(self.rows.len());
+        let mut transpose: Matrix<T> = Matrix::new_zero(self.rows.len());
         for i in 0..self.rows.len(){
             for j in 0..self.rows.len(){
-                transpose.rows[i][j] = Complex::from(self.rows[j][i].re) + Complex::i() * -1. * Complex::from(self.rows[j][i].im);
+                transpose.rows[i][j] = self.rows[j][i].conj();
             }
         }
         self.rows = transpose.rows;
     }
 
-    pub fn clone(&self) -> Matrix {
-        let vec: Vec<Vec<Complex<f32>>> = self.rows.clone();
+    /**
+    Check that M * M^† is the identity, with every entry allowed to be off by at most tolerance
+    */
+    pub fn is_unitary(&self, tolerance: T) -> bool {
+        let len: usize = self.rows.len();
+        for i in 0..len{
+            for j in 0..len{
+                let mut entry: Complex<T> = Complex::zero();
+                for k in 0..len{
+                    entry += self.rows[i][k] * self.rows[j][k].conj();
+                }
+                let expected: Complex<T> = if i == j { Complex::one() } else { Complex::zero() };
+                if (entry - expected).norm_sqr() > tolerance * tolerance {
+                    return false
+                }
+            }
+        }
+        true
+    }
+
+    pub fn clone(&self) -> Matrix<T> {
+        let vec: Vec<Vec<Complex<T>>> = self.rows.clone();
         Matrix::new(vec)
     }
 
@@ -194,21 +198,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_matrix_addition(){
-        let mut matrix1: Matrix = Matrix::new_zero(2);
-        matrix1.rows[0][0] = Complex::one();
-        let mut matrix2: Matrix = Matrix::new_zero(2);
-        matrix2.rows[1][1] = Complex::one();
-        let matrix = Matrix::matrix_addition(matrix1, matrix2).unwrap();
-
-        for i in 0..matrix.rows.len(){
-            for j in 0..matrix.rows[i].len(){
-                print!("Value: {}\n", matrix.rows[i][j]);
-            }
-        }
-    }
-
     #[test]
     fn test_matrix_tensor(){
         let data: Vec<Vec<Complex<f32>>> = vec![vec![Complex::one(), Complex::one() * 2.], vec![Complex::one() * 2., Complex::one()]];
@@ -0,0 +1,478 @@
+use std::collections::BTreeMap;
+use nalgebra::{Complex, DMatrix};
+use num_traits::{One, Zero};
+use rand::Rng;
+use crate::Backend::Backend;
+use crate::Instruction::{Control, Instruction};
+use crate::Matrix::Matrix;
+use crate::Noise::KrausChannel;
+use crate::Precision::{Precision, real};
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::draw_outcome;
+
+/**
+State of a chain of n qubits as a matrix product state: the amplitude of |s0 s1 ... s(n-1)> is the product A0[s0] A1[s1] ... A(n-1)[s(n-1)]
+of one χ(q) x χ(q+1) matrix per qubit and bit value, the bond dimensions χ starting and ending at 1
+Memory grows with the entanglement between the two halves of the chain instead of 2^n, so weakly entangled chains of many qubits fit
+The state is kept in mixed canonical form around one site, so the singular values found when a gate splits sites again are the true
+Schmidt coefficients: the smallest ones are dropped when they fall below the truncation threshold or exceed the maximum bond dimension,
+and the weight they carried is added to the truncation error
+Gates on qubits that are not neighbours are applied by swapping the qubits next to each other and back
+*/
+#[derive(Clone)]
+pub struct MatrixProductState<T: Precision = f32> {
+    // sites[q][s] is the matrix of qubit q for bit value s
+    sites: Vec<[DMatrix<Complex<T>>; 2]>,
+    // Every site left of the center is left canonical and every site right of it right canonical
+    center: usize,
+    max_bond_dimension: usize,
+    truncation_threshold: T,
+    truncation_error: T,
+}
+
+impl<T: Precision> MatrixProductState<T> {
+    // |0...0> without any limit on the bond dimension, only singular values below 1e-12 are dropped
+    pub fn new(num_qubits: usize) -> Self {
+        Self::new_with_limits(num_qubits, usize::MAX, real(1e-12))
+    }
+
+    /**
+    |0...0> keeping at most max_bond_dimension singular values on every bond and dropping the ones smaller than truncation_threshold
+    */
+    pub fn new_with_limits(num_qubits: usize, max_bond_dimension: usize, truncation_threshold: T) -> Self {
+        let zero: DMatrix<Complex<T>> = DMatrix::zeros(1, 1);
+        let one: DMatrix<Complex<T>> = DMatrix::from_element(1, 1, Complex::one());
+        Self {
+            sites: vec![[one, zero]; num_qubits],
+            center: 0,
+            max_bond_dimension: max_bond_dimension.max(1),
+            truncation_threshold,
+            truncation_error: T::zero(),
+        }
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.sites.len()
+    }
+
+    // χ of the bond between qubit q and q + 1 for every q
+    pub fn bond_dimensions(&self) -> Vec<usize> {
+        self.sites.iter().take(self.num_qubits().saturating_sub(1)).map(|site| site[0].ncols()).collect()
+    }
+
+    pub fn max_bond_dimension(&self) -> usize {
+        self.max_bond_dimension
+    }
+
+    pub fn truncation_threshold(&self) -> T {
+        self.truncation_threshold
+    }
+
+    /**
+    Total weight (sum of squared singular values) dropped by truncations so far
+    1 minus the truncation error is roughly the fidelity of the state with the exact one
+    */
+    pub fn truncation_error(&self) -> T {
+        self.truncation_error
+    }
+
+    /**
+    Amplitude of a basis state given as a bitstring, qubit 0 first (ex "0110")
+    */
+    pub fn amplitude(&self, bits: &str) -> Result<Complex<T>, QuantumError> {
+        if bits.len() != self.num_qubits() {
+            return Err(QuantumError::DimensionMismatch {expected: self.num_qubits(), found: bits.len()})
+        }
+        let mut product: DMatrix<Complex<T>> = DMatrix::from_element(1, 1, Complex::one());
+        for (bit, site) in bits.chars().zip(self.sites.iter()) {
+            product = match bit {
+                '0' => product * &site[0],
+                '1' => product * &site[1],
+                _ => return Err(QuantumError::InvalidBitstring(bits.to_string())),
+            };
+        }
+        Ok(product[(0, 0)])
+    }
+
+    /**
+    Measure every qubit shots times without collapsing the state, like QuantumRegister::sample
+    Each shot draws the qubits from left to right, each conditioned on the bits drawn before it, in O(n χ^2)
+    */
+    pub fn sample(&self, shots: usize) -> BTreeMap<String, usize> {
+        self.sample_with_rng(shots, &mut rand::thread_rng())
+    }
+
+    pub fn sample_with_rng<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+        let mut state: Self = self.clone();
+        state.move_center(0);
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for _ in 0..shots {
+            let mut left: DMatrix<Complex<T>> = DMatrix::from_element(1, 1, Complex::one());
+            let mut bits: String = String::with_capacity(state.num_qubits());
+            for site in state.sites.iter() {
+                let branches: [DMatrix<Complex<T>>; 2] = [&left * &site[0], &left * &site[1]];
+                let probabilities: Vec<T> = branches.iter().map(|branch| branch.norm_squared()).collect();
+                let bit: usize = draw_outcome(&probabilities, rng);
+                left = &branches[bit] / Complex::from(probabilities[bit].sqrt());
+                bits.push(if bit == 0 { '0' } else { '1' });
+            }
+            *counts.entry(bits).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /**
+    Measure a qubit and collapse the state onto the outcome
+    */
+    pub fn measure_qubit(&mut self, qubit: usize) -> Result<i32, QuantumError> {
+        self.measure_qubit_with_rng(qubit, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubit_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<i32, QuantumError> {
+        self.check_qubits(&[qubit])?;
+        self.move_center(qubit);
+        // With the center on the qubit the norm of each of its matrices is the probability of the bit
+        let probabilities: Vec<T> = self.sites[qubit].iter().map(|matrix| matrix.norm_squared()).collect();
+        let bit: usize = draw_outcome(&probabilities, rng);
+        let site: &mut [DMatrix<Complex<T>>; 2] = &mut self.sites[qubit];
+        site[bit] /= Complex::from(probabilities[bit].sqrt());
+        site[1 - bit].fill(Complex::zero());
+        Ok(bit as i32)
+    }
+
+    // Measure qubits one after the other and return their bits in the order given
+    pub fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        self.check_qubits(qubits)?;
+        qubits.iter().map(|&qubit| self.measure_qubit_with_rng(qubit, rng)).collect()
+    }
+
+    // Put a qubit back into |0> by measuring it and flipping it if it came out as 1
+    pub fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        if self.measure_qubit_with_rng(qubit, rng)? == 1 {
+            self.sites[qubit].swap(0, 1);
+        }
+        Ok(())
+    }
+
+    /**
+    Apply a noise channel as one Monte-Carlo trajectory, like QuantumRegister::apply_channel
+    */
+    pub fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        channel.check_placement(targets, self.num_qubits())?;
+        let operators: Vec<DMatrix<Complex<T>>> = channel.matrices().iter().map(to_dmatrix).collect();
+        self.on_adjacent_qubits(targets, |state, start| {
+            let theta: Vec<DMatrix<Complex<T>>> = state.contract(start, targets.len());
+            let branches: Vec<Vec<DMatrix<Complex<T>>>> = operators.iter().map(|operator| apply_to_block(operator, &theta)).collect();
+            let probabilities: Vec<T> = branches.iter()
+                .map(|branch| branch.iter().fold(T::zero(), |total, matrix| total + matrix.norm_squared()))
+                .collect();
+            let outcome: usize = draw_outcome(&probabilities, rng);
+            let scale: Complex<T> = Complex::from(T::one() / probabilities[outcome].sqrt());
+            let theta: Vec<DMatrix<Complex<T>>> = branches[outcome].iter().map(|matrix| matrix * scale).collect();
+            state.split(start, theta);
+        });
+        Ok(())
+    }
+
+    /**
+    Apply a 2^k x 2^k matrix to the given qubits, the first one being the left most factor of the matrix
+    */
+    pub(crate) fn apply_matrix(&mut self, matrix: &DMatrix<Complex<T>>, qubits: &[usize]) {
+        self.on_adjacent_qubits(qubits, |state, start| {
+            let theta: Vec<DMatrix<Complex<T>>> = state.contract(start, qubits.len());
+            state.split(start, apply_to_block(matrix, &theta));
+        });
+    }
+
+    /**
+    Swap the given qubits next to each other in the order given, starting at the left most one, call f with the site of the first one
+    and swap everything back afterwards
+    */
+    fn on_adjacent_qubits<F: FnOnce(&mut Self, usize)>(&mut self, qubits: &[usize], f: F) {
+        let start: usize = qubits.iter().copied().min().unwrap_or(0);
+        let mut positions: Vec<usize> = qubits.to_vec();
+        let mut swaps: Vec<usize> = vec![];
+        for k in 0..positions.len() {
+            // Every qubit not placed yet sits right of the placed ones, so it only ever moves left
+            while positions[k] > start + k {
+                let site: usize = positions[k] - 1;
+                self.swap_sites(site);
+                swaps.push(site);
+                for position in positions.iter_mut().filter(|position| **position == site) {
+                    *position += 1;
+                }
+                positions[k] -= 1;
+            }
+        }
+        f(self, start);
+        for &site in swaps.iter().rev() {
+            self.swap_sites(site);
+        }
+    }
+
+    fn swap_sites(&mut self, site: usize) {
+        let theta: Vec<DMatrix<Complex<T>>> = self.contract(site, 2);
+        self.split(site, vec![theta[0].clone(), theta[2].clone(), theta[1].clone(), theta[3].clone()]);
+    }
+
+    /**
+    Contract the sites start..start + k into one block: 2^k matrices χ(start) x χ(start + k), the first site's bit being the most significant
+    Moves the center into the block first so the block holds the whole norm
+    */
+    fn contract(&mut self, start: usize, k: usize) -> Vec<DMatrix<Complex<T>>> {
+        self.move_center(start);
+        let mut theta: Vec<DMatrix<Complex<T>>> = vec![DMatrix::identity(self.sites[start][0].nrows(), self.sites[start][0].nrows())];
+        for site in self.sites[start..start + k].iter() {
+            theta = theta.iter().flat_map(|matrix| [matrix * &site[0], matrix * &site[1]]).collect();
+        }
+        theta
+    }
+
+    /**
+    Split a block back into k sites from left to right with one singular value decomposition per bond, truncating each bond
+    The center ends on the last site of the block
+    */
+    fn split(&mut self, start: usize, mut theta: Vec<DMatrix<Complex<T>>>) {
+        let k: usize = theta.len().trailing_zeros() as usize;
+        for site in start..start + k - 1 {
+            let rest: usize = theta.len() / 2;
+            let (rows, cols) = theta[0].shape();
+            // Rows (bit, left bond), columns (bits of the remaining sites, right bond)
+            let block: DMatrix<Complex<T>> = DMatrix::from_fn(2 * rows, rest * cols, |i, j| {
+                theta[(i / rows) * rest + j / cols][(i % rows, j % cols)]
+            });
+            let (left, remainder) = self.decompose(block, true);
+            self.sites[site] = [left.rows(0, rows).into_owned(), left.rows(rows, rows).into_owned()];
+            theta = (0..rest).map(|r| remainder.columns(r * cols, cols).into_owned()).collect();
+        }
+        let last: usize = start + k - 1;
+        self.sites[last] = [theta[0].clone(), theta[1].clone()];
+        self.center = last;
+    }
+
+    // Move the center of the canonical form to the given site without truncating
+    fn move_center(&mut self, to: usize) {
+        while self.center < to {
+            let site: usize = self.center;
+            let rows: usize = self.sites[site][0].nrows();
+            let cols: usize = self.sites[site][0].ncols();
+            let mut block: DMatrix<Complex<T>> = DMatrix::zeros(2 * rows, cols);
+            block.rows_mut(0, rows).copy_from(&self.sites[site][0]);
+            block.rows_mut(rows, rows).copy_from(&self.sites[site][1]);
+            let (left, remainder) = self.decompose(block, false);
+            self.sites[site] = [left.rows(0, rows).into_owned(), left.rows(rows, rows).into_owned()];
+            let next: &mut [DMatrix<Complex<T>>; 2] = &mut self.sites[site + 1];
+            next[0] = &remainder * &next[0];
+            next[1] = &remainder * &next[1];
+            self.center += 1;
+        }
+        while self.center > to {
+            let site: usize = self.center;
+            let rows: usize = self.sites[site][0].nrows();
+            let cols: usize = self.sites[site][0].ncols();
+            let mut block: DMatrix<Complex<T>> = DMatrix::zeros(rows, 2 * cols);
+            block.columns_mut(0, cols).copy_from(&self.sites[site][0]);
+            block.columns_mut(cols, cols).copy_from(&self.sites[site][1]);
+            // Decompose the adjoint so the isometry ends up on the right
+            let (right, remainder) = self.decompose(block.adjoint(), false);
+            let right: DMatrix<Complex<T>> = right.adjoint();
+            let remainder: DMatrix<Complex<T>> = remainder.adjoint();
+            self.sites[site] = [right.columns(0, cols).into_owned(), right.columns(cols, cols).into_owned()];
+            let previous: &mut [DMatrix<Complex<T>>; 2] = &mut self.sites[site - 1];
+            previous[0] = &previous[0] * &remainder;
+            previous[1] = &previous[1] * &remainder;
+            self.center -= 1;
+        }
+    }
+
+    /**
+    Singular value decomposition M = U S V^†, returning the isometry U and the remainder S V^† with only the kept singular values
+    Without truncation only numerically zero singular values are dropped, with it the limits of the state apply and the dropped weight
+    is added to the truncation error, the kept singular values being scaled back up to the norm of M
+    */
+    fn decompose(&mut self, block: DMatrix<Complex<T>>, truncate: bool) -> (DMatrix<Complex<T>>, DMatrix<Complex<T>>) {
+        let svd = block.svd(true, true);
+        let (u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+        let values: Vec<T> = svd.singular_values.iter().copied().collect();
+        let total: T = values.iter().fold(T::zero(), |total, &value| total + value * value);
+        let largest: T = values.first().copied().unwrap_or(T::zero());
+        let cutoff: T = if truncate { self.truncation_threshold.max(largest * real(1e-14)) } else { largest * real(1e-14) };
+        let limit: usize = if truncate { self.max_bond_dimension } else { usize::MAX };
+        let kept: usize = values.iter().take_while(|&&value| value > cutoff).count().min(limit).max(1);
+
+        let weight: T = values[..kept].iter().fold(T::zero(), |total, &value| total + value * value);
+        let mut scale: T = T::one();
+        if truncate && total > T::zero() && kept < values.len() {
+            self.truncation_error += (total - weight) / total;
+            scale = (total / weight).sqrt();
+        }
+        let mut remainder: DMatrix<Complex<T>> = v_t.rows(0, kept).into_owned();
+        for (i, mut row) in remainder.row_iter_mut().enumerate() {
+            row *= Complex::from(values[i] * scale);
+        }
+        (u.columns(0, kept).into_owned(), remainder)
+    }
+
+    // Every qubit has to be in range and used only once
+    fn check_qubits(&self, qubits: &[usize]) -> Result<(), QuantumError> {
+        let qubits: Vec<i32> = qubits.iter().map(|&qubit| qubit as i32).collect();
+        crate::Gates::Gate::<T>::check_distinct_qubits(&[], &qubits, self.num_qubits() as i32)
+    }
+}
+
+// Gate matrix as an nalgebra matrix
+fn to_dmatrix<T: Precision>(matrix: &Matrix<T>) -> DMatrix<Complex<T>> {
+    let size: usize = matrix.rows.len();
+    DMatrix::from_fn(size, size, |i, j| matrix.rows[i][j])
+}
+
+/**
+Matrix of a gate with controls on the qubits controls followed by targets: the gate where every control matches and the identity elsewhere
+*/
+fn with_controls<T: Precision>(matrix: &Matrix<T>, controls: &[Control]) -> DMatrix<Complex<T>> {
+    let dim: usize = matrix.rows.len();
+    let size: usize = dim << controls.len();
+    // Index of the local block where every control fires
+    let active: usize = controls.iter().fold(0, |index, control| (index << 1) | control.on_one as usize) * dim;
+    DMatrix::from_fn(size, size, |i, j| {
+        if i >= active && i < active + dim && j >= active && j < active + dim {
+            matrix.rows[i - active][j - active]
+        } else if i == j {
+            Complex::one()
+        } else {
+            Complex::zero()
+        }
+    })
+}
+
+// theta'[s] = Σ_t M[s][t] theta[t]
+fn apply_to_block<T: Precision>(matrix: &DMatrix<Complex<T>>, theta: &[DMatrix<Complex<T>>]) -> Vec<DMatrix<Complex<T>>> {
+    (0..theta.len()).map(|s| {
+        theta.iter().enumerate().fold(DMatrix::zeros(theta[0].nrows(), theta[0].ncols()), |total, (t, block)| {
+            if matrix[(s, t)] == Complex::zero() { total } else { total + block * matrix[(s, t)] }
+        })
+    }).collect()
+}
+
+impl<T: Precision> Backend<T> for MatrixProductState<T> {
+    fn num_qubits(&self) -> usize {
+        MatrixProductState::num_qubits(self)
+    }
+
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+        let gate = instruction.gate()?;
+        gate.check_placement(&instruction.controls, &instruction.targets, self.num_qubits())?;
+        self.apply_matrix(&with_controls(gate.matrix(), &instruction.controls), &instruction.qubits());
+        Ok(())
+    }
+
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        MatrixProductState::measure_qubits_with_rng(self, qubits, rng)
+    }
+
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        MatrixProductState::apply_channel_with_rng(self, channel, targets, rng)
+    }
+
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        MatrixProductState::reset_with_rng(self, qubit, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Gates::Gate;
+    use crate::QuantumCircuit::QuantumCircuit;
+    use crate::QuantumRegister::QuantumRegister;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use nalgebra::ComplexField;
+
+    #[test]
+    fn test_matches_state_vector() {
+        // Long range and multi qubit gates go through swaps, without limits the state stays exact
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(6);
+        circuit.h(0).rx(1, 0.3).cx(0, 5).u(3, 0.2, 0.5, -0.4).ccx(4, 1, 2).mcx(&[Control::new_negated(5)], 1);
+        circuit.iswap(4, 0).qft(&[2, 5, 0]).cp(3, 1, 0.7).unitary(Gate::new_sqrt_swap(), &[5, 2]).ry(4, 1.1);
+
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 64);
+        circuit.run(&mut register).unwrap();
+        let mut state: MatrixProductState<f64> = MatrixProductState::new(6);
+        circuit.run(&mut state).unwrap();
+        for index in 0..64 {
+            let amplitude: Complex<f64> = state.amplitude(&format!("{:06b}", index)).unwrap();
+            assert!((amplitude - register.state[index]).modulus() < 1e-9, "{} {} {}", index, amplitude, register.state[index]);
+        }
+        assert!(state.truncation_error() < 1e-12);
+        assert_eq!(Err(QuantumError::InvalidBitstring("0a0000".to_string())), state.amplitude("0a0000"));
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 6, found: 2}), state.amplitude("01"));
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // GHZ state on 80 qubits only needs bond dimension 2
+        let num_qubits: usize = 80;
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(num_qubits as i32);
+        circuit.h(0);
+        for qubit in 1..num_qubits {
+            circuit.cx(qubit - 1, qubit);
+        }
+        let mut state: MatrixProductState<f64> = MatrixProductState::new_with_limits(num_qubits, 8, 1e-10);
+        circuit.run(&mut state).unwrap();
+        assert!(state.bond_dimensions().iter().all(|&bond| bond == 2));
+        assert!((state.amplitude(&"1".repeat(num_qubits)).unwrap().modulus() - 0.5f64.sqrt()).abs() < 1e-9);
+        assert!(state.amplitude(&("1".to_string() + &"0".repeat(num_qubits - 1))).unwrap().modulus() < 1e-9);
+
+        let counts: BTreeMap<String, usize> = state.sample_with_rng(200, &mut StdRng::seed_from_u64(3));
+        assert_eq!(vec!["0".repeat(num_qubits), "1".repeat(num_qubits)], counts.keys().cloned().collect::<Vec<String>>());
+
+        // Measuring the middle qubit fixes every other one
+        let mut rng: StdRng = StdRng::seed_from_u64(8);
+        let bit: i32 = state.measure_qubit_with_rng(40, &mut rng).unwrap();
+        assert_eq!(vec![bit; 3], state.measure_qubits_with_rng(&[0, 79, 17], &mut rng).unwrap());
+    }
+
+    #[test]
+    fn test_truncation() {
+        // A bell pair squeezed into bond dimension 1 loses half its weight
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        circuit.ry(0, 1.).cx(0, 1);
+        let mut state: MatrixProductState<f64> = MatrixProductState::new_with_limits(3, 1, 0.);
+        circuit.run(&mut state).unwrap();
+        let dropped: f64 = 0.5f64.sin().powi(2);
+        assert!((state.truncation_error() - dropped).abs() < 1e-12);
+        assert_eq!(vec![1, 1], state.bond_dimensions());
+        assert!((state.amplitude("000").unwrap().modulus() - 1.).abs() < 1e-12);
+
+        // A threshold drops singular values below it
+        let mut state: MatrixProductState<f64> = MatrixProductState::new_with_limits(3, 16, 0.1);
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        circuit.ry(0, 0.1).cx(0, 1);
+        circuit.run(&mut state).unwrap();
+        assert!((state.truncation_error() - 0.05f64.sin().powi(2)).abs() < 1e-12);
+        assert_eq!(16, state.max_bond_dimension());
+    }
+
+    #[test]
+    fn test_measurement_and_reset() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        circuit.add_classical_register("c", 2).unwrap();
+        circuit.h(0).cx(0, 3).measure(3, 0).x(1).c_if("c", 1).reset(0).reset(3).measure(1, 1);
+        let mut rng: StdRng = StdRng::seed_from_u64(12);
+        for _ in 0..10 {
+            let mut state: MatrixProductState<f64> = MatrixProductState::new(4);
+            let bits: Vec<i32> = circuit.run_with_rng(&mut state, &mut rng).unwrap();
+            assert_eq!(bits[0], bits[1]);
+            let expected: String = format!("0{}00", bits[0]);
+            assert!((state.amplitude(&expected).unwrap().modulus() - 1.).abs() < 1e-9);
+        }
+
+        // Amplitude damping with γ = 1 always ends in |0>
+        let mut state: MatrixProductState<f64> = MatrixProductState::new(2);
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(1).channel(KrausChannel::new_amplitude_damping(1.).unwrap(), &[1]);
+        circuit.run_with_rng(&mut state, &mut rng).unwrap();
+        assert!((state.amplitude("00").unwrap().modulus() - 1.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,509 @@
+use nalgebra::Complex;
+use num_traits::{One, Zero};
+use rand::Rng;
+use crate::Gates::Gate;
+use crate::Instruction::Instruction;
+use crate::Matrix::Matrix;
+use crate::Precision::{Precision, real};
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::{apply_local_matrix, draw_outcome};
+
+/**
+Noise channel ρ -> Σ K_i ρ K_i^† on k qubits given by its Kraus operators K_i, each a 2^k x 2^k matrix
+The operators satisfy Σ K_i^† K_i = I so probabilities still add up to 1 after the channel
+On a DensityMatrix the channel is applied exactly, on a QuantumRegister a single operator is picked at random
+with probability ||K_i |ψ>||^2 (a Monte-Carlo trajectory), so averaging many runs gives the same result as the density matrix
+*/
+pub struct KrausChannel<T: Precision = f32> {
+    operators: Vec<Matrix<T>>,
+}
+
+impl<T: Precision> KrausChannel<T> {
+    /**
+    Channel from user supplied Kraus operators
+    Every operator has to be 2^k x 2^k with the same k and together they have to satisfy Σ K_i^† K_i = I (within 1e-4)
+    */
+    pub fn new(operators: Vec<Vec<Vec<Complex<T>>>>) -> Result<Self, QuantumError> {
+        let size: usize = match operators.first() {
+            Some(rows) => rows.len(),
+            None => return Err(QuantumError::NotTracePreserving),
+        };
+        if size == 0 || !size.is_power_of_two() {
+            return Err(QuantumError::DimensionMismatch {expected: size.next_power_of_two().max(2), found: size})
+        }
+        for rows in operators.iter() {
+            if let Some(length) = std::iter::once(rows.len()).chain(rows.iter().map(|row| row.len())).find(|&length| length != size) {
+                return Err(QuantumError::DimensionMismatch {expected: size, found: length})
+            }
+        }
+        let channel: Self = Self {operators: operators.into_iter().map(Matrix::new).collect()};
+        if !channel.is_trace_preserving(real(1e-4)) {
+            return Err(QuantumError::NotTracePreserving)
+        }
+        Ok(channel)
+    }
+
+    // With probability p the qubit gets an X: K0 = sqrt(1 - p) I, K1 = sqrt(p) X
+    pub fn new_bit_flip(probability: T) -> Result<Self, QuantumError> {
+        check_probability(probability)?;
+        Ok(Self::new_pauli_mixture(probability, &[pauli(1)]))
+    }
+
+    // With probability p the qubit gets a Z: K0 = sqrt(1 - p) I, K1 = sqrt(p) Z
+    pub fn new_phase_flip(probability: T) -> Result<Self, QuantumError> {
+        check_probability(probability)?;
+        Ok(Self::new_pauli_mixture(probability, &[pauli(3)]))
+    }
+
+    /**
+    With probability p the qubits get one of the 4^k - 1 Pauli products other than the identity, each as likely
+    For one qubit this is ρ -> (1 - p) ρ + p/3 (XρX + YρY + ZρZ), and p = 3/4 leaves the maximally mixed state
+    */
+    pub fn new_depolarizing(probability: T, num_qubits: usize) -> Result<Self, QuantumError> {
+        check_probability(probability)?;
+        if num_qubits == 0 {
+            return Err(QuantumError::DimensionMismatch {expected: 2, found: 1})
+        }
+        // Every Pauli product, the identity (index 0) left out
+        let errors: Vec<Matrix<T>> = (1..1 << (2 * num_qubits)).map(|index: usize| {
+            (0..num_qubits).fold(Matrix::new_identity(1), |mut product, qubit| {
+                product.tensor_product(&pauli((index >> (2 * (num_qubits - 1 - qubit))) & 3))
+            })
+        }).collect();
+        Ok(Self::new_pauli_mixture(probability, &errors))
+    }
+
+    /**
+    Energy loss (T1): |1> decays to |0> with probability γ
+    K0 = [[1, 0], [0, sqrt(1 - γ)]], K1 = [[0, sqrt(γ)], [0, 0]]
+    */
+    pub fn new_amplitude_damping(gamma: T) -> Result<Self, QuantumError> {
+        check_probability(gamma)?;
+        let zero: Complex<T> = Complex::zero();
+        Ok(Self {operators: vec![
+            Matrix::new(vec![vec![Complex::one(), zero], vec![zero, Complex::from((T::one() - gamma).sqrt())]]),
+            Matrix::new(vec![vec![zero, Complex::from(gamma.sqrt())], vec![zero, zero]]),
+        ]})
+    }
+
+    /**
+    Loss of coherence without energy loss (pure dephasing): the off diagonal terms of ρ are scaled by sqrt(1 - λ)
+    K0 = [[1, 0], [0, sqrt(1 - λ)]], K1 = [[0, 0], [0, sqrt(λ)]]
+    */
+    pub fn new_phase_damping(lambda: T) -> Result<Self, QuantumError> {
+        check_probability(lambda)?;
+        let zero: Complex<T> = Complex::zero();
+        Ok(Self {operators: vec![
+            Matrix::new(vec![vec![Complex::one(), zero], vec![zero, Complex::from((T::one() - lambda).sqrt())]]),
+            Matrix::new(vec![vec![zero, zero], vec![zero, Complex::from(lambda.sqrt())]]),
+        ]})
+    }
+
+    /**
+    T1 / T2 relaxation of a qubit over a gate taking time (same unit as t1 and t2)
+    Amplitude damping with γ = 1 - e^(-time/T1) followed by phase damping with λ = 1 - e^(time/T1 - 2 time/T2),
+    so |1> decays like e^(-time/T1) and the coherence between |0> and |1> like e^(-time/T2)
+    T1 and T2 have to be positive, time not negative and T2 at most 2 T1 like for any physical qubit (a larger T2 would give a negative λ)
+    */
+    pub fn new_thermal_relaxation(t1: T, t2: T, time: T) -> Result<Self, QuantumError> {
+        // Written so that NaN fails every check
+        if !(t1 > T::zero() && t2 > T::zero()) {
+            return Err(QuantumError::InvalidRelaxationTimes(format!("T1 = {} and T2 = {} have to be positive", t1, t2)))
+        }
+        if matches!(time.partial_cmp(&T::zero()), None | Some(std::cmp::Ordering::Less)) {
+            return Err(QuantumError::InvalidRelaxationTimes(format!("gate time {} is negative", time)))
+        }
+        if t2 > real::<T>(2.) * t1 {
+            return Err(QuantumError::InvalidRelaxationTimes(format!("T2 = {} is larger than 2 T1 = {}", t2, real::<T>(2.) * t1)))
+        }
+        let gamma: T = T::one() - (-time / t1).exp();
+        let lambda: T = T::one() - (time / t1 - real::<T>(2.) * time / t2).exp();
+        let damping: Self = Self::new_amplitude_damping(gamma)?;
+        let dephasing: Self = Self::new_phase_damping(lambda)?;
+        // Kraus operators of the two channels in a row are the products P_j A_i
+        let mut operators: Vec<Matrix<T>> = vec![];
+        for phase in dephasing.operators.iter() {
+            for amplitude in damping.operators.iter() {
+                operators.push(phase.clone().matrix_multiplication(amplitude.clone()));
+            }
+        }
+        Ok(Self {operators})
+    }
+
+    // Number of qubits the channel acts on, every operator is 2^k x 2^k
+    pub fn num_qubits(&self) -> usize {
+        self.operators[0].rows.len().trailing_zeros() as usize
+    }
+
+    // Rows of each Kraus operator
+    pub fn operators(&self) -> Vec<&[Vec<Complex<T>>]> {
+        self.operators.iter().map(|operator| operator.rows.as_slice()).collect()
+    }
+
+    /**
+    Probability and Pauli product (I, X, Y, Z as 0 to 3 for each qubit) of every operator when each one is a multiple of a Pauli product,
+    like for bit flip, phase flip and depolarizing channels, None otherwise
+    */
+    pub(crate) fn pauli_decomposition(&self) -> Option<Vec<(T, Vec<usize>)>> {
+        let num_qubits: usize = self.num_qubits();
+        let size: usize = 1 << num_qubits;
+        let tolerance: T = real(1e-6);
+        self.operators.iter().map(|operator| {
+            (0..1 << (2 * num_qubits)).find_map(|index: usize| {
+                let paulis: Vec<usize> = (0..num_qubits).map(|qubit| (index >> (2 * (num_qubits - 1 - qubit))) & 3).collect();
+                let product: Matrix<T> = paulis.iter().fold(Matrix::new_identity(1), |mut product, &pauli_index| product.tensor_product(&pauli(pauli_index)));
+                // K = c P with c = tr(P^† K) / 2^k
+                let trace: Complex<T> = (0..size).fold(Complex::zero(), |total: Complex<T>, i| {
+                    (0..size).fold(total, |total, j| total + product.rows[j][i].conj() * operator.rows[j][i])
+                });
+                let c: Complex<T> = trace / Complex::from(real::<T>(size as f64));
+                let matches: bool = (0..size).all(|i| (0..size).all(|j| (operator.rows[i][j] - product.rows[i][j] * c).norm_sqr() <= tolerance * tolerance));
+                if matches { Some((c.norm_sqr(), paulis)) } else { None }
+            })
+        }).collect()
+    }
+
+    pub(crate) fn matrices(&self) -> &[Matrix<T>] {
+        &self.operators
+    }
+
+    // The operators have to match the number of targets, which have to be distinct qubits of the register
+    pub(crate) fn check_placement(&self, targets: &[usize], num_qubits: usize) -> Result<(), QuantumError> {
+        if self.num_qubits() != targets.len() {
+            return Err(QuantumError::DimensionMismatch {expected: 1 << targets.len(), found: 1 << self.num_qubits()})
+        }
+        let targets: Vec<i32> = targets.iter().map(|&qubit| qubit as i32).collect();
+        Gate::<T>::check_distinct_qubits(&[], &targets, num_qubits as i32)
+    }
+
+    /**
+    One Monte-Carlo step on a pure state: K_i is drawn with probability ||K_i |ψ>||^2 and the state becomes K_i |ψ> / ||K_i |ψ>||
+    The probabilities are computed one operator at a time in a single scratch copy of the state, then only K_i is applied to the state
+    */
+    pub(crate) fn apply_trajectory<R: Rng + ?Sized>(&self, state: &mut [Complex<T>], num_qubits: usize, targets: &[usize], rng: &mut R) {
+        let mut branch: Vec<Complex<T>> = state.to_vec();
+        let probabilities: Vec<T> = self.operators.iter().map(|operator| {
+            branch.copy_from_slice(state);
+            apply_local_matrix(&mut branch, num_qubits, operator, targets, &[]);
+            branch.iter().fold(T::zero(), |total, amplitude| total + amplitude.norm_sqr())
+        }).collect();
+        let outcome: usize = draw_outcome(&probabilities, rng);
+        apply_local_matrix(state, num_qubits, &self.operators[outcome], targets, &[]);
+        let scale: Complex<T> = Complex::from(T::one() / probabilities[outcome].sqrt());
+        for amplitude in state.iter_mut() {
+            *amplitude *= scale;
+        }
+    }
+
+    // Channel applying the identity with probability 1 - p and each of the errors with probability p / (number of errors)
+    fn new_pauli_mixture(probability: T, errors: &[Matrix<T>]) -> Self {
+        let size: usize = errors[0].rows.len();
+        let mut identity: Matrix<T> = Matrix::new_identity(size);
+        identity.scalar_multiplication(Complex::from((T::one() - probability).sqrt()));
+        let mut operators: Vec<Matrix<T>> = vec![identity];
+        for error in errors.iter() {
+            let mut operator: Matrix<T> = error.clone();
+            operator.scalar_multiplication(Complex::from((probability / real(errors.len() as f64)).sqrt()));
+            operators.push(operator);
+        }
+        Self {operators}
+    }
+
+    // Σ K_i^† K_i = I, with every entry allowed to be off by at most tolerance
+    fn is_trace_preserving(&self, tolerance: T) -> bool {
+        let size: usize = self.operators[0].rows.len();
+        (0..size).all(|i| (0..size).all(|j| {
+            let entry: Complex<T> = self.operators.iter().fold(Complex::zero(), |total, operator| {
+                (0..size).fold(total, |total, k| total + operator.rows[k][i].conj() * operator.rows[k][j])
+            });
+            let expected: Complex<T> = if i == j { Complex::one() } else { Complex::zero() };
+            (entry - expected).norm_sqr() <= tolerance * tolerance
+        }))
+    }
+}
+
+impl<T: Precision> Clone for KrausChannel<T> {
+    fn clone(&self) -> Self {
+        Self {operators: self.operators.iter().map(|operator| operator.clone()).collect()}
+    }
+}
+
+// Which gates a noise channel follows
+#[derive(Clone)]
+enum GateSelector {
+    // Gates with one of these names, controls included (ex "cx", "h", "rz")
+    Named(Vec<String>),
+    // Every gate acting on a single qubit
+    SingleQubit,
+}
+
+/**
+Noise of a device, applied automatically while a circuit runs (see QuantumCircuit::set_noise_model)
+Gate errors are channels applied right after every matching gate, in the order they were added
+A channel on one qubit is applied to each qubit of the gate, a channel on k qubits to all of the gate's qubits together (controls first)
+The readout error flips every measured classical bit with a given probability, the state itself collapses onto the true outcome
+*/
+#[derive(Clone)]
+pub struct NoiseModel<T: Precision = f32> {
+    gate_errors: Vec<(GateSelector, KrausChannel<T>)>,
+    readout_error: T,
+}
+
+impl<T: Precision> NoiseModel<T> {
+    // Noise model without any errors
+    pub fn new() -> Self {
+        Self {gate_errors: vec![], readout_error: T::zero()}
+    }
+
+    /**
+    Apply channel after every gate with one of the given names (ex model.add_gate_error(KrausChannel::new_depolarizing(0.01, 2)?, &["cx"]))
+    */
+    pub fn add_gate_error(&mut self, channel: KrausChannel<T>, gates: &[&str]) -> &mut Self {
+        self.gate_errors.push((GateSelector::Named(gates.iter().map(|gate| gate.to_string()).collect()), channel));
+        self
+    }
+
+    /**
+    Apply channel after every gate acting on a single qubit (ex T1 / T2 relaxation from KrausChannel::new_thermal_relaxation)
+    */
+    pub fn add_single_qubit_error(&mut self, channel: KrausChannel<T>) -> &mut Self {
+        self.gate_errors.push((GateSelector::SingleQubit, channel));
+        self
+    }
+
+    // Flip every measured bit with the given probability
+    pub fn set_readout_error(&mut self, probability: T) -> Result<&mut Self, QuantumError> {
+        check_probability(probability)?;
+        self.readout_error = probability;
+        Ok(self)
+    }
+
+    pub fn readout_error(&self) -> T {
+        self.readout_error
+    }
+
+    /**
+    Channels to apply after a gate instruction, each with the qubits it acts on
+    Instructions that are not gates (measure, reset, barrier and noise channels themselves) get no noise
+    */
+    pub(crate) fn gate_errors(&self, instruction: &Instruction<T>) -> Vec<(&KrausChannel<T>, Vec<usize>)> {
+        if !instruction.is_unitary() {
+            return vec![]
+        }
+        let qubits: Vec<usize> = instruction.qubits();
+        let name: String = instruction.name();
+        let mut errors: Vec<(&KrausChannel<T>, Vec<usize>)> = vec![];
+        for (selector, channel) in self.gate_errors.iter() {
+            let matches: bool = match selector {
+                GateSelector::Named(names) => names.contains(&name),
+                GateSelector::SingleQubit => qubits.len() == 1,
+            };
+            if !matches {
+                continue
+            }
+            if channel.num_qubits() == 1 {
+                errors.extend(qubits.iter().map(|&qubit| (channel, vec![qubit])));
+            } else {
+                errors.push((channel, qubits.clone()));
+            }
+        }
+        errors
+    }
+
+    // Measured bit after the readout error
+    pub(crate) fn read<R: Rng + ?Sized>(&self, bit: i32, rng: &mut R) -> i32 {
+        if self.readout_error > T::zero() && real::<T>(rng.gen_range(0. .. 1.)) < self.readout_error {
+            1 - bit
+        } else {
+            bit
+        }
+    }
+}
+
+impl<T: Precision> Default for NoiseModel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Probabilities and damping rates have to be between 0 and 1, written so that NaN fails as well
+pub(crate) fn check_probability<T: Precision>(probability: T) -> Result<(), QuantumError> {
+    if !(probability >= T::zero() && probability <= T::one()) {
+        return Err(QuantumError::InvalidProbability(probability.to_string()))
+    }
+    Ok(())
+}
+
+// I, X, Y, Z for index 0 to 3
+fn pauli<T: Precision>(index: usize) -> Matrix<T> {
+    let zero: Complex<T> = Complex::zero();
+    let one: Complex<T> = Complex::one();
+    let i: Complex<T> = Complex::i();
+    Matrix::new(match index {
+        0 => vec![vec![one, zero], vec![zero, one]],
+        1 => vec![vec![zero, one], vec![one, zero]],
+        2 => vec![vec![zero, -i], vec![i, zero]],
+        _ => vec![vec![one, zero], vec![zero, -one]],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DensityMatrix::DensityMatrix;
+    use crate::QuantumCircuit::QuantumCircuit;
+    use crate::QuantumRegister::QuantumRegister;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_channels_are_trace_preserving() {
+        let channels: Vec<KrausChannel<f64>> = vec![
+            KrausChannel::new_bit_flip(0.1).unwrap(),
+            KrausChannel::new_phase_flip(0.3).unwrap(),
+            KrausChannel::new_depolarizing(0.2, 1).unwrap(),
+            KrausChannel::new_depolarizing(0.2, 2).unwrap(),
+            KrausChannel::new_amplitude_damping(0.4).unwrap(),
+            KrausChannel::new_phase_damping(0.6).unwrap(),
+        ];
+        for channel in channels.iter() {
+            assert!(channel.is_trace_preserving(1e-12));
+        }
+        assert_eq!(16, channels[3].operators().len());
+        assert_eq!(2, channels[3].num_qubits());
+
+        assert_eq!(Some(QuantumError::InvalidProbability("1.5".to_string())), KrausChannel::<f64>::new_bit_flip(1.5).err());
+        assert_eq!(Some(QuantumError::InvalidProbability("NaN".to_string())), KrausChannel::<f64>::new_amplitude_damping(f64::NAN).err());
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 2, found: 1}), KrausChannel::<f64>::new_depolarizing(0.1, 0).err());
+        let c = |re: f64| Complex::new(re, 0.);
+        assert_eq!(Some(QuantumError::NotTracePreserving), KrausChannel::new(vec![vec![vec![c(1.), c(0.)], vec![c(0.), c(0.)]]]).err());
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 2, found: 1}), KrausChannel::new(vec![vec![vec![c(1.), c(0.)], vec![c(0.)]]]).err());
+    }
+
+    #[test]
+    fn test_channels_on_density_matrix() {
+        // Depolarizing with p = 3/4 and amplitude damping with γ = 1 wipe out any state
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).cx(0, 1);
+        circuit.channel(KrausChannel::new_depolarizing(0.75, 1).unwrap(), &[0]);
+        circuit.channel(KrausChannel::new_amplitude_damping(1.).unwrap(), &[1]);
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 4);
+        circuit.run(&mut rho).unwrap();
+        let expected: DensityMatrix<f64> = DensityMatrix::new_mixed(&[(1., QuantumRegister::new_from_int(0, 4)), (1., QuantumRegister::new_from_int(2, 4))]).unwrap();
+        for (a, b) in expected.rho.iter().zip(rho.rho.iter()) {
+            assert!((a - b).norm_sqr() < 1e-20);
+        }
+
+        // Phase damping scales the coherence of |+> by sqrt(1 - λ), phase flip by 1 - 2p
+        let mut plus: DensityMatrix<f64> = DensityMatrix::new_from_register(&QuantumRegister::new_from_vec(vec![Complex::new(1., 0.), Complex::new(1., 0.)]));
+        plus.apply_channel(&KrausChannel::new_phase_damping(0.36).unwrap(), &[0]).unwrap();
+        assert!((plus.rho[(0, 1)].re - 0.4).abs() < 1e-12);
+        plus.apply_channel(&KrausChannel::new_phase_flip(0.25).unwrap(), &[0]).unwrap();
+        assert!((plus.rho[(0, 1)].re - 0.2).abs() < 1e-12);
+        assert!((plus.trace() - 1.).abs() < 1e-12);
+
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 2, found: 4}), plus.apply_channel(&KrausChannel::new_depolarizing(0.1, 2).unwrap(), &[0]));
+    }
+
+    #[test]
+    fn test_thermal_relaxation() {
+        let channel: KrausChannel<f64> = KrausChannel::new_thermal_relaxation(50., 30., 10.).unwrap();
+        assert!(channel.is_trace_preserving(1e-12));
+        let mut one: DensityMatrix<f64> = DensityMatrix::new_from_int(1, 2);
+        one.apply_channel(&channel, &[0]).unwrap();
+        assert!((one.probabilities()[1] - (-0.2f64).exp()).abs() < 1e-12);
+
+        let mut plus: DensityMatrix<f64> = DensityMatrix::new_from_register(&QuantumRegister::new_from_vec(vec![Complex::new(1., 0.), Complex::new(1., 0.)]));
+        plus.apply_channel(&channel, &[0]).unwrap();
+        assert!((plus.rho[(0, 1)].re - 0.5 * (-1f64 / 3.).exp()).abs() < 1e-12);
+
+        // T2 = 2 T1 is the limit without pure dephasing
+        assert!(KrausChannel::<f64>::new_thermal_relaxation(10., 20., 1.).is_ok());
+        assert_eq!(Some(QuantumError::InvalidRelaxationTimes("T2 = 30 is larger than 2 T1 = 20".to_string())), KrausChannel::<f64>::new_thermal_relaxation(10., 30., 1.).err());
+        assert_eq!(Some(QuantumError::InvalidRelaxationTimes("T1 = 0 and T2 = 0 have to be positive".to_string())), KrausChannel::<f64>::new_thermal_relaxation(0., 0., 0.).err());
+        assert!(matches!(KrausChannel::<f64>::new_thermal_relaxation(f64::NAN, 10., 1.), Err(QuantumError::InvalidRelaxationTimes(_))));
+        assert!(matches!(KrausChannel::<f64>::new_thermal_relaxation(50., 30., -1.), Err(QuantumError::InvalidRelaxationTimes(_))));
+    }
+
+    #[test]
+    fn test_noise_model() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).cx(0, 1);
+        let mut expected: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        expected.h(0).channel(KrausChannel::new_amplitude_damping(0.1).unwrap(), &[0]);
+        expected.cx(0, 1).channel(KrausChannel::new_depolarizing(0.05, 2).unwrap(), &[0, 1]);
+        expected.channel(KrausChannel::new_phase_flip(0.2).unwrap(), &[0]).channel(KrausChannel::new_phase_flip(0.2).unwrap(), &[1]);
+
+        let mut noise_model: NoiseModel<f64> = NoiseModel::new();
+        noise_model.add_single_qubit_error(KrausChannel::new_amplitude_damping(0.1).unwrap());
+        noise_model.add_gate_error(KrausChannel::new_depolarizing(0.05, 2).unwrap(), &["cx"]);
+        noise_model.add_gate_error(KrausChannel::new_phase_flip(0.2).unwrap(), &["cx", "ccx"]);
+        circuit.set_noise_model(Some(noise_model));
+
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 4);
+        let mut expected_rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 4);
+        circuit.run(&mut rho).unwrap();
+        expected.run(&mut expected_rho).unwrap();
+        for (a, b) in expected_rho.rho.iter().zip(rho.rho.iter()) {
+            assert!((a - b).norm_sqr() < 1e-20);
+        }
+
+        // Without a noise model the same circuit is ideal again
+        circuit.set_noise_model(None);
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 4);
+        circuit.run(&mut rho).unwrap();
+        assert!((rho.purity() - 1.).abs() < 1e-12);
+
+        let mut wrong: NoiseModel<f64> = NoiseModel::new();
+        wrong.add_gate_error(KrausChannel::new_depolarizing(0.05, 2).unwrap(), &["h"]);
+        circuit.set_noise_model(Some(wrong));
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 2, found: 4}), circuit.run(&mut DensityMatrix::new_from_int(0, 4)));
+    }
+
+    #[test]
+    fn test_readout_error() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 2).unwrap();
+        circuit.x(0).measure(0, 0).measure(1, 1);
+        let mut noise_model: NoiseModel<f64> = NoiseModel::new();
+        noise_model.set_readout_error(1.).unwrap();
+        circuit.set_noise_model(Some(noise_model.clone()));
+        assert_eq!(vec![0, 1], circuit.run(&mut QuantumRegister::new_from_int(0, 4)).unwrap());
+
+        // Sweep the readout error on the same circuit
+        let mut rng: StdRng = StdRng::seed_from_u64(3);
+        for probability in [0.1, 0.3] {
+            noise_model.set_readout_error(probability).unwrap();
+            circuit.set_noise_model(Some(noise_model.clone()));
+            let shots: usize = 3000;
+            let flips: usize = (0..shots).map(|_| circuit.run_with_rng(&mut DensityMatrix::new_from_int(0, 4), &mut rng).unwrap()[1] as usize).sum();
+            assert!((flips as f64 / shots as f64 - probability).abs() < 0.03);
+        }
+        assert_eq!(Some(QuantumError::InvalidProbability("-0.1".to_string())), noise_model.set_readout_error(-0.1).err());
+        assert_eq!(Some(QuantumError::InvalidProbability("NaN".to_string())), noise_model.set_readout_error(f64::NAN).err());
+    }
+
+    #[test]
+    fn test_trajectories_match_density_matrix() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).channel(KrausChannel::new_amplitude_damping(0.3).unwrap(), &[0]).cx(0, 1).channel(KrausChannel::new_bit_flip(0.2).unwrap(), &[1]);
+
+        let mut rho: DensityMatrix<f64> = DensityMatrix::new_from_int(0, 4);
+        circuit.run(&mut rho).unwrap();
+
+        let mut rng: StdRng = StdRng::seed_from_u64(7);
+        let shots: usize = 4000;
+        let mut average: Vec<f64> = vec![0.; 4];
+        for _ in 0..shots {
+            let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 4);
+            circuit.run_with_rng(&mut register, &mut rng).unwrap();
+            assert!((register.state.norm() - 1.).abs() < 1e-9);
+            for (total, probability) in average.iter_mut().zip(register.probabilities()) {
+                *total += probability / shots as f64;
+            }
+        }
+        for (expected, found) in rho.probabilities().into_iter().zip(average) {
+            assert!((expected - found).abs() < 0.03, "expected {} found {}", expected, found);
+        }
+    }
+}
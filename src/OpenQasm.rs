@@ -0,0 +1,1199 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use crate::Instruction::{Condition, Control, Instruction, Operation};
+use crate::Precision::{Precision, real};
+use crate::QuantumCircuit::QuantumCircuit;
+use crate::QuantumError::QuantumError;
+
+/**
+Gates of qelib1.inc that have no single operation of their own, written in OpenQASM using the ones that do
+They are read when a program includes qelib1.inc, so programs can then use them like any other gate
+*/
+const QELIB1_DEFINITIONS: &str = "
+gate rzz(theta) a, b { cx a, b; u1(theta) b; cx a, b; }
+gate rxx(theta) a, b { u3(pi/2, theta, 0) a; h b; cx a, b; u1(-theta) b; cx a, b; h b; u2(-pi, pi-theta) a; }
+gate cu(theta, phi, lambda, gamma) c, t { p(gamma) c; p((lambda+phi)/2) c; p((lambda-phi)/2) t; cx c, t; u(-theta/2, 0, -(phi+lambda)/2) t; cx c, t; u(theta/2, phi, 0) t; }
+";
+
+// Multi character symbols come first so "->" is not read as "-"
+const SYMBOLS: [&str; 15] = ["->", "==", ";", ",", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/", "^"];
+
+const FUNCTIONS: [&str; 6] = ["sin", "cos", "tan", "exp", "ln", "sqrt"];
+
+/**
+Read an OpenQASM 2.0 program into a circuit
+Quantum registers are laid out one after the other in the order they are declared (the first qubit of the first qreg is qubit 0),
+classical registers become classical registers of the circuit with the same names
+Supports qreg, creg, include "qelib1.inc", the gates of qelib1.inc, user gate definitions, measure, reset, barrier and if
+Like in the OpenQASM 2.0 specification only U and CX exist until "qelib1.inc" is included
+Anything else (ex opaque gates or other include files) is an error pointing at the line and column where it was found
+*/
+pub fn parse<T: Precision>(source: &str) -> Result<QuantumCircuit<T>, QuantumError> {
+    let mut parser: Parser<T> = Parser::new(tokenize(source)?);
+    parser.program()?;
+
+    let num_qubits: usize = parser.qregs.iter().map(|register| register.size).sum();
+    let mut circuit: QuantumCircuit<T> = QuantumCircuit::new_with_qubits(num_qubits as i32);
+    for register in parser.cregs.iter() {
+        circuit.add_classical_register(&register.name, register.size)?;
+    }
+    for instruction in parser.instructions {
+        circuit.add_instruction(instruction);
+    }
+    Ok(circuit)
+}
+
+fn parse_error(token: &Token, message: String) -> QuantumError {
+    QuantumError::QasmParse {line: token.line, column: token.column, message}
+}
+
+/**
+Values of the parameters of a gate call, an expression like 0/0 or ln(-1) that is not a finite number is an error at the gate
+*/
+fn evaluate(exprs: &[Expr], values: &HashMap<String, f64>, name: &str, token: &Token) -> Result<Vec<f64>, QuantumError> {
+    exprs.iter().map(|expr| {
+        let value: f64 = expr.eval(values);
+        if !value.is_finite() {
+            return Err(parse_error(token, format!("parameter of {} evaluates to {}", name, value)))
+        }
+        Ok(value)
+    }).collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Int(u64),
+    Real(f64),
+    Str(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl TokenKind {
+    // How the token is named in error messages
+    fn describe(&self) -> String {
+        match self {
+            TokenKind::Ident(name) => format!("'{}'", name),
+            TokenKind::Int(value) => format!("'{}'", value),
+            TokenKind::Real(value) => format!("'{}'", value),
+            TokenKind::Str(text) => format!("\"{}\"", text),
+            TokenKind::Symbol(symbol) => format!("'{}'", symbol),
+            TokenKind::End => "end of file".to_string(),
+        }
+    }
+}
+
+// Token with the line and column (both starting at 1) of its first character
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, QuantumError> {
+    let chars: Vec<char> = source.chars().collect();
+    let is_digit = |i: usize| i < chars.len() && chars[i].is_ascii_digit();
+    let mut tokens: Vec<Token> = vec![];
+    let (mut i, mut line, mut column): (usize, usize, usize) = (0, 1, 1);
+    while i < chars.len() {
+        let c: char = chars[i];
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            column = 1;
+            continue
+        }
+        if c.is_whitespace() {
+            i += 1;
+            column += 1;
+            continue
+        }
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue
+        }
+
+        let start: usize = i;
+        let here: Token = Token {kind: TokenKind::End, line, column};
+        let kind: TokenKind = if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            TokenKind::Ident(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() || (c == '.' && is_digit(i + 1)) {
+            let mut is_real: bool = false;
+            while is_digit(i) {
+                i += 1;
+            }
+            if i < chars.len() && chars[i] == '.' {
+                is_real = true;
+                i += 1;
+                while is_digit(i) {
+                    i += 1;
+                }
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j: usize = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if is_digit(j) {
+                    is_real = true;
+                    i = j;
+                    while is_digit(i) {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            if is_real {
+                TokenKind::Real(text.parse().map_err(|_| parse_error(&here, format!("invalid number {}", text)))?)
+            } else {
+                TokenKind::Int(text.parse().map_err(|_| parse_error(&here, format!("integer {} is too large", text)))?)
+            }
+        } else if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                i += 1;
+            }
+            if i >= chars.len() || chars[i] != '"' {
+                return Err(parse_error(&here, "unterminated string".to_string()))
+            }
+            i += 1;
+            TokenKind::Str(chars[start + 1..i - 1].iter().collect())
+        } else {
+            let symbol: &'static str = SYMBOLS.iter().copied()
+                .find(|symbol| chars[i..].iter().copied().take(symbol.len()).eq(symbol.chars()))
+                .ok_or_else(|| parse_error(&here, format!("unexpected character '{}'", c)))?;
+            i += symbol.len();
+            TokenKind::Symbol(symbol)
+        };
+        column += i - start;
+        tokens.push(Token {kind, ..here});
+    }
+    tokens.push(Token {kind: TokenKind::End, line, column});
+    Ok(tokens)
+}
+
+/**
+Parameter expression, kept unevaluated inside gate definitions until the gate is used with actual values
+*/
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(f64),
+    Param(String),
+    Neg(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Function(String, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, params: &HashMap<String, f64>) -> f64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Param(name) => params[name],
+            Expr::Neg(expr) => -expr.eval(params),
+            Expr::Binary(op, left, right) => {
+                let (left, right): (f64, f64) = (left.eval(params), right.eval(params));
+                match *op {
+                    "+" => left + right,
+                    "-" => left - right,
+                    "*" => left * right,
+                    "/" => left / right,
+                    _ => left.powf(right),
+                }
+            }
+            Expr::Function(name, expr) => {
+                let value: f64 = expr.eval(params);
+                match name.as_str() {
+                    "sin" => value.sin(),
+                    "cos" => value.cos(),
+                    "tan" => value.tan(),
+                    "exp" => value.exp(),
+                    "ln" => value.ln(),
+                    _ => value.sqrt(),
+                }
+            }
+        }
+    }
+}
+
+// One gate used inside a gate definition, acting on the definition's own qubit arguments
+#[derive(Clone)]
+struct GateCall {
+    name: String,
+    params: Vec<Expr>,
+    qubits: Vec<String>,
+}
+
+#[derive(Clone)]
+struct GateDefinition {
+    params: Vec<String>,
+    qubits: Vec<String>,
+    body: Vec<GateCall>,
+}
+
+// Register declared by the program, offset is the index of its first (qu)bit
+struct Register {
+    name: String,
+    offset: usize,
+    size: usize,
+}
+
+/**
+Gates of qelib1.inc (and the built in U and CX) that map onto a single operation
+Return the operation, how many of the gate's qubits are controls and how many parameters the gate takes in OpenQASM
+*/
+fn builtin_gate<T: Precision>(name: &str) -> Option<(Operation<T>, usize, usize)> {
+    let gate: (Operation<T>, usize, usize) = match name {
+        "U" | "u3" | "u" => (Operation::U, 0, 3),
+        "u2" => (Operation::U, 0, 2),
+        "u1" | "p" => (Operation::Phase, 0, 1),
+        "u0" => (Operation::I, 0, 1),
+        "id" => (Operation::I, 0, 0),
+        "x" => (Operation::X, 0, 0),
+        "y" => (Operation::Y, 0, 0),
+        "z" => (Operation::Z, 0, 0),
+        "h" => (Operation::H, 0, 0),
+        "s" => (Operation::S, 0, 0),
+        "sdg" => (Operation::Sdg, 0, 0),
+        "t" => (Operation::T, 0, 0),
+        "tdg" => (Operation::Tdg, 0, 0),
+        "sx" => (Operation::Sx, 0, 0),
+        "sxdg" => (Operation::Sxdg, 0, 0),
+        "rx" => (Operation::Rx, 0, 1),
+        "ry" => (Operation::Ry, 0, 1),
+        "rz" => (Operation::Rz, 0, 1),
+        "swap" => (Operation::Swap, 0, 0),
+        "CX" | "cx" => (Operation::X, 1, 0),
+        "cy" => (Operation::Y, 1, 0),
+        "cz" => (Operation::Z, 1, 0),
+        "ch" => (Operation::H, 1, 0),
+        "csx" => (Operation::Sx, 1, 0),
+        "crx" => (Operation::Rx, 1, 1),
+        "cry" => (Operation::Ry, 1, 1),
+        "crz" => (Operation::Rz, 1, 1),
+        "cu1" | "cp" => (Operation::Phase, 1, 1),
+        "cu3" => (Operation::U, 1, 3),
+        "ccx" => (Operation::X, 2, 0),
+        "cswap" => (Operation::Swap, 1, 0),
+        "c3x" => (Operation::X, 3, 0),
+        "c4x" => (Operation::X, 4, 0),
+        _ => return None,
+    };
+    Some(gate)
+}
+
+struct Parser<T: Precision> {
+    tokens: Vec<Token>,
+    position: usize,
+    qregs: Vec<Register>,
+    cregs: Vec<Register>,
+    gates: HashMap<String, GateDefinition>,
+    instructions: Vec<Instruction<T>>,
+    // Whether qelib1.inc was included, before that only the built in U and CX are known
+    qelib1: bool,
+}
+
+impl<T: Precision> Parser<T> {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {tokens, position: 0, qregs: vec![], cregs: vec![], gates: HashMap::new(), instructions: vec![], qelib1: false}
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    // Take the next token, the end token is never passed
+    fn next(&mut self) -> Token {
+        let token: Token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        self.peek().kind == TokenKind::Symbol(symbol_of(symbol))
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<Token, QuantumError> {
+        let token: Token = self.next();
+        if token.kind != TokenKind::Symbol(symbol_of(symbol)) {
+            return Err(parse_error(&token, format!("expected '{}', found {}", symbol, token.kind.describe())))
+        }
+        Ok(token)
+    }
+
+    fn expect_ident(&mut self) -> Result<(String, Token), QuantumError> {
+        let token: Token = self.next();
+        match &token.kind {
+            TokenKind::Ident(name) => Ok((name.clone(), token)),
+            kind => Err(parse_error(&token, format!("expected an identifier, found {}", kind.describe()))),
+        }
+    }
+
+    fn expect_int(&mut self) -> Result<(u64, Token), QuantumError> {
+        let token: Token = self.next();
+        match token.kind {
+            TokenKind::Int(value) => Ok((value, token)),
+            ref kind => Err(parse_error(&token, format!("expected an integer, found {}", kind.describe()))),
+        }
+    }
+
+    // Comma separated identifiers, at least one
+    fn ident_list(&mut self) -> Result<Vec<(String, Token)>, QuantumError> {
+        let mut idents: Vec<(String, Token)> = vec![self.expect_ident()?];
+        while self.peek_symbol(",") {
+            self.next();
+            idents.push(self.expect_ident()?);
+        }
+        Ok(idents)
+    }
+
+    // Gate mapping onto a single operation that is available at this point of the program
+    fn builtin_gate(&self, name: &str) -> Option<(Operation<T>, usize, usize)> {
+        builtin_gate(name).filter(|_| self.qelib1 || name == "U" || name == "CX")
+    }
+
+    // Number of parameters and qubits a gate takes, None for unknown gates
+    fn signature(&self, name: &str) -> Option<(usize, usize)> {
+        if let Some((operation, num_controls, num_params)) = self.builtin_gate(name) {
+            let num_targets: usize = if matches!(operation, Operation::Swap) { 2 } else { 1 };
+            return Some((num_params, num_controls + num_targets))
+        }
+        self.gates.get(name).map(|definition| (definition.params.len(), definition.qubits.len()))
+    }
+
+    fn program(&mut self) -> Result<(), QuantumError> {
+        if self.peek().kind == TokenKind::Ident("OPENQASM".to_string()) {
+            self.next();
+            let token: Token = self.next();
+            let supported: bool = match token.kind {
+                TokenKind::Real(version) => version == 2.,
+                TokenKind::Int(version) => version == 2,
+                _ => false,
+            };
+            if !supported {
+                return Err(parse_error(&token, format!("unsupported OpenQASM version {}, only 2.0 is supported", token.kind.describe())))
+            }
+            self.expect_symbol(";")?;
+        }
+        while self.peek().kind != TokenKind::End {
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    // Make the gates of qelib1.inc available, reading the definitions of the ones without an operation of their own
+    fn include_qelib1(&mut self) -> Result<(), QuantumError> {
+        if self.qelib1 {
+            return Ok(())
+        }
+        self.qelib1 = true;
+        let tokens: Vec<Token> = std::mem::replace(&mut self.tokens, tokenize(QELIB1_DEFINITIONS)?);
+        let position: usize = std::mem::replace(&mut self.position, 0);
+        while self.peek().kind != TokenKind::End {
+            self.statement()?;
+        }
+        self.tokens = tokens;
+        self.position = position;
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), QuantumError> {
+        let token: Token = self.peek().clone();
+        let word: String = match &token.kind {
+            TokenKind::Ident(word) => word.clone(),
+            kind => return Err(parse_error(&token, format!("expected a statement, found {}", kind.describe()))),
+        };
+        match word.as_str() {
+            "OPENQASM" => Err(parse_error(&token, "the OPENQASM header must be the first statement".to_string())),
+            "include" => {
+                self.next();
+                let file: Token = self.next();
+                if file.kind != TokenKind::Str("qelib1.inc".to_string()) {
+                    return Err(parse_error(&file, format!("only \"qelib1.inc\" can be included, found {}", file.kind.describe())))
+                }
+                self.expect_symbol(";")?;
+                self.include_qelib1()
+            }
+            "qreg" | "creg" => {
+                self.next();
+                self.declaration(word == "qreg")
+            }
+            "gate" => {
+                self.next();
+                self.gate_definition()
+            }
+            "opaque" => Err(parse_error(&token, "opaque gates are not supported".to_string())),
+            "barrier" => {
+                self.next();
+                let mut qubits: Vec<usize> = vec![];
+                for argument in self.arguments()? {
+                    qubits.extend(argument);
+                }
+                self.expect_symbol(";")?;
+                self.instructions.push(Instruction::new(Operation::Barrier, qubits, vec![], vec![]));
+                Ok(())
+            }
+            "if" => {
+                self.next();
+                self.expect_symbol("(")?;
+                let (register, register_token): (String, Token) = self.expect_ident()?;
+                if !self.cregs.iter().any(|creg| creg.name == register) {
+                    return Err(parse_error(&register_token, format!("unknown classical register {}", register)))
+                }
+                self.expect_symbol("==")?;
+                let (value, _): (u64, Token) = self.expect_int()?;
+                self.expect_symbol(")")?;
+                self.quantum_operation(Some(Condition {register, value}))
+            }
+            _ => self.quantum_operation(None),
+        }
+    }
+
+    fn declaration(&mut self, quantum: bool) -> Result<(), QuantumError> {
+        let (name, name_token): (String, Token) = self.expect_ident()?;
+        if self.qregs.iter().chain(self.cregs.iter()).any(|register| register.name == name) {
+            return Err(parse_error(&name_token, format!("register {} is already declared", name)))
+        }
+        self.expect_symbol("[")?;
+        let (size, size_token): (u64, Token) = self.expect_int()?;
+        if size == 0 {
+            return Err(parse_error(&size_token, "register size must be at least 1".to_string()))
+        }
+        self.expect_symbol("]")?;
+        self.expect_symbol(";")?;
+        let registers: &mut Vec<Register> = if quantum { &mut self.qregs } else { &mut self.cregs };
+        let offset: usize = registers.iter().map(|register| register.size).sum();
+        registers.push(Register {name, offset, size: size as usize});
+        Ok(())
+    }
+
+    fn gate_definition(&mut self) -> Result<(), QuantumError> {
+        let (name, name_token): (String, Token) = self.expect_ident()?;
+        if self.signature(&name).is_some() {
+            return Err(parse_error(&name_token, format!("gate {} is already defined", name)))
+        }
+        let mut params: Vec<String> = vec![];
+        if self.peek_symbol("(") {
+            self.next();
+            if !self.peek_symbol(")") {
+                for (param, token) in self.ident_list()? {
+                    if params.contains(&param) {
+                        return Err(parse_error(&token, format!("parameter {} is declared twice", param)))
+                    }
+                    params.push(param);
+                }
+            }
+            self.expect_symbol(")")?;
+        }
+        let mut qubits: Vec<String> = vec![];
+        for (qubit, token) in self.ident_list()? {
+            if qubits.contains(&qubit) || params.contains(&qubit) {
+                return Err(parse_error(&token, format!("argument {} is declared twice", qubit)))
+            }
+            qubits.push(qubit);
+        }
+
+        self.expect_symbol("{")?;
+        let mut body: Vec<GateCall> = vec![];
+        while !self.peek_symbol("}") {
+            let (call, call_token): (String, Token) = self.expect_ident()?;
+            let signature: Option<(usize, usize)> = self.signature(&call);
+            if signature.is_none() && call != "barrier" {
+                return Err(parse_error(&call_token, format!("unknown gate {}", call)))
+            }
+            let mut call_params: Vec<Expr> = vec![];
+            if self.peek_symbol("(") && call != "barrier" {
+                call_params = self.expression_list(&params)?;
+            }
+            let mut call_qubits: Vec<String> = vec![];
+            for (qubit, token) in self.ident_list()? {
+                if !qubits.contains(&qubit) {
+                    return Err(parse_error(&token, format!("{} is not an argument of gate {}", qubit, name)))
+                }
+                call_qubits.push(qubit);
+            }
+            if let Some((num_params, num_qubits)) = signature {
+                self.check_signature(&call, &call_token, (num_params, num_qubits), call_params.len(), call_qubits.len())?;
+            }
+            self.expect_symbol(";")?;
+            body.push(GateCall {name: call, params: call_params, qubits: call_qubits});
+        }
+        self.expect_symbol("}")?;
+        self.gates.insert(name, GateDefinition {params, qubits, body});
+        Ok(())
+    }
+
+    fn check_signature(&self, name: &str, token: &Token, (num_params, num_qubits): (usize, usize), found_params: usize, found_qubits: usize) -> Result<(), QuantumError> {
+        if found_params != num_params {
+            return Err(parse_error(token, format!("{} takes {} parameters, found {}", name, num_params, found_params)))
+        }
+        if found_qubits != num_qubits {
+            return Err(parse_error(token, format!("{} acts on {} qubits, found {}", name, num_qubits, found_qubits)))
+        }
+        Ok(())
+    }
+
+    // Gate application, measure or reset, possibly under a classical condition
+    fn quantum_operation(&mut self, condition: Option<Condition>) -> Result<(), QuantumError> {
+        let (name, token): (String, Token) = self.expect_ident()?;
+        match name.as_str() {
+            "measure" => {
+                let qubits: Vec<usize> = self.argument(true)?;
+                self.expect_symbol("->")?;
+                let clbits: Vec<usize> = self.argument(false)?;
+                self.expect_symbol(";")?;
+                if qubits.len() != clbits.len() {
+                    return Err(parse_error(&token, format!("cannot measure {} qubits into {} classical bits", qubits.len(), clbits.len())))
+                }
+                for (qubit, clbit) in qubits.into_iter().zip(clbits) {
+                    let mut instruction: Instruction<T> = Instruction::new_measure(vec![qubit], vec![clbit]);
+                    instruction.condition = condition.clone();
+                    self.instructions.push(instruction);
+                }
+            }
+            "reset" => {
+                let qubits: Vec<usize> = self.argument(true)?;
+                self.expect_symbol(";")?;
+                for qubit in qubits {
+                    let mut instruction: Instruction<T> = Instruction::new(Operation::Reset, vec![qubit], vec![], vec![]);
+                    instruction.condition = condition.clone();
+                    self.instructions.push(instruction);
+                }
+            }
+            _ => {
+                let signature: (usize, usize) = self.signature(&name)
+                    .ok_or_else(|| parse_error(&token, format!("unknown gate {}", name)))?;
+                let mut params: Vec<f64> = vec![];
+                if self.peek_symbol("(") {
+                    params = evaluate(&self.expression_list(&[])?, &HashMap::new(), &name, &token)?;
+                }
+                let arguments: Vec<Vec<usize>> = self.arguments()?;
+                self.expect_symbol(";")?;
+                self.check_signature(&name, &token, signature, params.len(), arguments.len())?;
+
+                // Whole registers are broadcast: the gate is applied once per index, single qubits are reused every time
+                let size: usize = arguments.iter().map(|argument| argument.len()).max().unwrap_or(1);
+                if arguments.iter().any(|argument| argument.len() != 1 && argument.len() != size) {
+                    return Err(parse_error(&token, format!("registers given to {} have different sizes", name)))
+                }
+                for i in 0..size {
+                    let qubits: Vec<usize> = arguments.iter().map(|argument| if argument.len() == 1 { argument[0] } else { argument[i] }).collect();
+                    self.apply_gate(&name, &params, &qubits, &condition, &token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Add the instructions of one gate with known parameter values, expanding user definitions
+    fn apply_gate(&mut self, name: &str, params: &[f64], qubits: &[usize], condition: &Option<Condition>, token: &Token) -> Result<(), QuantumError> {
+        for (i, qubit) in qubits.iter().enumerate() {
+            if qubits[..i].contains(qubit) {
+                return Err(parse_error(token, format!("qubit {} is used more than once in {}", qubit, name)))
+            }
+        }
+        if let Some((operation, num_controls, _)) = self.builtin_gate(name) {
+            let params: Vec<f64> = match name {
+                "u2" => vec![PI / 2., params[0], params[1]],
+                "u0" => vec![],
+                _ => params.to_vec(),
+            };
+            let controls: Vec<Control> = qubits[..num_controls].iter().map(|&qubit| Control::new(qubit)).collect();
+            let mut instruction: Instruction<T> = Instruction::new(operation, qubits[num_controls..].to_vec(), controls, params.into_iter().map(real).collect());
+            instruction.condition = condition.clone();
+            self.instructions.push(instruction);
+            return Ok(())
+        }
+
+        let definition: GateDefinition = self.gates[name].clone();
+        let values: HashMap<String, f64> = definition.params.iter().cloned().zip(params.iter().copied()).collect();
+        for call in definition.body.iter() {
+            let call_qubits: Vec<usize> = call.qubits.iter()
+                .map(|argument| qubits[definition.qubits.iter().position(|qubit| qubit == argument).unwrap()])
+                .collect();
+            if call.name == "barrier" {
+                self.instructions.push(Instruction::new(Operation::Barrier, call_qubits, vec![], vec![]));
+                continue
+            }
+            let call_params: Vec<f64> = evaluate(&call.params, &values, &call.name, token)?;
+            self.apply_gate(&call.name, &call_params, &call_qubits, condition, token)?;
+        }
+        Ok(())
+    }
+
+    // Comma separated register arguments
+    fn arguments(&mut self) -> Result<Vec<Vec<usize>>, QuantumError> {
+        let mut arguments: Vec<Vec<usize>> = vec![self.argument(true)?];
+        while self.peek_symbol(",") {
+            self.next();
+            arguments.push(self.argument(true)?);
+        }
+        Ok(arguments)
+    }
+
+    /**
+    A whole register (every one of its indices) or a single indexed (qu)bit of it (ex q or q[2])
+    Return the global index of each qubit, or classical bit when quantum is false
+    */
+    fn argument(&mut self, quantum: bool) -> Result<Vec<usize>, QuantumError> {
+        let (name, token): (String, Token) = self.expect_ident()?;
+        let registers: &Vec<Register> = if quantum { &self.qregs } else { &self.cregs };
+        let (offset, size): (usize, usize) = match registers.iter().find(|register| register.name == name) {
+            Some(register) => (register.offset, register.size),
+            None => {
+                let kind: &str = if quantum { "quantum" } else { "classical" };
+                return Err(parse_error(&token, format!("unknown {} register {}", kind, name)))
+            }
+        };
+        if !self.peek_symbol("[") {
+            return Ok((offset..offset + size).collect())
+        }
+        self.next();
+        let (index, index_token): (u64, Token) = self.expect_int()?;
+        self.expect_symbol("]")?;
+        if index as usize >= size {
+            return Err(parse_error(&index_token, format!("index {} is out of range for register {} of size {}", index, name, size)))
+        }
+        Ok(vec![offset + index as usize])
+    }
+
+    // Parenthesized, comma separated expressions that may use the given parameter names
+    fn expression_list(&mut self, params: &[String]) -> Result<Vec<Expr>, QuantumError> {
+        self.expect_symbol("(")?;
+        let mut exprs: Vec<Expr> = vec![];
+        if !self.peek_symbol(")") {
+            exprs.push(self.expression(params)?);
+            while self.peek_symbol(",") {
+                self.next();
+                exprs.push(self.expression(params)?);
+            }
+        }
+        self.expect_symbol(")")?;
+        Ok(exprs)
+    }
+
+    // Sum of terms, the lowest precedence
+    fn expression(&mut self, params: &[String]) -> Result<Expr, QuantumError> {
+        let mut expr: Expr = self.term(params)?;
+        while self.peek_symbol("+") || self.peek_symbol("-") {
+            let op: &'static str = if self.next().kind == TokenKind::Symbol("+") { "+" } else { "-" };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term(params)?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self, params: &[String]) -> Result<Expr, QuantumError> {
+        let mut expr: Expr = self.factor(params)?;
+        while self.peek_symbol("*") || self.peek_symbol("/") {
+            let op: &'static str = if self.next().kind == TokenKind::Symbol("*") { "*" } else { "/" };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.factor(params)?));
+        }
+        Ok(expr)
+    }
+
+    // Unary signs and powers, ^ binds tighter than a leading minus and groups to the right
+    fn factor(&mut self, params: &[String]) -> Result<Expr, QuantumError> {
+        if self.peek_symbol("-") {
+            self.next();
+            return Ok(Expr::Neg(Box::new(self.factor(params)?)))
+        }
+        if self.peek_symbol("+") {
+            self.next();
+            return self.factor(params)
+        }
+        let base: Expr = self.primary(params)?;
+        if self.peek_symbol("^") {
+            self.next();
+            return Ok(Expr::Binary("^", Box::new(base), Box::new(self.factor(params)?)))
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self, params: &[String]) -> Result<Expr, QuantumError> {
+        let token: Token = self.next();
+        match &token.kind {
+            TokenKind::Int(value) => Ok(Expr::Number(*value as f64)),
+            TokenKind::Real(value) => Ok(Expr::Number(*value)),
+            TokenKind::Symbol("(") => {
+                let expr: Expr = self.expression(params)?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            TokenKind::Ident(name) if name == "pi" => Ok(Expr::Number(PI)),
+            TokenKind::Ident(name) if FUNCTIONS.contains(&name.as_str()) => {
+                self.expect_symbol("(")?;
+                let expr: Expr = self.expression(params)?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Function(name.clone(), Box::new(expr)))
+            }
+            TokenKind::Ident(name) if params.contains(name) => Ok(Expr::Param(name.clone())),
+            TokenKind::Ident(name) => Err(parse_error(&token, format!("unknown parameter {}", name))),
+            kind => Err(parse_error(&token, format!("expected an expression, found {}", kind.describe()))),
+        }
+    }
+}
+
+/**
+Write a circuit as an OpenQASM 2.0 program using the gates of qelib1.inc
+Gates keep their names and exact parameters, the qubits form one register q and classical registers keep their names
+(the quantum register becomes q0, q1, ... instead when a classical register is already called q)
+Parameters that are NaN or infinite have no OpenQASM form and are an UnsupportedExport error
+Gates qelib1.inc has no name for (iswap, sqrt_swap, qft, iqft) are written as gate definitions at the top of the program
+and controls firing on |0> are written as x gates around the controlled gate
+Matrix gates and controlled gates without a qelib1.inc name (ex controlled s) are an UnsupportedExport error
+*/
+pub fn to_qasm2<T: Precision>(circuit: &QuantumCircuit<T>) -> Result<String, QuantumError> {
+    Exporter::new(circuit, false).program()
+}
+
+/**
+Write a circuit as an OpenQASM 3.0 program using the gates of stdgates.inc
+Works like to_qasm2 but controls are written with the ctrl @ and negctrl @ modifiers, so any controlled named gate can be exported
+*/
+pub fn to_qasm3<T: Precision>(circuit: &QuantumCircuit<T>) -> Result<String, QuantumError> {
+    Exporter::new(circuit, true).program()
+}
+
+// Controlled gates of qelib1.inc, named with one c per control
+const QELIB1_CONTROLLED: [&str; 14] = ["cx", "cy", "cz", "ch", "csx", "crx", "cry", "crz", "cu1", "cu3", "cswap", "ccx", "c3x", "c4x"];
+
+// Controlled gates of stdgates.inc, used instead of the ctrl @ modifier when every control fires on |1>
+const STDGATES_CONTROLLED: [&str; 10] = ["cx", "cy", "cz", "ch", "crx", "cry", "crz", "cp", "cswap", "ccx"];
+
+struct Exporter<'a, T: Precision> {
+    circuit: &'a QuantumCircuit<T>,
+    qasm3: bool,
+    // Name of the quantum register, different from every classical register
+    qreg: String,
+    // Gate definitions the program needs, by name so each one is written once
+    definitions: std::collections::BTreeMap<String, String>,
+}
+
+impl<'a, T: Precision> Exporter<'a, T> {
+    fn new(circuit: &'a QuantumCircuit<T>, qasm3: bool) -> Self {
+        let taken = |name: &str| circuit.classical_registers().iter().any(|register| register.name == name);
+        let qreg: String = std::iter::once("q".to_string()).chain((0..).map(|i| format!("q{}", i)))
+            .find(|name| !taken(name))
+            .unwrap_or_default();
+        Self {circuit, qasm3, qreg, definitions: std::collections::BTreeMap::new()}
+    }
+
+    fn program(mut self) -> Result<String, QuantumError> {
+        let mut body: String = String::new();
+        for instruction in self.circuit.instructions() {
+            body += &self.instruction(instruction)?;
+        }
+
+        let mut program: String = if self.qasm3 {
+            "OPENQASM 3.0;\ninclude \"stdgates.inc\";\n".to_string()
+        } else {
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n".to_string()
+        };
+        for definition in self.definitions.values() {
+            program += definition;
+        }
+        if self.circuit.num_qubits() > 0 {
+            program += &self.declaration(&self.qreg, self.circuit.num_qubits() as usize, true);
+        }
+        for register in self.circuit.classical_registers() {
+            program += &self.declaration(&register.name, register.size, false);
+        }
+        Ok(program + &body)
+    }
+
+    fn declaration(&self, name: &str, size: usize, quantum: bool) -> String {
+        match (self.qasm3, quantum) {
+            (false, true) => format!("qreg {}[{}];\n", name, size),
+            (false, false) => format!("creg {}[{}];\n", name, size),
+            (true, true) => format!("qubit[{}] {};\n", size, name),
+            (true, false) => format!("bit[{}] {};\n", size, name),
+        }
+    }
+
+    // Name of a classical bit inside its register (ex c[1])
+    fn clbit(&self, clbit: usize) -> Result<String, QuantumError> {
+        self.circuit.classical_registers().iter()
+            .find(|register| clbit >= register.offset && clbit < register.offset + register.size)
+            .map(|register| format!("{}[{}]", register.name, clbit - register.offset))
+            .ok_or(QuantumError::ClassicalBitOutOfRange {bit: clbit, num_bits: self.circuit.num_clbits()})
+    }
+
+    // Lines of one instruction, wrapped in its classical condition
+    fn instruction(&mut self, instruction: &Instruction<T>) -> Result<String, QuantumError> {
+        let lines: Vec<String> = match instruction.operation {
+            Operation::Measure => {
+                let mut lines: Vec<String> = vec![];
+                for (&qubit, &clbit) in instruction.targets.iter().zip(instruction.clbits.iter()) {
+                    let clbit: String = self.clbit(clbit)?;
+                    lines.push(if self.qasm3 {
+                        format!("{} = measure {}[{}];", clbit, self.qreg, qubit)
+                    } else {
+                        format!("measure {}[{}] -> {};", self.qreg, qubit, clbit)
+                    });
+                }
+                lines
+            }
+            Operation::Reset => instruction.targets.iter().map(|qubit| format!("reset {}[{}];", self.qreg, qubit)).collect(),
+            Operation::Barrier if instruction.targets.is_empty() => vec![format!("barrier {};", self.qreg)],
+            Operation::Barrier => vec![format!("barrier {};", self.qubit_list(&instruction.targets))],
+            _ => self.gate_lines(instruction)?,
+        };
+
+        Ok(match (&instruction.condition, self.qasm3) {
+            (None, _) => lines.iter().map(|line| format!("{}\n", line)).collect(),
+            (Some(condition), false) => lines.iter().map(|line| format!("if({}=={}) {}\n", condition.register, condition.value, line)).collect(),
+            (Some(condition), true) => {
+                let block: String = lines.iter().map(|line| format!("    {}\n", line)).collect();
+                format!("if ({} == {}) {{\n{}}}\n", condition.register, condition.value, block)
+            }
+        })
+    }
+
+    fn gate_lines(&mut self, instruction: &Instruction<T>) -> Result<Vec<String>, QuantumError> {
+        let name: String = self.gate_name(instruction)?;
+        if let Some(param) = instruction.params.iter().find(|param| !param.is_finite()) {
+            return Err(QuantumError::UnsupportedExport(format!("{}({})", instruction.name(), param)))
+        }
+        let params: String = if instruction.params.is_empty() {
+            String::new()
+        } else {
+            let params: Vec<String> = instruction.params.iter().map(|param| param.to_string()).collect();
+            format!("({})", params.join(", "))
+        };
+        let qubits: String = self.qubit_list(&instruction.qubits());
+        if instruction.controls.is_empty() {
+            return Ok(vec![format!("{}{} {};", name, params, qubits)])
+        }
+
+        let controlled: String = "c".repeat(instruction.controls.len()) + &name;
+        let all_on_one: bool = instruction.controls.iter().all(|control| control.on_one);
+        if self.qasm3 {
+            let name: String = if all_on_one && STDGATES_CONTROLLED.contains(&controlled.as_str()) {
+                controlled
+            } else {
+                let modifiers: String = instruction.controls.iter().map(|control| if control.on_one { "ctrl @ " } else { "negctrl @ " }).collect();
+                modifiers + &name
+            };
+            return Ok(vec![format!("{}{} {};", name, params, qubits)])
+        }
+
+        if !QELIB1_CONTROLLED.contains(&controlled.as_str()) {
+            return Err(QuantumError::UnsupportedExport(instruction.name()))
+        }
+        // Controls firing on |0> become ordinary controls between two x gates
+        let flips: Vec<String> = instruction.controls.iter().filter(|control| !control.on_one).map(|control| format!("x {}[{}];", self.qreg, control.qubit)).collect();
+        let mut lines: Vec<String> = flips.clone();
+        lines.push(format!("{}{} {};", controlled, params, qubits));
+        lines.extend(flips);
+        Ok(lines)
+    }
+
+    // OpenQASM name of a gate without its controls, adding a gate definition if the gate library has no such gate
+    fn gate_name(&mut self, instruction: &Instruction<T>) -> Result<String, QuantumError> {
+        let phase: &str = if self.qasm3 { "p" } else { "u1" };
+        let controlled_phase: &str = if self.qasm3 { "cp" } else { "cu1" };
+        let name: String = match instruction.operation {
+            Operation::Phase => phase.to_string(),
+            Operation::U => "u3".to_string(),
+            Operation::Sxdg if self.qasm3 => {
+                self.define("sxdg", "gate sxdg a { h a; sdg a; h a; }\n".to_string())
+            }
+            Operation::ISwap => {
+                self.define("iswap", "gate iswap a, b { s a; s b; h a; cx a, b; cx b, a; h b; }\n".to_string())
+            }
+            Operation::SqrtSwap => {
+                let definition: String = format!("gate sqrt_swap a, b {{ cx b, a; h b; {}(pi/2) a, b; h b; cx b, a; }}\n", controlled_phase);
+                self.define("sqrt_swap", definition)
+            }
+            Operation::Qft | Operation::InverseQft => {
+                let inverse: bool = matches!(instruction.operation, Operation::InverseQft);
+                let name: String = format!("{}_{}", instruction.operation.name(), instruction.targets.len());
+                let definition: String = qft_definition(&name, instruction.targets.len(), inverse, controlled_phase);
+                self.define(&name, definition)
+            }
+            Operation::Unitary(_) | Operation::Kraus(_) => return Err(QuantumError::UnsupportedExport(instruction.name())),
+            ref operation => operation.name().to_string(),
+        };
+        Ok(name)
+    }
+
+    fn define(&mut self, name: &str, definition: String) -> String {
+        self.definitions.entry(name.to_string()).or_insert(definition);
+        name.to_string()
+    }
+
+    fn qubit_list(&self, qubits: &[usize]) -> String {
+        qubits.iter().map(|qubit| format!("{}[{}]", self.qreg, qubit)).collect::<Vec<String>>().join(", ")
+    }
+}
+
+/**
+Gate definition of the quantum fourier transform on k qubits, the first argument being the most significant bit
+Hadamards and controlled phases of pi/2^d followed by swaps reversing the qubit order, the inverse runs the same gates backwards with negated phases
+*/
+fn qft_definition(name: &str, k: usize, inverse: bool, controlled_phase: &str) -> String {
+    let mut gates: Vec<String> = vec![];
+    for i in 0..k {
+        gates.push(format!("h a{};", i));
+        for j in i + 1..k {
+            let sign: &str = if inverse { "-" } else { "" };
+            gates.push(format!("{}({}pi/{}) a{}, a{};", controlled_phase, sign, 1u64 << (j - i), j, i));
+        }
+    }
+    for i in 0..k / 2 {
+        gates.push(format!("swap a{}, a{};", i, k - 1 - i));
+    }
+    if inverse {
+        gates.reverse();
+    }
+    let arguments: Vec<String> = (0..k).map(|i| format!("a{}", i)).collect();
+    format!("gate {} {} {{ {} }}\n", name, arguments.join(", "), gates.join(" "))
+}
+
+// The static copy of a symbol so it can be compared with symbol tokens
+fn symbol_of(symbol: &str) -> &'static str {
+    SYMBOLS.iter().copied().find(|known| *known == symbol).expect("unknown symbol")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QuantumRegister::QuantumRegister;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn assert_same_state(circuit1: &QuantumCircuit<f64>, circuit2: &QuantumCircuit<f64>) {
+        let size: usize = 1 << circuit1.num_qubits();
+        let mut r1: QuantumRegister<f64> = QuantumRegister::new_from_int(0, size);
+        let mut r2: QuantumRegister<f64> = QuantumRegister::new_from_int(0, size);
+        circuit1.run(&mut r1).unwrap();
+        circuit2.run(&mut r2).unwrap();
+        for i in 0..size {
+            assert!((r1.state[i] - r2.state[i]).norm_sqr() < 1e-12, "amplitude {} differs: {} vs {}", i, r1.state[i], r2.state[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_bell_pair() {
+        let source: &str = "
+            OPENQASM 2.0;
+            include \"qelib1.inc\";
+            // two registers are laid out one after the other
+            qreg a[1];
+            qreg b[1];
+            creg c[2];
+            h a[0];
+            cx a[0], b[0];
+            measure a[0] -> c[0];
+            measure b[0] -> c[1];
+        ";
+        let circuit: QuantumCircuit<f64> = parse(source).unwrap();
+        assert_eq!(2, circuit.num_qubits());
+        assert_eq!(2, circuit.num_clbits());
+        let names: Vec<String> = circuit.instructions().iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(vec!["h 0", "cx 0 1", "measure 0 -> 0", "measure 1 -> 1"], names);
+
+        let mut rng: StdRng = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            let clbits: Vec<i32> = circuit.run_with_rng(&mut QuantumRegister::new_from_int(0, 4), &mut rng).unwrap();
+            assert_eq!(clbits[0], clbits[1]);
+        }
+    }
+
+    #[test]
+    fn test_qelib1_gates_and_expressions() {
+        let source: &str = "
+            OPENQASM 2.0;
+            include \"qelib1.inc\";
+            qreg q[3];
+            u2(0, pi) q[0];
+            u3(pi/2, -0.5*2, 1e-1) q[1];
+            crz(2^-1 + cos(0)) q[0], q[2];
+            ccx q[0], q[1], q[2];
+            rzz(0.3) q[1], q[2];
+        ";
+        let parsed: QuantumCircuit<f64> = parse(source).unwrap();
+        let mut expected: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        expected.h(0).u(1, PI / 2., -1., 0.1);
+        expected.controlled_unitary(crate::Gates::Gate::new_rz(1.5), &[Control::new(0)], &[2]);
+        expected.ccx(0, 1, 2).cx(1, 2).p(2, 0.3).cx(1, 2);
+        assert_same_state(&parsed, &expected);
+    }
+
+    #[test]
+    fn test_user_gate_definition_and_broadcast() {
+        let source: &str = "
+            OPENQASM 2.0;
+            include \"qelib1.inc\";
+            gate entangle(theta) x, y { ry(theta / 2) x; barrier x, y; cx x, y; }
+            gate twice(theta) x, y { entangle(theta) x, y; entangle(-theta) y, x; }
+            qreg q[2];
+            qreg r[2];
+            h q;
+            twice(1.2) q, r;
+        ";
+        let parsed: QuantumCircuit<f64> = parse(source).unwrap();
+        let mut expected: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        expected.h(0).h(1);
+        for i in 0..2 {
+            expected.ry(i, 0.6).cx(i, i + 2).ry(i + 2, -0.6).cx(i + 2, i);
+        }
+        assert_same_state(&parsed, &expected);
+        assert_eq!("barrier 0 2", parsed.instructions()[3].to_string());
+    }
+
+    #[test]
+    fn test_if_and_reset() {
+        let source: &str = "
+            OPENQASM 2.0;
+            include \"qelib1.inc\";
+            qreg q[2];
+            creg c[1];
+            x q[0];
+            measure q[0] -> c[0];
+            if (c == 1) x q[1];
+            reset q;
+        ";
+        let circuit: QuantumCircuit<f64> = parse(source).unwrap();
+        assert_eq!("if(c==1) x 1", circuit.instructions()[2].to_string());
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 4);
+        assert_eq!(vec![1], circuit.run(&mut register).unwrap());
+        assert!((register.state[0].re - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_errors_have_positions() {
+        let error = |source: &str| parse::<f64>(source).err().unwrap();
+        // Same program on the line after an include of qelib1.inc
+        let included = |source: &str| error(&format!("include \"qelib1.inc\";\n{}", source));
+        let at = |line: usize, column: usize, message: &str| QuantumError::QasmParse {line, column, message: message.to_string()};
+
+        assert_eq!(at(1, 10, "unsupported OpenQASM version '3', only 2.0 is supported"), error("OPENQASM 3;"));
+        assert_eq!(at(1, 10, "unsupported OpenQASM version '2.7', only 2.0 is supported"), error("OPENQASM 2.7;"));
+        assert_eq!(at(2, 1, "opaque gates are not supported"), error("qreg q[1];\nopaque g q;"));
+        assert_eq!(at(2, 3, "unknown gate foo"), error("qreg q[1];\n  foo q[0];"));
+        assert_eq!(at(2, 16, "expected ';', found 'x'"), included("qreg q[1]; h q x q;"));
+        assert_eq!(at(2, 16, "index 1 is out of range for register q of size 1"), included("qreg q[1]; x q[1];"));
+        assert_eq!(at(2, 19, "unknown parameter theta"), included("qreg q[1]; rx(2 * theta) q[0];"));
+        assert_eq!(at(1, 9, "only \"qelib1.inc\" can be included, found \"other.inc\""), error("include \"other.inc\";"));
+        assert_eq!(at(2, 23, "cx acts on 2 qubits, found 1"), included("qreg q[2]; gate g a { cx a; }"));
+        assert_eq!(at(2, 12, "qubit 0 is used more than once in cx"), included("qreg q[2]; cx q[0], q[0];"));
+        assert_eq!(at(2, 12, "parameter of rx evaluates to NaN"), included("qreg q[1]; rx(0/0) q[0];"));
+        assert_eq!(at(2, 12, "parameter of u1 evaluates to NaN"), included("qreg q[1]; u1(ln(-1)) q[0];"));
+        // Inside a definition the parameter is only known when the gate is used
+        assert_eq!(at(2, 49, "parameter of rz evaluates to inf"), included("qreg q[1]; gate g(a) b { rz(1 / a) b; } h q[0]; g(0) q[0];"));
+
+        // Without the include only U and CX exist
+        assert_eq!(at(1, 12, "unknown gate x"), error("qreg q[2]; x q[0];"));
+        assert_eq!(at(1, 26, "unknown gate cx"), error("qreg q[2]; gate g a, b { cx a, b; }"));
+        assert_eq!(3, parse::<f64>("qreg q[2]; U(pi, 0, pi) q[0]; CX q[0], q[1]; gate g a { U(0, 0, 0) a; } g q[1];").unwrap().instructions().len());
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        circuit.h(0).h(1).sxdg(2).u(3, 0.1, -0.7, 2.5).p(1, 0.3).rx(2, 1.25);
+        circuit.iswap(0, 2).sqrt_swap(1, 3).qft(&[0, 1, 3]).inverse_qft(&[2, 3]).qft(&[1, 2, 0]);
+        circuit.mcx(&[Control::new_negated(0), Control::new(1)], 3).cp(2, 0, -0.4).cswap(3, 1, 2);
+        let qasm: String = to_qasm2(&circuit).unwrap();
+        assert!(qasm.starts_with("OPENQASM 2.0;\ninclude \"qelib1.inc\";\n"));
+        assert!(qasm.contains("u3(0.1, -0.7, 2.5) q[3];\n"));
+        assert!(qasm.contains("x q[0];\nccx q[0], q[1], q[3];\nx q[0];\n"));
+        assert_eq!(1, qasm.matches("gate qft_3 ").count());
+        assert_same_state(&circuit, &parse(&qasm).unwrap());
+    }
+
+    #[test]
+    fn test_export_classical() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("a", 1).unwrap();
+        circuit.add_classical_register("b", 2).unwrap();
+        circuit.h(0).measure(0, 2).x(1).c_if("b", 2).barrier(&[0, 1]).reset(0);
+        let qasm2: &str = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg a[1];\ncreg b[2];\n\
+            h q[0];\nmeasure q[0] -> b[1];\nif(b==2) x q[1];\nbarrier q[0], q[1];\nreset q[0];\n";
+        assert_eq!(qasm2, to_qasm2(&circuit).unwrap());
+        assert_eq!(qasm2, to_qasm2(&parse::<f64>(qasm2).unwrap()).unwrap());
+
+        circuit.add_instruction(Instruction::new(Operation::S, vec![0], vec![Control::new_negated(1)], vec![]));
+        let qasm3: &str = "OPENQASM 3.0;\ninclude \"stdgates.inc\";\nqubit[2] q;\nbit[1] a;\nbit[2] b;\n\
+            h q[0];\nb[1] = measure q[0];\nif (b == 2) {\n    x q[1];\n}\nbarrier q[0], q[1];\nreset q[0];\nnegctrl @ s q[1], q[0];\n";
+        assert_eq!(qasm3, to_qasm3(&circuit).unwrap());
+        assert_eq!(Err(QuantumError::UnsupportedExport("cs".to_string())), to_qasm2(&circuit));
+
+        circuit.unitary(crate::Gates::Gate::new_h(), &[1]);
+        assert_eq!(Err(QuantumError::UnsupportedExport("unitary".to_string())), to_qasm3(&circuit));
+    }
+
+    /**
+    Rewrite the OpenQASM 3.0 that to_qasm3 writes for circuits without ctrl @ modifiers as OpenQASM 2.0, so the parser can check it
+    Declarations, measurements and if blocks change syntax, the gates of stdgates.inc keep their names in qelib1.inc
+    */
+    fn qasm3_to_qasm2(qasm3: &str) -> String {
+        let mut lines = qasm3.lines();
+        let mut qasm2: String = String::new();
+        while let Some(line) = lines.next() {
+            let statement: &str = line.trim_end_matches(';');
+            let converted: String = if line == "OPENQASM 3.0;" {
+                "OPENQASM 2.0;".to_string()
+            } else if line == "include \"stdgates.inc\";" {
+                "include \"qelib1.inc\";".to_string()
+            } else if line.starts_with("gate sxdg ") {
+                // Part of qelib1.inc already
+                continue
+            } else if let Some((size, name)) = statement.strip_prefix("qubit[").and_then(|rest| rest.split_once("] ")) {
+                format!("qreg {}[{}];", name, size)
+            } else if let Some((size, name)) = statement.strip_prefix("bit[").and_then(|rest| rest.split_once("] ")) {
+                format!("creg {}[{}];", name, size)
+            } else if let Some((clbit, qubit)) = statement.split_once(" = measure ") {
+                format!("measure {} -> {};", qubit, clbit)
+            } else if let Some(condition) = line.strip_prefix("if (").and_then(|rest| rest.strip_suffix(") {")) {
+                lines.by_ref().take_while(|line| *line != "}").map(|body| format!("if ({}) {}\n", condition, body.trim())).collect::<String>()
+                    .trim_end().to_string()
+            } else {
+                line.to_string()
+            };
+            qasm2 += &converted;
+            qasm2 += "\n";
+        }
+        qasm2
+    }
+
+    #[test]
+    fn test_export_qasm3_round_trip() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        circuit.h(0).h(1).sxdg(2).u(3, 0.1, -0.7, 2.5).p(1, 0.3).rx(2, 1.25);
+        circuit.iswap(0, 2).sqrt_swap(1, 3).qft(&[0, 1, 3]).inverse_qft(&[2, 3]);
+        circuit.ccx(0, 1, 3).cp(2, 0, -0.4).cswap(3, 1, 2).cx(1, 0);
+        assert_same_state(&circuit, &parse(&qasm3_to_qasm2(&to_qasm3(&circuit).unwrap())).unwrap());
+
+        // A classical register called q moves the quantum register to another name
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("q", 1).unwrap();
+        circuit.add_classical_register("q0", 1).unwrap();
+        circuit.h(0).cx(0, 1).measure(0, 0).x(1).c_if("q", 1).barrier(&[0, 1]).reset(1).measure(1, 1);
+        let qasm3: String = to_qasm3(&circuit).unwrap();
+        assert!(qasm3.contains("qubit[2] q1;\nbit[1] q;\nbit[1] q0;\n"));
+        let qasm2: String = to_qasm2(&circuit).unwrap();
+        assert!(qasm2.contains("qreg q1[2];\ncreg q[1];\ncreg q0[1];\n"));
+        assert_eq!(qasm2, to_qasm2(&parse::<f64>(&qasm3_to_qasm2(&qasm3)).unwrap()).unwrap());
+        assert_eq!(qasm2, to_qasm2(&parse::<f64>(&qasm2).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_export_rejects_non_finite_parameters() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.rx(0, f64::NAN);
+        assert_eq!(Err(QuantumError::UnsupportedExport("rx(NaN)".to_string())), to_qasm2(&circuit));
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.cp(0, 1, f64::INFINITY);
+        assert_eq!(Err(QuantumError::UnsupportedExport("cp(inf)".to_string())), to_qasm3(&circuit));
+    }
+}
@@ -0,0 +1,19 @@
+use nalgebra::RealField;
+
+/**
+Real number type used for the parts of every complex amplitude and matrix entry
+f32 is the default (cheap, and what the WASM front end uses)
+f64 keeps the norm of the state from drifting over long circuits with hundreds of gates
+*/
+pub trait Precision: RealField + Copy {}
+
+impl Precision for f32 {}
+
+impl Precision for f64 {}
+
+/**
+Convert an f64 constant into the working precision
+*/
+pub fn real<T: Precision>(value: f64) -> T {
+    nalgebra::convert(value)
+}
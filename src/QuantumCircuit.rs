@@ -1,54 +1,586 @@
+use std::fmt;
+use rand::Rng;
+use crate::Backend::Backend;
 use crate::Gates::Gate;
+use crate::Instruction::{Condition, Control, Instruction, Operation};
+use crate::Noise::{KrausChannel, NoiseModel};
+use crate::Precision::Precision;
+use crate::QuantumError::QuantumError;
 use crate::QuantumRegister::QuantumRegister;
 use crate::Qubit::Qubit;
+use crate::UnitarySimulator::UnitarySimulator;
 
-pub struct QuantumCircuit {
-    gates: Vec<Gate>,
-    num_qubits: i32
+/**
+Named group of classical bits in a circuit
+The circuit keeps all classical bits in one list, the register covers bits offset..offset + size of it
+When read as a number, bit 0 of the register is the least significant bit
+*/
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClassicalRegister {
+    pub name: String,
+    pub size: usize,
+    pub offset: usize,
 }
 
-impl QuantumCircuit {
-    pub fn new(gate: Gate, num_qubits: i32) -> Self {
-        Self {gates: vec![gate], num_qubits}
+/**
+Ordered list of instructions acting on a fixed number of qubits and any number of classical bits
+Each instruction remembers which qubits it touches, so the circuit can be inspected and run without pre-expanding every gate to the full register
+*/
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct QuantumCircuit<T: Precision = f32> {
+    instructions: Vec<Instruction<T>>,
+    num_qubits: i32,
+    classical_registers: Vec<ClassicalRegister>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    noise_model: Option<NoiseModel<T>>
+}
+
+impl<T: Precision> QuantumCircuit<T> {
+    pub fn new(gate: Gate<T>, num_qubits: i32) -> Self {
+        let mut circuit: QuantumCircuit<T> = QuantumCircuit::new_with_qubits(num_qubits);
+        circuit.add_gate(gate);
+        circuit
+    }
+
+    /**
+    Instantiate a circuit with no instructions, to be filled with the builder methods (ex circuit.h(0).cx(0, 3))
+    */
+    pub fn new_with_qubits(num_qubits: i32) -> Self {
+        Self {instructions: vec![], num_qubits, classical_registers: vec![], noise_model: None}
+    }
+
+    /**
+    Noise applied on every run of the circuit without changing its instructions, None for an ideal run
+    Gate errors are applied right after each matching gate and the readout error to each measured bit
+    */
+    pub fn set_noise_model(&mut self, noise_model: Option<NoiseModel<T>>) {
+        self.noise_model = noise_model;
+    }
+
+    pub fn noise_model(&self) -> Option<&NoiseModel<T>> {
+        self.noise_model.as_ref()
+    }
+
+    /**
+    Build a circuit from an OpenQASM 2.0 program, see OpenQasm::parse
+    */
+    pub fn from_qasm(source: &str) -> Result<Self, QuantumError> {
+        crate::OpenQasm::parse(source)
+    }
+
+    // Write the circuit as an OpenQASM 2.0 program, see OpenQasm::to_qasm2
+    pub fn to_qasm2(&self) -> Result<String, QuantumError> {
+        crate::OpenQasm::to_qasm2(self)
+    }
+
+    // Write the circuit as an OpenQASM 3.0 program, see OpenQasm::to_qasm3
+    pub fn to_qasm3(&self) -> Result<String, QuantumError> {
+        crate::OpenQasm::to_qasm3(self)
+    }
+
+    pub fn num_qubits(&self) -> i32 {
+        self.num_qubits
+    }
+
+    pub fn instructions(&self) -> &[Instruction<T>] {
+        &self.instructions
+    }
+
+    /**
+    Add a named classical register of size bits after all existing classical bits
+    A register holds at most 64 bits since its value is read as a u64 by classical_value and c_if
+    Return the index of its first bit
+    */
+    pub fn add_classical_register(&mut self, name: &str, size: usize) -> Result<usize, QuantumError> {
+        if self.classical_register(name).is_ok() {
+            return Err(QuantumError::DuplicateClassicalRegister(name.to_string()))
+        }
+        if size > u64::BITS as usize {
+            return Err(QuantumError::ClassicalRegisterTooLarge {name: name.to_string(), size})
+        }
+        let offset: usize = self.num_clbits();
+        self.classical_registers.push(ClassicalRegister {name: name.to_string(), size, offset});
+        Ok(offset)
+    }
+
+    pub fn classical_registers(&self) -> &[ClassicalRegister] {
+        &self.classical_registers
+    }
+
+    pub fn classical_register(&self, name: &str) -> Result<&ClassicalRegister, QuantumError> {
+        self.classical_registers.iter().find(|register| register.name == name)
+            .ok_or_else(|| QuantumError::UnknownClassicalRegister(name.to_string()))
     }
 
-    pub fn add_gate(&mut self, gate: Gate){
-        self.gates.push(gate);
+    // Total number of classical bits over all classical registers
+    pub fn num_clbits(&self) -> usize {
+        self.classical_registers.iter().map(|register| register.size).sum()
     }
 
-    pub fn run(&self, register: &mut QuantumRegister) {
-        for i  in 0..self.gates.len(){
-            match self.gates[i].apply(register) {
-                Ok(_) => {
-                    continue
+    /**
+    Index of bit `index` of a named classical register in the list of all classical bits
+    */
+    pub fn clbit(&self, register: &str, index: usize) -> Result<usize, QuantumError> {
+        let register: &ClassicalRegister = self.classical_register(register)?;
+        if index >= register.size {
+            return Err(QuantumError::ClassicalBitOutOfRange {bit: index, num_bits: register.size})
+        }
+        Ok(register.offset + index)
+    }
+
+    /**
+    Value of a named classical register given the classical bits returned by run, bit 0 being the least significant
+    */
+    pub fn classical_value(&self, clbits: &[i32], register: &str) -> Result<u64, QuantumError> {
+        let register: &ClassicalRegister = self.classical_register(register)?;
+        if clbits.len() < register.offset + register.size {
+            return Err(QuantumError::ClassicalBitOutOfRange {bit: register.offset + register.size - 1, num_bits: clbits.len()})
+        }
+        Ok((0..register.size).fold(0, |value, i| value | ((clbits[register.offset + i] as u64) << i)))
+    }
+
+    /**
+    Add a gate that acts on the whole register (ex one built with Gate::new_multi_cnot)
+    */
+    pub fn add_gate(&mut self, gate: Gate<T>){
+        let targets: Vec<usize> = (0..self.num_qubits.max(0) as usize).collect();
+        self.add_instruction(Instruction::new(Operation::Unitary(gate), targets, vec![], vec![]));
+    }
+
+    pub fn add_instruction(&mut self, instruction: Instruction<T>){
+        self.instructions.push(instruction);
+    }
+
+    // Add a named gate with the given targets, controls and parameters
+    fn push(&mut self, operation: Operation<T>, targets: Vec<usize>, controls: Vec<Control>, params: Vec<T>) -> &mut Self {
+        self.add_instruction(Instruction::new(operation, targets, controls, params));
+        self
+    }
+
+    // Identity gate
+    pub fn i(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::I, vec![qubit], vec![], vec![])
+    }
+
+    // Pauli X gate
+    pub fn x(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::X, vec![qubit], vec![], vec![])
+    }
+
+    // Pauli Y gate
+    pub fn y(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Y, vec![qubit], vec![], vec![])
+    }
+
+    // Pauli Z gate
+    pub fn z(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Z, vec![qubit], vec![], vec![])
+    }
+
+    // Hadamard gate
+    pub fn h(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::H, vec![qubit], vec![], vec![])
+    }
+
+    // pi/8 gate
+    pub fn t(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::T, vec![qubit], vec![], vec![])
+    }
+
+    // Inverse of the pi/8 gate
+    pub fn tdg(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Tdg, vec![qubit], vec![], vec![])
+    }
+
+    // S gate (square root of Z)
+    pub fn s(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::S, vec![qubit], vec![], vec![])
+    }
+
+    // Inverse of the S gate
+    pub fn sdg(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Sdg, vec![qubit], vec![], vec![])
+    }
+
+    // Square root of X
+    pub fn sx(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Sx, vec![qubit], vec![], vec![])
+    }
+
+    // Inverse of the square root of X
+    pub fn sxdg(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Sxdg, vec![qubit], vec![], vec![])
+    }
+
+    // Rotation around the X axis by theta radians
+    pub fn rx(&mut self, qubit: usize, theta: T) -> &mut Self {
+        self.push(Operation::Rx, vec![qubit], vec![], vec![theta])
+    }
+
+    // Rotation around the Y axis by theta radians
+    pub fn ry(&mut self, qubit: usize, theta: T) -> &mut Self {
+        self.push(Operation::Ry, vec![qubit], vec![], vec![theta])
+    }
+
+    // Rotation around the Z axis by theta radians
+    pub fn rz(&mut self, qubit: usize, theta: T) -> &mut Self {
+        self.push(Operation::Rz, vec![qubit], vec![], vec![theta])
+    }
+
+    // General single qubit gate U(θ, φ, λ)
+    pub fn u(&mut self, qubit: usize, theta: T, phi: T, lambda: T) -> &mut Self {
+        self.push(Operation::U, vec![qubit], vec![], vec![theta, phi, lambda])
+    }
+
+    // Phase gate diag(1, e^(iλ)) with λ in radians
+    pub fn p(&mut self, qubit: usize, lambda: T) -> &mut Self {
+        self.push(Operation::Phase, vec![qubit], vec![], vec![lambda])
+    }
+
+    // Controlled not gate
+    pub fn cx(&mut self, control: usize, target: usize) -> &mut Self {
+        self.push(Operation::X, vec![target], vec![Control::new(control)], vec![])
+    }
+
+    // Controlled Z gate
+    pub fn cz(&mut self, control: usize, target: usize) -> &mut Self {
+        self.push(Operation::Z, vec![target], vec![Control::new(control)], vec![])
+    }
+
+    // Controlled phase gate with λ in radians
+    pub fn cp(&mut self, control: usize, target: usize, lambda: T) -> &mut Self {
+        self.push(Operation::Phase, vec![target], vec![Control::new(control)], vec![lambda])
+    }
+
+    // Swap the states of two qubits
+    pub fn swap(&mut self, qubit1: usize, qubit2: usize) -> &mut Self {
+        self.push(Operation::Swap, vec![qubit1, qubit2], vec![], vec![])
+    }
+
+    // Swap two qubits and multiply |01> and |10> by i
+    pub fn iswap(&mut self, qubit1: usize, qubit2: usize) -> &mut Self {
+        self.push(Operation::ISwap, vec![qubit1, qubit2], vec![], vec![])
+    }
+
+    // Square root of the swap gate
+    pub fn sqrt_swap(&mut self, qubit1: usize, qubit2: usize) -> &mut Self {
+        self.push(Operation::SqrtSwap, vec![qubit1, qubit2], vec![], vec![])
+    }
+
+    // Toffoli gate (controlled controlled not)
+    pub fn ccx(&mut self, control1: usize, control2: usize, target: usize) -> &mut Self {
+        self.push(Operation::X, vec![target], vec![Control::new(control1), Control::new(control2)], vec![])
+    }
+
+    // Fredkin gate (controlled swap)
+    pub fn cswap(&mut self, control: usize, target1: usize, target2: usize) -> &mut Self {
+        self.push(Operation::Swap, vec![target1, target2], vec![Control::new(control)], vec![])
+    }
+
+    // Not gate controlled by any number of qubits, each firing on |1> or |0>
+    pub fn mcx(&mut self, controls: &[Control], target: usize) -> &mut Self {
+        self.push(Operation::X, vec![target], controls.to_vec(), vec![])
+    }
+
+    // Gate on the given targets controlled by any number of qubits, each firing on |1> or |0>
+    pub fn controlled_unitary(&mut self, gate: Gate<T>, controls: &[Control], targets: &[usize]) -> &mut Self {
+        self.push(Operation::Unitary(gate), targets.to_vec(), controls.to_vec(), vec![])
+    }
+
+    // Measure a qubit into a classical bit (index into all classical bits, see clbit)
+    pub fn measure(&mut self, qubit: usize, clbit: usize) -> &mut Self {
+        self.add_instruction(Instruction::new_measure(vec![qubit], vec![clbit]));
+        self
+    }
+
+    // Put a qubit back into |0>
+    pub fn reset(&mut self, qubit: usize) -> &mut Self {
+        self.push(Operation::Reset, vec![qubit], vec![], vec![])
+    }
+
+    // Barrier across the given qubits, it does nothing to the state
+    pub fn barrier(&mut self, qubits: &[usize]) -> &mut Self {
+        self.push(Operation::Barrier, qubits.to_vec(), vec![], vec![])
+    }
+
+    /**
+    Condition the last added instruction on a classical register holding value (ex circuit.x(1).c_if("c", 3))
+    Does nothing on a circuit without instructions, since there is nothing to condition
+    */
+    pub fn c_if(&mut self, register: &str, value: u64) -> &mut Self {
+        if let Some(instruction) = self.instructions.last_mut() {
+            instruction.condition = Some(Condition {register: register.to_string(), value});
+        }
+        self
+    }
+
+    // Quantum fourier transform on the given qubits, the first one being the most significant
+    pub fn qft(&mut self, qubits: &[usize]) -> &mut Self {
+        self.push(Operation::Qft, qubits.to_vec(), vec![], vec![])
+    }
+
+    pub fn inverse_qft(&mut self, qubits: &[usize]) -> &mut Self {
+        self.push(Operation::InverseQft, qubits.to_vec(), vec![], vec![])
+    }
+
+    // Gate acting on the given targets, the first target being the left most factor of its matrix
+    pub fn unitary(&mut self, gate: Gate<T>, targets: &[usize]) -> &mut Self {
+        self.push(Operation::Unitary(gate), targets.to_vec(), vec![], vec![])
+    }
+
+    /**
+    Noise channel on the given targets (ex circuit.channel(KrausChannel::new_bit_flip(0.01)?, &[2]))
+    The first target is the left most factor of the Kraus operators
+    */
+    pub fn channel(&mut self, channel: KrausChannel<T>, targets: &[usize]) -> &mut Self {
+        self.push(Operation::Kraus(channel), targets.to_vec(), vec![], vec![])
+    }
+
+    /**
+    Apply every instruction of the circuit to the register in order
+    The register can be any backend, a QuantumRegister for pure states or a DensityMatrix for mixed states
+    Measurements collapse the register and write into the classical bits, which start at 0 and are returned at the end
+    If the circuit has a noise model its errors are applied along the way
+    Stops at the first instruction that fails and returns its error, leaving the register in the state reached so far
+    */
+    pub fn run<B: Backend<T>>(&self, register: &mut B) -> Result<Vec<i32>, QuantumError> {
+        self.run_with_rng(register, &mut rand::thread_rng())
+    }
+
+    /**
+    Same as run but measurements draw from the given random number generator, so a seeded generator gives reproducible runs
+    */
+    pub fn run_with_rng<B: Backend<T>, R: Rng + ?Sized>(&self, register: &mut B, rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        self.check_register(register)?;
+        let mut clbits: Vec<i32> = vec![0; self.num_clbits()];
+        for instruction in self.instructions.iter(){
+            self.execute(instruction, register, &mut clbits, rng)?;
+        }
+        Ok(clbits)
+    }
+
+    /**
+    Full 2^n x 2^n unitary of the circuit, built by composing the matrix of every gate on a UnitarySimulator
+    The noise model is ignored and barriers are skipped, measurements, resets, channels and classically conditioned gates have no unitary
+    and return NonUnitaryOperation
+    The product is checked to be unitary within tolerance (every entry of U * U^†), otherwise NonUnitaryMatrix is returned
+    */
+    pub fn to_unitary(&self, tolerance: T) -> Result<Gate<T>, QuantumError> {
+        let mut simulator: UnitarySimulator<T> = UnitarySimulator::new(self.num_qubits.max(0) as usize);
+        for instruction in self.instructions.iter() {
+            if instruction.condition.is_some() {
+                return Err(QuantumError::NonUnitaryOperation(instruction.name()))
+            }
+            if !matches!(instruction.operation, Operation::Barrier) {
+                simulator.apply_instruction(instruction)?;
+            }
+        }
+        simulator.unitary(tolerance)
+    }
+
+    pub fn run_with_tracking(&self, register: &mut QuantumRegister<T>) -> Result<Vec<Vec<Qubit<T>>>, QuantumError> {
+        self.check_register(register)?;
+        let mut clbits: Vec<i32> = vec![0; self.num_clbits()];
+        let mut ret: Vec<Vec<Qubit<T>>> = vec![];
+        for instruction in self.instructions.iter(){
+            ret.push(register.get_qubit_norms());
+            self.execute(instruction, register, &mut clbits, &mut rand::thread_rng())?;
+        }
+        Ok(ret)
+    }
+
+    // Run a single instruction, skipping it if its classical condition does not hold
+    fn execute<B: Backend<T>, R: Rng + ?Sized>(&self, instruction: &Instruction<T>, register: &mut B, clbits: &mut [i32], rng: &mut R) -> Result<(), QuantumError> {
+        if let Some(condition) = &instruction.condition {
+            if self.classical_value(clbits, &condition.register)? != condition.value {
+                return Ok(())
+            }
+        }
+        if !instruction.controls.is_empty() && !instruction.is_unitary() {
+            return Err(QuantumError::UncontrollableOperation(instruction.operation.name().to_string()))
+        }
+        match instruction.operation {
+            Operation::Measure => {
+                if instruction.clbits.len() != instruction.targets.len() {
+                    return Err(QuantumError::DimensionMismatch {expected: instruction.targets.len(), found: instruction.clbits.len()})
                 }
-                Err(_) => {
-                    print!("Error in applying gate\n")
+                if let Some(&clbit) = instruction.clbits.iter().find(|&&clbit| clbit >= clbits.len()) {
+                    return Err(QuantumError::ClassicalBitOutOfRange {bit: clbit, num_bits: clbits.len()})
+                }
+                let bits: Vec<i32> = register.measure_qubits_with_rng(&instruction.targets, rng)?;
+                for (&clbit, bit) in instruction.clbits.iter().zip(bits) {
+                    clbits[clbit] = match &self.noise_model {
+                        Some(noise_model) => noise_model.read(bit, rng),
+                        None => bit,
+                    };
+                }
+            }
+            Operation::Reset => {
+                for &qubit in instruction.targets.iter() {
+                    register.reset_with_rng(qubit, rng)?;
+                }
+            }
+            Operation::Kraus(ref channel) => register.apply_channel_with_rng(channel, &instruction.targets, rng)?,
+            Operation::Barrier => {}
+            _ => {
+                register.apply_instruction(instruction)?;
+                if let Some(noise_model) = &self.noise_model {
+                    for (channel, qubits) in noise_model.gate_errors(instruction) {
+                        register.apply_channel_with_rng(channel, &qubits, rng)?;
+                    }
                 }
             }
         }
+        Ok(())
     }
 
-    pub fn run_with_tracking(&self, register: &mut QuantumRegister) -> Vec<Vec<Qubit>> {
-        let mut ret: Vec<Vec<Qubit>> = vec![];
-        for i  in 0..self.gates.len(){
-            ret.push(register.get_qubit_norms());
-            match self.gates[i].apply(register) {
-                Ok(_) => {
-                    continue
+    // The register has to hold exactly as many qubits as the circuit was built for
+    fn check_register<B: Backend<T>>(&self, register: &B) -> Result<(), QuantumError> {
+        if register.num_qubits() as i32 != self.num_qubits {
+            return Err(QuantumError::DimensionMismatch {expected: self.num_qubits.max(0) as usize, found: register.num_qubits()})
+        }
+        Ok(())
+    }
+
+    /**
+    Labels one instruction puts on the diagram, by wire (qubits first, then classical registers)
+    An instruction on a qubit the circuit does not have is drawn as one label (ex ?h(q3)) on a marker wire below all others
+    */
+    fn diagram_item(&self, instruction: &Instruction<T>) -> DiagramItem {
+        let num_qubits: usize = self.num_qubits.max(0) as usize;
+        let qubits: Vec<usize> = instruction.qubits();
+        if qubits.iter().any(|&qubit| qubit >= num_qubits) {
+            let marker: usize = num_qubits + self.classical_registers.len();
+            let qubits: Vec<String> = qubits.iter().map(|qubit| format!("q{}", qubit)).collect();
+            return DiagramItem {top: marker, bottom: marker, labels: vec![(marker, format!("?{}({})", instruction.name(), qubits.join(",")))]}
+        }
+        let classical_wire = |clbit: usize| self.classical_registers.iter().enumerate()
+            .find(|(_, register)| clbit >= register.offset && clbit < register.offset + register.size)
+            .map(|(i, register)| (num_qubits + i, (clbit - register.offset).to_string()));
+
+        let mut labels: Vec<(usize, String)> = instruction.controls.iter()
+            .map(|control| (control.qubit, if control.on_one { "*" } else { "o" }.to_string()))
+            .collect();
+        match instruction.operation {
+            Operation::Measure => {
+                for (&qubit, &clbit) in instruction.targets.iter().zip(instruction.clbits.iter()) {
+                    labels.push((qubit, "M".to_string()));
+                    labels.extend(classical_wire(clbit));
+                }
+            }
+            Operation::Reset => labels.extend(instruction.targets.iter().map(|&qubit| (qubit, "|0>".to_string()))),
+            Operation::Barrier if instruction.targets.is_empty() => labels.extend((0..num_qubits).map(|qubit| (qubit, "|".to_string()))),
+            Operation::Barrier => labels.extend(instruction.targets.iter().map(|&qubit| (qubit, "|".to_string()))),
+            Operation::Swap => labels.extend(instruction.targets.iter().map(|&qubit| (qubit, "x".to_string()))),
+            ref operation => {
+                let mut label: String = operation.name().to_uppercase();
+                if !instruction.params.is_empty() {
+                    let params: Vec<String> = instruction.params.iter().map(|param| format!("{:.4}", param)).collect();
+                    label += &format!("({})", params.join(", "));
+                }
+                // Gates on several targets number them so the order of the targets can be read off
+                for (i, &qubit) in instruction.targets.iter().enumerate() {
+                    let suffix: String = if instruction.targets.len() > 1 { format!(":{}", i) } else { String::new() };
+                    labels.push((qubit, label.clone() + &suffix));
+                }
+            }
+        }
+        if let Some(condition) = &instruction.condition {
+            if let Some(i) = self.classical_registers.iter().position(|register| register.name == condition.register) {
+                labels.push((num_qubits + i, format!("[={}]", condition.value)));
+            }
+        }
+
+        let top: usize = labels.iter().map(|(wire, _)| *wire).min().unwrap_or(0);
+        let bottom: usize = labels.iter().map(|(wire, _)| *wire).max().unwrap_or(0);
+        DiagramItem {top, bottom, labels}
+    }
+}
+
+// Labels of one instruction in the circuit diagram, joined by a vertical line from wire top to wire bottom
+struct DiagramItem {
+    top: usize,
+    bottom: usize,
+    labels: Vec<(usize, String)>,
+}
+
+/**
+Draws the circuit as text: one wire per qubit (q0, q1, ...) drawn with - followed by one wire per classical register drawn with =
+Gates are written on their target wires (ex H, RX(0.5000)), controls as * (fires on |1>) or o (fires on |0>), swaps as x,
+measurements as M with the classical bit index on the register wire and conditions as [=value] on the register wire
+Parts of one instruction are joined by a vertical line | which crosses other wires as +
+Instructions that do not share any wires are drawn in the same column
+Instructions on qubits out of range are drawn on an extra wire ? instead of failing
+*/
+impl<T: Precision> fmt::Display for QuantumCircuit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let num_qubits: usize = self.num_qubits.max(0) as usize;
+        let mut names: Vec<String> = (0..num_qubits).map(|qubit| format!("q{}", qubit))
+            .chain(self.classical_registers.iter().map(|register| register.name.clone()))
+            .collect();
+        let marker: usize = names.len();
+        let items: Vec<DiagramItem> = self.instructions.iter().map(|instruction| self.diagram_item(instruction)).collect();
+        if items.iter().any(|item| item.bottom >= marker) {
+            names.push("?".to_string());
+        }
+        if names.is_empty() {
+            return Ok(())
+        }
+
+        // Each instruction goes in the first column after everything already drawn on the wires it spans
+        let mut columns: Vec<Vec<DiagramItem>> = vec![];
+        let mut next_free: Vec<usize> = vec![0; names.len()];
+        for item in items {
+            if item.labels.is_empty() {
+                continue
+            }
+            let column: usize = (item.top..=item.bottom).map(|wire| next_free[wire]).max().unwrap_or(0);
+            for free in next_free[item.top..=item.bottom].iter_mut() {
+                *free = column + 1;
+            }
+            if column == columns.len() {
+                columns.push(vec![]);
+            }
+            columns[column].push(item);
+        }
+
+        // Wire w is drawn on row 2w, the odd rows in between only hold vertical lines
+        let name_width: usize = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+        let mut rows: Vec<String> = (0..2 * names.len() - 1)
+            .map(|row| if row % 2 == 0 { format!("{:<width$} ", names[row / 2].clone() + ":", width = name_width + 1) } else { " ".repeat(name_width + 2) })
+            .collect();
+        for column in columns.iter() {
+            let mut cells: Vec<Option<String>> = vec![None; rows.len()];
+            for item in column.iter() {
+                for (row, cell) in cells.iter_mut().enumerate().take(2 * item.bottom + 1).skip(2 * item.top) {
+                    *cell = Some(if row % 2 == 0 { "+" } else { "|" }.to_string());
                 }
-                Err(_) => {
-                    print!("Error in applying gate\n")
+                for (wire, label) in item.labels.iter() {
+                    cells[2 * wire] = Some(label.clone());
                 }
             }
+            let width: usize = cells.iter().flatten().map(|label| label.chars().count()).max().unwrap_or(1);
+            for (row, line) in rows.iter_mut().enumerate() {
+                let fill: char = if row % 2 == 1 || row / 2 == marker { ' ' } else if row / 2 < num_qubits { '-' } else { '=' };
+                let label: &str = cells[row].as_deref().unwrap_or("");
+                let left: usize = (width - label.chars().count()) / 2;
+                let right: usize = width - label.chars().count() - left;
+                line.push_str(&fill.to_string().repeat(left + 1));
+                line.push_str(label);
+                line.push_str(&fill.to_string().repeat(right + 1));
+            }
         }
-        ret
+        let rows: Vec<&str> = rows.iter().map(|row| row.trim_end()).collect();
+        write!(f, "{}", rows.join("\n"))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
 
     fn print_register(register: &QuantumRegister){
         for i in 0..register.state.len(){
@@ -62,14 +594,14 @@ mod tests {
         let h1: Gate = Gate::multi_single_qubit_gate(0,num_qubits,Gate::new_h()).unwrap();
         let h2: Gate = Gate::multi_single_qubit_gate(1,num_qubits,Gate::new_h()).unwrap();
         let h3: Gate = Gate::multi_single_qubit_gate(0,num_qubits,Gate::new_t()).unwrap();
-        let gate: Gate = Gate::new_multi_cnot(0, 3, num_qubits);
-        let gate1: Gate = Gate::new_multi_cnot(1, 3, num_qubits);
+        let gate: Gate = Gate::new_multi_cnot(0, 3, num_qubits).unwrap();
+        let gate1: Gate = Gate::new_multi_cnot(1, 3, num_qubits).unwrap();
 
         let mut circuit: QuantumCircuit = QuantumCircuit::new(h1, num_qubits);
 
         //let gate1: Gate = Gate::multi_single_qubit_gate(2, num_qubits, Gate::new_identity()).unwrap();
         //let gate2: Gate = Gate::multi_single_qubit_gate(1, num_qubits, Gate::new_identity()).unwrap();
-        let gate2: Gate = Gate::new_multi_cnot(2,3,num_qubits);
+        let gate2: Gate = Gate::new_multi_cnot(2,3,num_qubits).unwrap();
         circuit.add_gate(h2);
         circuit.add_gate(h3);
         circuit.add_gate(gate);
@@ -86,7 +618,7 @@ mod tests {
         register.add(q4);
         print_register(&register);
         print!("\n\n");
-        let vec = circuit.run_with_tracking(&mut register);
+        let vec = circuit.run_with_tracking(&mut register).unwrap();
         print_register(&register);
 
 
@@ -113,5 +645,226 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_run_returns_first_error() {
+        let mut circuit: QuantumCircuit = QuantumCircuit::new(Gate::new_multi_h(2), 2);
+        circuit.add_gate(Gate::new_multi_h(3));
+        circuit.add_gate(Gate::new_multi_h(2));
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 4, found: 8}), circuit.run(&mut register));
+        // Only the first gate ran, so the register is in an equal superposition
+        for i in 0..register.state.len(){
+            assert!((register.state[i].re - 0.5).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_builders() {
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(4);
+        circuit.h(0).cx(0, 3).t(1).p(2, 0.5);
+
+        let names: Vec<String> = circuit.instructions().iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(vec!["h 0", "cx 0 3", "t 1", "p(0.5000) 2"], names);
+        assert_eq!(vec![3], circuit.instructions()[1].targets);
+        assert_eq!(vec![Control::new(0)], circuit.instructions()[1].controls);
+
+        // Same circuit built from full register gates
+        let mut expanded: QuantumCircuit = QuantumCircuit::new(Gate::multi_single_qubit_gate(0, 4, Gate::new_h()).unwrap(), 4);
+        expanded.add_gate(Gate::new_multi_cnot(0, 3, 4).unwrap());
+        expanded.add_gate(Gate::multi_single_qubit_gate(1, 4, Gate::new_t()).unwrap());
+        expanded.add_gate(Gate::multi_single_qubit_gate(2, 4, Gate::new_phase_rotation(0.5 / (2. * std::f32::consts::PI))).unwrap());
+
+        let mut r1: QuantumRegister = QuantumRegister::new_from_vec(vec![nalgebra::Complex::from(1.); 16]);
+        let mut r2: QuantumRegister = r1.clone();
+        circuit.run(&mut r1).unwrap();
+        expanded.run(&mut r2).unwrap();
+        for i in 0..r1.state.len(){
+            assert!((r1.state[i] - r2.state[i]).norm_sqr() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_register_size_checked() {
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.h(0);
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        assert_eq!(Err(QuantumError::DimensionMismatch {expected: 3, found: 2}), circuit.run(&mut register));
+
+        circuit.h(3);
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 8);
+        assert_eq!(Err(QuantumError::QubitOutOfRange {qubit: 3, num_qubits: 3}), circuit.run(&mut register));
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_single_qubit_builders() {
+        // H = U(π/2, 0, π) and X = SX SX, so both circuits end in the same state
+        let pi: f32 = std::f32::consts::PI;
+        let mut c1: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        c1.h(0).sx(1).sx(1).s(0).sdg(0).t(1).tdg(1).rz(0, 0.3).rz(0, -0.3);
+        let mut c2: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        c2.u(0, pi / 2., 0., pi).x(1).rx(1, 0.4).rx(1, -0.4).ry(0, 1.2).ry(0, -1.2);
+
+        assert_eq!(vec!["h", "sx", "sx", "s", "sdg", "t", "tdg", "rz", "rz"], c1.instructions().iter().map(|instruction| instruction.name()).collect::<Vec<String>>());
+
+        let mut r1: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        let mut r2: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        c1.run(&mut r1).unwrap();
+        c2.run(&mut r2).unwrap();
+        for i in 0..r1.state.len(){
+            assert!((r1.state[i] - r2.state[i]).norm_sqr() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_multi_qubit_builders() {
+        // Every gate is checked against the same gate expanded to the whole register
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(4);
+        circuit.h(0).h(1).h(3).t(3).swap(3, 0).cz(1, 2).cp(2, 0, 0.7).iswap(1, 3).sqrt_swap(0, 2).ccx(3, 0, 2).cswap(1, 3, 0);
+
+        let mut expanded: QuantumCircuit = QuantumCircuit::new_with_qubits(4);
+        for (gate, targets) in [(Gate::new_h(), vec![0]), (Gate::new_h(), vec![1]), (Gate::new_h(), vec![3]), (Gate::new_t(), vec![3]),
+                                (Gate::new_swap(), vec![3, 0]), (Gate::new_cz(), vec![1, 2]), (Gate::new_controlled_phase(0.7 / (2. * std::f32::consts::PI)), vec![2, 0]),
+                                (Gate::new_iswap(), vec![1, 3]), (Gate::new_sqrt_swap(), vec![0, 2]), (Gate::new_toffoli(), vec![3, 0, 2]), (Gate::new_fredkin(), vec![1, 3, 0])]{
+            expanded.add_gate(gate.expand(&targets, 4).unwrap());
+        }
+
+        assert_eq!(vec!["h", "h", "h", "t", "swap", "cz", "cp", "iswap", "sqrt_swap", "ccx", "cswap"], circuit.instructions().iter().map(|instruction| instruction.name()).collect::<Vec<String>>());
+
+        let mut r1: QuantumRegister = QuantumRegister::new_from_int(5, 16);
+        let mut r2: QuantumRegister = QuantumRegister::new_from_int(5, 16);
+        circuit.run(&mut r1).unwrap();
+        expanded.run(&mut r2).unwrap();
+        for i in 0..r1.state.len(){
+            assert!((r1.state[i] - r2.state[i]).norm_sqr() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_controlled_builders() {
+        // Grover style oracle marking |0110>: flip the ancilla (qubit 4) only for that basis state
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(5);
+        circuit.mcx(&[Control::new_negated(0), Control::new(1), Control::new(2), Control::new_negated(3)], 4);
+        for input in 0..16{
+            let mut register: QuantumRegister = QuantumRegister::new_from_int(input << 1, 32);
+            circuit.run(&mut register).unwrap();
+            let expected: usize = if input == 0b0110 { (input << 1) | 1 } else { input << 1 };
+            assert!((register.state[expected].re - 1.).abs() < 1e-5);
+        }
+
+        // Controlled swap on a negated control only swaps when the control is |0>
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.controlled_unitary(Gate::new_swap(), &[Control::new_negated(2)], &[0, 1]);
+        assert_eq!("cunitary !2 0 1", circuit.instructions()[0].to_string());
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0b100, 8);
+        circuit.run(&mut register).unwrap();
+        assert!((register.state[0b010].re - 1.).abs() < 1e-5);
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0b101, 8);
+        circuit.run(&mut register).unwrap();
+        assert!((register.state[0b101].re - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_teleportation() {
+        // Teleport RY(θ)|0> from qubit 0 to qubit 2 using two measurements and classically conditioned corrections
+        let theta: f64 = 1.1;
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        let m0: usize = circuit.add_classical_register("m0", 1).unwrap();
+        let m1: usize = circuit.add_classical_register("m1", 1).unwrap();
+        circuit.ry(0, theta).h(1).cx(1, 2).barrier(&[0, 1, 2]);
+        circuit.cx(0, 1).h(0).measure(0, m0).measure(1, m1);
+        circuit.x(2).c_if("m1", 1);
+        circuit.z(2).c_if("m0", 1);
+
+        assert_eq!("if(m1==1) x 2", circuit.instructions()[8].to_string());
+
+        let mut rng: StdRng = StdRng::seed_from_u64(11);
+        let mut outcomes: Vec<u64> = vec![];
+        for _ in 0..40 {
+            let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 8);
+            let clbits: Vec<i32> = circuit.run_with_rng(&mut register, &mut rng).unwrap();
+            outcomes.push(circuit.classical_value(&clbits, "m0").unwrap() * 2 + circuit.classical_value(&clbits, "m1").unwrap());
+            let probability: f64 = register.qubit_probabilities()[2];
+            assert!((probability - (theta / 2.).sin().powi(2)).abs() < 1e-9);
+        }
+        // All four measurement outcomes come up and every one of them is corrected
+        for outcome in 0..4 {
+            assert!(outcomes.contains(&outcome));
+        }
+    }
+
+    #[test]
+    fn test_reset_and_classical_registers() {
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 2).unwrap();
+        assert_eq!(Err(QuantumError::DuplicateClassicalRegister("c".to_string())), circuit.add_classical_register("c", 1));
+        assert_eq!(Ok(1), circuit.clbit("c", 1));
+
+        circuit.x(0).x(1).measure(0, 0).reset(0).measure(0, 1).x(1).c_if("c", 1);
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0, 4);
+        let clbits: Vec<i32> = circuit.run(&mut register).unwrap();
+        assert_eq!(vec![1, 0], clbits);
+        assert_eq!(Ok(1), circuit.classical_value(&clbits, "c"));
+        // Qubit 0 was reset and qubit 1 was flipped back by the conditioned X
+        assert!((register.state[0].re - 1.).abs() < 1e-5);
+
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(1);
+        circuit.measure(0, 0);
+        assert_eq!(Err(QuantumError::ClassicalBitOutOfRange {bit: 0, num_bits: 0}), circuit.run(&mut QuantumRegister::new_from_int(0, 2)));
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(1);
+        circuit.x(0).c_if("missing", 0);
+        assert_eq!(Err(QuantumError::UnknownClassicalRegister("missing".to_string())), circuit.run(&mut QuantumRegister::new_from_int(0, 2)));
+
+        // Registers wider than a u64, too few classical bits and controlled measurements are errors rather than panics
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(1);
+        assert_eq!(Err(QuantumError::ClassicalRegisterTooLarge {name: "wide".to_string(), size: 65}), circuit.add_classical_register("wide", 65));
+        circuit.add_classical_register("c", 64).unwrap();
+        assert_eq!(Ok(u64::MAX), circuit.classical_value(&[1; 64], "c"));
+        assert_eq!(Err(QuantumError::ClassicalBitOutOfRange {bit: 63, num_bits: 0}), circuit.classical_value(&[], "c"));
+        let mut measure: Instruction = Instruction::new_measure(vec![0], vec![0]);
+        measure.controls.push(Control::new(0));
+        circuit.c_if("c", 0).add_instruction(measure);
+        assert_eq!(Err(QuantumError::UncontrollableOperation("measure".to_string())), circuit.run(&mut QuantumRegister::new_from_int(0, 2)));
+    }
+
+    #[test]
+    fn test_diagram() {
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 2).unwrap();
+        circuit.h(0).cx(0, 1).measure(0, 0).measure(1, 1);
+        let expected: &str = "\
+q0: -H--*--M----
+        |  |
+q1: ----X--+--M-
+           |  |
+c:  =======0==1=";
+        assert_eq!(expected, circuit.to_string());
+
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.add_classical_register("m", 1).unwrap();
+        circuit.rx(0, 0.5).mcx(&[Control::new_negated(0), Control::new(2)], 1).swap(0, 2).measure(2, 0);
+        circuit.x(0).c_if("m", 1).barrier(&[]).reset(1).iswap(2, 1).h(0);
+        let expected: &str = "\
+q0: -RX(0.5000)--o--x------X----|---H-----------
+                 |  |      |    |
+q1: -------------X--+------+----|--|0>--ISWAP:1-
+                 |  |      |    |          |
+q2: -------------*--x--M---+----|-------ISWAP:0-
+                       |   |
+m:  ===================0==[=1]==================";
+        assert_eq!(expected, circuit.to_string());
+
+        // Qubits out of range go on a marker wire instead of panicking
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 1).unwrap();
+        circuit.h(3).cx(0, 1).measure(5, 0).x(1);
+        let expected: &str = "\
+q0: ---*------------------
+       |
+q1: ---X----------X-------
+
+c:  ======================
+
+?:   ?h(q3)  ?measure(q5)";
+        assert_eq!(expected, circuit.to_string());
+    }
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+
+/**
+Errors that can come out of building or applying gates, matrices and registers
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuantumError {
+    // Two objects that have to agree in size do not (ex a 4x4 gate applied to a 3 qubit register)
+    DimensionMismatch { expected: usize, found: usize },
+    // Qubit index is negative or not smaller than the number of qubits in the register
+    QubitOutOfRange { qubit: i32, num_qubits: usize },
+    // The same qubit is used as a control and as a target of one gate
+    ControlEqualsTarget(i32),
+    // The same qubit appears more than once in the targets (or controls) of one gate
+    DuplicateQubit(i32),
+    // Matrix does not satisfy U * U^† = I
+    NonUnitaryMatrix,
+    // Operation has no matrix (ex measure or reset) but was used where a gate is needed
+    NonUnitaryOperation(String),
+    // Classical bit index is not smaller than the number of classical bits in the circuit
+    ClassicalBitOutOfRange { bit: usize, num_bits: usize },
+    // No classical register with this name exists in the circuit
+    UnknownClassicalRegister(String),
+    // A classical register with this name already exists in the circuit
+    DuplicateClassicalRegister(String),
+    // Qubit is entangled with the rest of the register so it can not be removed on its own
+    EntangledQubit(i32),
+    // OpenQASM source could not be read, line and column start at 1
+    QasmParse { line: usize, column: usize, message: String },
+    // Instruction has no OpenQASM form (ex a gate given as a matrix)
+    UnsupportedExport(String),
+    // Matrix is not a valid density matrix (not Hermitian, trace not 1 or a negative eigenvalue)
+    InvalidDensityMatrix(String),
+    // Kraus operators K_i do not satisfy Σ K_i^† K_i = I
+    NotTracePreserving,
+    // Probability or damping rate outside of [0, 1]
+    InvalidProbability(String),
+    // Histogram without shots or with malformed bitstrings
+    InvalidCounts(String),
+    // Matrix that has to be inverted is singular
+    SingularMatrix,
+    // Gate that a stabilizer simulation can not apply
+    NonCliffordGate(String),
+    // Bitstring with characters other than 0 and 1
+    InvalidBitstring(String),
+    // Classical register has more bits than fit in the u64 used for its value
+    ClassicalRegisterTooLarge { name: String, size: usize },
+    // Operation that can not be controlled was given control qubits (ex a measurement)
+    UncontrollableOperation(String),
+    // Relaxation times that no qubit can have (T1 or T2 not positive, negative gate time or T2 > 2 T1)
+    InvalidRelaxationTimes(String),
+    // More qubits than an object whose size is 2^n can index (ex a dense vector of 2^n entries)
+    TooManyQubits { num_qubits: usize, max: usize },
+}
+
+impl fmt::Display for QuantumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuantumError::DimensionMismatch { expected, found } => {
+                write!(f, "dimension mismatch: expected {}, found {}", expected, found)
+            }
+            QuantumError::QubitOutOfRange { qubit, num_qubits } => {
+                write!(f, "qubit {} is out of range for a register of {} qubits", qubit, num_qubits)
+            }
+            QuantumError::ControlEqualsTarget(qubit) => {
+                write!(f, "qubit {} is used as both a control and a target", qubit)
+            }
+            QuantumError::DuplicateQubit(qubit) => {
+                write!(f, "qubit {} is used more than once in the same gate", qubit)
+            }
+            QuantumError::NonUnitaryMatrix => {
+                write!(f, "matrix is not unitary")
+            }
+            QuantumError::NonUnitaryOperation(name) => {
+                write!(f, "{} is not a unitary gate", name)
+            }
+            QuantumError::ClassicalBitOutOfRange { bit, num_bits } => {
+                write!(f, "classical bit {} is out of range for a circuit with {} classical bits", bit, num_bits)
+            }
+            QuantumError::UnknownClassicalRegister(name) => {
+                write!(f, "no classical register named {}", name)
+            }
+            QuantumError::DuplicateClassicalRegister(name) => {
+                write!(f, "a classical register named {} already exists", name)
+            }
+            QuantumError::EntangledQubit(qubit) => {
+                write!(f, "qubit {} is entangled with the rest of the register", qubit)
+            }
+            QuantumError::QasmParse { line, column, message } => {
+                write!(f, "line {}, column {}: {}", line, column, message)
+            }
+            QuantumError::UnsupportedExport(name) => {
+                write!(f, "{} can not be written as OpenQASM", name)
+            }
+            QuantumError::InvalidDensityMatrix(reason) => {
+                write!(f, "invalid density matrix: {}", reason)
+            }
+            QuantumError::NotTracePreserving => {
+                write!(f, "kraus operators do not preserve the trace")
+            }
+            QuantumError::InvalidProbability(probability) => {
+                write!(f, "probability {} is not between 0 and 1", probability)
+            }
+            QuantumError::InvalidCounts(reason) => {
+                write!(f, "invalid counts: {}", reason)
+            }
+            QuantumError::SingularMatrix => {
+                write!(f, "matrix is not invertible")
+            }
+            QuantumError::NonCliffordGate(name) => {
+                write!(f, "{} is not a Clifford gate", name)
+            }
+            QuantumError::InvalidBitstring(bits) => {
+                write!(f, "invalid bitstring: {}", bits)
+            }
+            QuantumError::ClassicalRegisterTooLarge { name, size } => {
+                write!(f, "classical register {} has {} bits, at most 64 are supported", name, size)
+            }
+            QuantumError::UncontrollableOperation(name) => {
+                write!(f, "{} can not have control qubits", name)
+            }
+            QuantumError::InvalidRelaxationTimes(reason) => {
+                write!(f, "invalid relaxation times: {}", reason)
+            }
+            QuantumError::TooManyQubits { num_qubits, max } => {
+                write!(f, "{} qubits is more than the {} supported", num_qubits, max)
+            }
+        }
+    }
+}
+
+impl Error for QuantumError {}
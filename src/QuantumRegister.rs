@@ -1,33 +1,40 @@
+use std::collections::BTreeMap;
 use nalgebra::{Complex, DVector, Normed, Unit};
+use crate::Backend::Backend;
+use crate::Instruction::{Control, Instruction};
+use crate::Matrix::Matrix;
+use crate::Noise::KrausChannel;
+use crate::Precision::{Precision, real};
+use crate::QuantumError::QuantumError;
 use crate::Qubit::Qubit;
 use num_traits::One;
 use num_traits::Zero;
 use rand::Rng;
 
-pub struct QuantumRegister {
-    pub state: Unit<DVector<Complex<f32>>>
+pub struct QuantumRegister<T: Precision = f32> {
+    pub state: Unit<DVector<Complex<T>>>
 }
 
-impl QuantumRegister {
-    pub fn new(qubit: Qubit) -> Self {
+impl<T: Precision> QuantumRegister<T> {
+    pub fn new(qubit: Qubit<T>) -> Self {
         Self{
-            state: Unit::<DVector<Complex<f32>>>::new_normalize(DVector::from_vec(vec![qubit.state.x.into(), qubit.state.y.into()]))
+            state: Unit::<DVector<Complex<T>>>::new_normalize(DVector::from_vec(vec![qubit.state.x.into(), qubit.state.y.into()]))
         }
     }
 
     pub fn new_from_int(index: usize, size: usize) -> Self {
-        let mut state: Vec<Complex<f32>> = vec![Complex::zero() ; size];
+        let mut state: Vec<Complex<T>> = vec![Complex::zero() ; size];
         state[index] = Complex::one();
         Self {
-            state: Unit::<DVector<Complex<f32>>>::new_normalize(DVector::from_vec(state))
+            state: Unit::<DVector<Complex<T>>>::new_normalize(DVector::from_vec(state))
         }
     }
 
-    pub fn new_from_vec(vec: Vec<Complex<f32>>) -> Self {
-        Self {state: Unit::<DVector<Complex<f32>>>::new_normalize(DVector::from_vec(vec))}
+    pub fn new_from_vec(vec: Vec<Complex<T>>) -> Self {
+        Self {state: Unit::<DVector<Complex<T>>>::new_normalize(DVector::from_vec(vec))}
     }
 
-    pub fn new_from_unit_vec(vec: Unit<DVector<Complex<f32>>>) -> Self{
+    pub fn new_from_unit_vec(vec: Unit<DVector<Complex<T>>>) -> Self{
         Self {state: vec}
     }
 
@@ -36,81 +43,262 @@ impl QuantumRegister {
     Each entry from the register must be combined with each entry in the cubit by multiplication
     This is very inefficient (which should be obvious since this is not running on a quantum computer)
     */
-    pub fn add(&mut self, qubit: Qubit) {
-        let mut new_vec: Vec<Complex<f32>> = vec![Complex::zero(); self.state.len() * 2];
+    pub fn add(&mut self, qubit: Qubit<T>) {
+        let mut new_vec: Vec<Complex<T>> = vec![Complex::zero(); self.state.len() * 2];
         for i in 0..new_vec.len(){
             new_vec[i] = self.state[i/2] * qubit.state[i%2];
         }
-        self.state = Unit::<DVector<Complex<f32>>>::new_normalize(DVector::from_vec(new_vec));
+        self.state = Unit::<DVector<Complex<T>>>::new_normalize(DVector::from_vec(new_vec));
     }
+
+    /**
+    Add a new qubit so that it becomes qubit `position`, qubits from position onwards move up by one
+    Inserting at num_qubits() is the same as add
+    */
+    pub fn insert(&mut self, position: usize, qubit: Qubit<T>) -> Result<(), QuantumError> {
+        let num_qubits: usize = self.num_qubits();
+        if position > num_qubits {
+            return Err(QuantumError::QubitOutOfRange {qubit: position as i32, num_qubits})
+        }
+        // The new qubit is bit (num_qubits - position) of the new index, the bits below it keep their place
+        let shift: usize = num_qubits - position;
+        let low_mask: usize = (1 << shift) - 1;
+        let new_state: Vec<Complex<T>> = (0..self.state.len() * 2).map(|j| {
+            let old: usize = ((j >> (shift + 1)) << shift) | (j & low_mask);
+            self.state[old] * qubit.state[(j >> shift) & 1]
+        }).collect();
+        self.change_state(new_state);
+        Ok(())
+    }
+
+    /**
+    Put a single qubit back into |0> by measuring it and flipping it if it came out as 1
+    The rest of the register collapses the same way it would for measure_qubit
+    */
+    pub fn reset(&mut self, qubit: usize) -> Result<(), QuantumError> {
+        self.reset_with_rng(qubit, &mut rand::thread_rng())
+    }
+
+    pub fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        if self.measure_qubit_with_rng(qubit, rng)? == 1 {
+            let bit: usize = 1 << (self.num_qubits() - 1 - qubit);
+            let new_state: Vec<Complex<T>> = (0..self.state.len())
+                .map(|j| if j & bit == 0 { self.state[j | bit] } else { Complex::zero() })
+                .collect();
+            self.change_state(new_state);
+        }
+        Ok(())
+    }
+
+    /**
+    Apply a noise channel as one Monte-Carlo trajectory: a single Kraus operator K_i is drawn with probability ||K_i |ψ>||^2
+    and the state becomes K_i |ψ> normalized, averaging over many runs gives the channel's mixed state
+    */
+    pub fn apply_channel(&mut self, channel: &KrausChannel<T>, targets: &[usize]) -> Result<(), QuantumError> {
+        self.apply_channel_with_rng(channel, targets, &mut rand::thread_rng())
+    }
+
+    pub fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        let num_qubits: usize = self.num_qubits();
+        channel.check_placement(targets, num_qubits)?;
+        channel.apply_trajectory(self.state.as_mut_unchecked().as_mut_slice(), num_qubits, targets, rng);
+        Ok(())
+    }
+
+    /**
+    Trace out a qubit that is not entangled with the rest of the register and return it, the register shrinks by one qubit
+    Splitting the state into the part where the qubit is |0> (a0) and where it is |1> (a1), the qubit is unentangled
+    exactly when a0 and a1 are parallel, i.e. |<a0, a1>|^2 = |a0|^2 * |a1|^2 (equality in Cauchy-Schwarz)
+    Return EntangledQubit if that does not hold, a measured (or reset) qubit can always be removed
+    */
+    pub fn remove(&mut self, qubit: usize) -> Result<Qubit<T>, QuantumError> {
+        let num_qubits: usize = self.num_qubits();
+        if qubit >= num_qubits {
+            return Err(QuantumError::QubitOutOfRange {qubit: qubit as i32, num_qubits})
+        }
+        if num_qubits == 1 {
+            return Err(QuantumError::DimensionMismatch {expected: 2, found: 1})
+        }
+        let shift: usize = num_qubits - 1 - qubit;
+        let low_mask: usize = (1 << shift) - 1;
+        let split = |bit: usize| -> Vec<Complex<T>> {
+            (0..self.state.len() / 2).map(|k| self.state[((k >> shift) << (shift + 1)) | (bit << shift) | (k & low_mask)]).collect()
+        };
+        let (a0, a1): (Vec<Complex<T>>, Vec<Complex<T>>) = (split(0), split(1));
+        let inner = |u: &[Complex<T>], v: &[Complex<T>]| -> Complex<T> {
+            u.iter().zip(v.iter()).fold(Complex::zero(), |total, (x, y)| total + x.conj() * y)
+        };
+        let norm0: T = inner(&a0, &a0).re;
+        let norm1: T = inner(&a1, &a1).re;
+        if norm0 * norm1 - inner(&a0, &a1).norm_sqr() > real(1e-4) {
+            return Err(QuantumError::EntangledQubit(qubit as i32))
+        }
+
+        // The rest of the register is whichever half carries more weight, the qubit's amplitudes are the overlaps with it
+        let rest: Vec<Complex<T>> = if norm0 >= norm1 { a0 } else { a1 };
+        let alpha: Complex<T> = inner(&rest, &split(0));
+        let beta: Complex<T> = inner(&rest, &split(1));
+        self.change_state(rest);
+        Ok(Qubit::new_from_vec(vec![alpha, beta]))
+    }
+
     /**
     Measure the quantum register and collapse it's state onto the outcome of the measurement
+    Each basis state comes out with probability |amplitude|^2 (Born rule)
     Return an integer corresponding to the state of the qubits in the classical state
     */
     pub fn measure(&mut self) -> i32 {
-        let rng = rand::thread_rng().gen_range(0. .. 1.);
-        let mut current_probability = 0.;
-        let mut new_state: Vec<Complex<f32>> = vec![];
-        for i in 0..self.state.len(){
-            let norm: f32 = self.state[i].norm();
-            current_probability += norm;
-            if current_probability >= rng{
-                new_state.push(Complex::one());
-                new_state.append(&mut vec![Complex::zero() ; self.state.len() - i - 1]);
-                self.change_state(new_state);
-                return i as i32
-            } else {
-                new_state.push(Complex::zero());
-            }
-        }
-        -1
+        self.measure_with_rng(&mut rand::thread_rng())
+    }
+
+    /**
+    Same as measure but draws from the given random number generator, so a seeded generator gives reproducible outcomes
+    */
+    pub fn measure_with_rng<R: Rng + ?Sized>(&mut self, rng: &mut R) -> i32 {
+        let index: usize = self.peak_with_rng(rng) as usize;
+        let mut new_state: Vec<Complex<T>> = vec![Complex::zero(); self.state.len()];
+        new_state[index] = Complex::one();
+        self.change_state(new_state);
+        index as i32
     }
+
     /**
     Peak at a possible measurement value of the state of the qubits but don't collapse the state
     */
     pub fn peak(&self) -> i32 {
-        let rng = rand::thread_rng().gen_range(0. .. 1.);
-        let mut current_probability = 0.;
-        for i in 0..self.state.len(){
-            let norm: f32 = self.state[i].norm();
-            current_probability += norm;
-            if current_probability >= rng{
-                return i as i32
+        self.peak_with_rng(&mut rand::thread_rng())
+    }
+
+    pub fn peak_with_rng<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        draw_outcome(&self.probabilities(), rng) as i32
+    }
+
+    /**
+    Measure a single qubit and collapse only the part of the state that disagrees with the outcome
+    Return 0 or 1, the rest of the register stays in superposition
+    */
+    pub fn measure_qubit(&mut self, qubit: usize) -> Result<i32, QuantumError> {
+        self.measure_qubit_with_rng(qubit, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubit_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<i32, QuantumError> {
+        Ok(self.measure_qubits_with_rng(&[qubit], rng)?[0])
+    }
+
+    /**
+    Measure the given qubits together and collapse the register onto the outcome
+    The outcome is drawn from the joint probability of the measured qubits, every amplitude that disagrees with it is set to 0
+    and the remaining amplitudes are renormalized, leaving the unmeasured qubits in superposition
+    Return the classical bit of each measured qubit in the order they were given
+    */
+    pub fn measure_qubits(&mut self, qubits: &[usize]) -> Result<Vec<i32>, QuantumError> {
+        self.measure_qubits_with_rng(qubits, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        let num_qubits: usize = self.num_qubits();
+        for (i, &qubit) in qubits.iter().enumerate() {
+            if qubit >= num_qubits {
+                return Err(QuantumError::QubitOutOfRange {qubit: qubit as i32, num_qubits})
+            }
+            if qubits[..i].contains(&qubit) {
+                return Err(QuantumError::DuplicateQubit(qubit as i32))
             }
         }
-        -1
+
+        // Joint probability of every outcome of the measured qubits, the first qubit being the most significant bit
+        let outcome_of = |j: usize| -> usize {
+            qubits.iter().fold(0, |outcome, qubit| (outcome << 1) | ((j >> (num_qubits - 1 - qubit)) & 1))
+        };
+        let mut probabilities: Vec<T> = vec![T::zero(); 1 << qubits.len()];
+        for (j, probability) in self.probabilities().into_iter().enumerate() {
+            probabilities[outcome_of(j)] += probability;
+        }
+        let outcome: usize = draw_outcome(&probabilities, rng);
+
+        let new_state: Vec<Complex<T>> = self.state.iter().enumerate()
+            .map(|(j, &amplitude)| if outcome_of(j) == outcome { amplitude } else { Complex::zero() })
+            .collect();
+        self.change_state(new_state);
+
+        Ok((0..qubits.len()).map(|i| ((outcome >> (qubits.len() - 1 - i)) & 1) as i32).collect())
+    }
+
+    /**
+    Probability of each basis state coming out of a measurement of the whole register (Born rule, |amplitude|^2)
+    */
+    pub fn probabilities(&self) -> Vec<T> {
+        self.state.iter().map(|amplitude| amplitude.norm_sqr()).collect()
     }
 
     /**
-    Gets the state of each individual qubit by adding the norm of each element in the register that corresponds to each qubit
-    Each element in a register is a product of some qubit state vector elements (i.e. (x1y1, x1y2, x2y1, x2y2))
-    Every x1 is in the 1st half, and every x2 is in the 2nd half. Every y1 is in the first and 3rd quarter, every y2 is in the 2nd and 4th quarter
-    The position of each state element alternates every k/(2^i) where k is the length of the register and i is the ith qubit added
-    After a gate is applied, the state is a linear combination of all of the elements of the register, which are all products of the original qubit state elements
-    Factoring out each qubit state element and taking the sum of the norms of its positions in the register following the alternating rule will give the qubit state element after the gate has been applied
+    Measure the whole register shots many times without collapsing it, returning how often each outcome came up
+    Outcomes are bitstrings with qubit 0 first (ex "011" means qubit 0 is 0 and qubits 1 and 2 are 1), only outcomes that came up are present
+    The cumulative distribution is built once, so each shot only costs a binary search
     */
-    pub fn get_qubit_norms(&self) -> Vec<Qubit> {
-        let mut vec: Vec<Qubit> = vec![];
-        for i in 0..(self.state.len() as f32).log2() as i32{
-            let mut x1: Complex<f32> = Complex::zero();
-            let mut x2: Complex<f32> = Complex::zero();
-            for j in 0..self.state.len(){
-                // Need to access pow method
-                let d: i32 = 2;
-                let divider: i32 = self.state.len() as i32 / d.pow(i as u32 + 1);
+    pub fn sample(&self, shots: usize) -> BTreeMap<String, usize> {
+        self.sample_with_rng(shots, &mut rand::thread_rng())
+    }
 
-                print!("Condition: {}\n", j as i32 / divider);
+    pub fn sample_with_rng<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for index in self.sample_indices(shots, rng) {
+            *counts.entry(self.bitstring(index)).or_insert(0) += 1;
+        }
+        counts
+    }
 
-                if (j as i32 / divider) % 2 == 0{
-                    x1 += self.state[j].norm();
-                }
-                else {
-                    x2 += self.state[j].norm();
+    /**
+    Same as sample but returns the outcome of every shot in the order they were drawn
+    */
+    pub fn sample_memory(&self, shots: usize) -> Vec<String> {
+        self.sample_memory_with_rng(shots, &mut rand::thread_rng())
+    }
+
+    pub fn sample_memory_with_rng<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> Vec<String> {
+        self.sample_indices(shots, rng).into_iter().map(|index| self.bitstring(index)).collect()
+    }
+
+    /**
+    Bitstring of a basis state index for this register, qubit 0 first
+    */
+    pub fn bitstring(&self, index: usize) -> String {
+        format!("{:0width$b}", index, width = self.num_qubits())
+    }
+
+    // Basis state index of each shot
+    fn sample_indices<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> Vec<usize> {
+        sample_indices(&self.probabilities(), shots, rng)
+    }
+
+    /**
+    Probability of each qubit being measured as 1 on its own, qubit 0 first
+    Qubit q is 1 in every basis state whose bit (n - 1 - q) is set, so its probability is the sum of |amplitude|^2 over those states
+    */
+    pub fn qubit_probabilities(&self) -> Vec<T> {
+        let num_qubits: usize = self.num_qubits();
+        let mut ones: Vec<T> = vec![T::zero(); num_qubits];
+        for (j, probability) in self.probabilities().into_iter().enumerate() {
+            for (qubit, one) in ones.iter_mut().enumerate() {
+                if (j >> (num_qubits - 1 - qubit)) & 1 == 1 {
+                    *one += probability;
                 }
             }
-            vec.push(Qubit::new_from_vec(vec![x1, x2]))
         }
-        vec
+        ones
+    }
+
+    /**
+    Gets the state of each individual qubit from its marginal probabilities
+    Each qubit is returned as (sqrt(P(0)), sqrt(P(1))), so measuring it on its own gives the same statistics as measuring it inside the register
+    Relative phases and entanglement between qubits can not be represented by separate qubits and are dropped
+    */
+    pub fn get_qubit_norms(&self) -> Vec<Qubit<T>> {
+        let total: T = self.probabilities().iter().fold(T::zero(), |total, &probability| total + probability);
+        self.qubit_probabilities().into_iter().map(|one| {
+            let zero: T = total - one;
+            Qubit::new_from_vec(vec![Complex::from(zero.max(T::zero()).sqrt()), Complex::from(one.max(T::zero()).sqrt())])
+        }).collect()
     }
 
     /**
@@ -118,22 +306,142 @@ impl QuantumRegister {
     Register must be filled before being used
     */
     pub fn new_empty(size: usize) -> Self {
-        let vec: Vec<Complex<f32>> = vec![Complex::one(); size];
-        Self {state: Unit::<DVector<Complex<f32>>>::new_normalize(DVector::from_vec(vec))}
+        let vec: Vec<Complex<T>> = vec![Complex::one(); size];
+        Self {state: Unit::<DVector<Complex<T>>>::new_normalize(DVector::from_vec(vec))}
     }
 
-    pub fn change_state(&mut self, new_state: Vec<Complex<f32>>){
-        self.state = Unit::<DVector<Complex<f32>>>::new_normalize(DVector::from_vec(new_state));
+    pub fn change_state(&mut self, new_state: Vec<Complex<T>>){
+        self.state = Unit::<DVector<Complex<T>>>::new_normalize(DVector::from_vec(new_state));
     }
 
-    pub fn clone(&self) -> QuantumRegister {
+    pub fn clone(&self) -> QuantumRegister<T> {
         QuantumRegister::new_from_unit_vec(self.state.clone())
     }
+
+    /**
+    Number of qubits held by the register, the state vector has 2^n entries
+    */
+    pub fn num_qubits(&self) -> usize {
+        self.state.len().trailing_zeros() as usize
+    }
+
+    /**
+    Apply a small 2^k x 2^k matrix to the k target qubits without building the full 2^n x 2^n gate
+    Qubit 0 is the left most factor of the kronecker product, so qubit q corresponds to bit (n - 1 - q) of a state index
+    Every index with all target bits cleared (and every control bit equal to its control value) is the base of one group of 2^k amplitudes
+    Each group is gathered, multiplied by the matrix and written back in place, giving O(2^n * 4^k) work overall
+    The first target qubit is the most significant bit of the local matrix index, matching the tensor order of the gate
+    */
+    pub(crate) fn apply_local_matrix(&mut self, matrix: &Matrix<T>, targets: &[usize], controls: &[Control]) {
+        let num_qubits: usize = self.num_qubits();
+        apply_local_matrix(self.state.as_mut_unchecked().as_mut_slice(), num_qubits, matrix, targets, controls);
+    }
+}
+
+impl<T: Precision> Backend<T> for QuantumRegister<T> {
+    fn num_qubits(&self) -> usize {
+        QuantumRegister::num_qubits(self)
+    }
+
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+        instruction.apply(self)
+    }
+
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        QuantumRegister::measure_qubits_with_rng(self, qubits, rng)
+    }
+
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        QuantumRegister::apply_channel_with_rng(self, channel, targets, rng)
+    }
+
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        QuantumRegister::reset_with_rng(self, qubit, rng)
+    }
+}
+
+/**
+Kernel of QuantumRegister::apply_local_matrix working on any vector of 2^n amplitudes
+The matrix does not have to be unitary, so it is also used for the columns of density matrices and for Kraus operators
+*/
+pub(crate) fn apply_local_matrix<T: Precision>(state: &mut [Complex<T>], num_qubits: usize, matrix: &Matrix<T>, targets: &[usize], controls: &[Control]) {
+    let dim: usize = matrix.rows.len();
+
+    // Offset of every local basis state inside the full state vector
+    let mut offsets: Vec<usize> = vec![0; dim];
+    for (local, offset) in offsets.iter_mut().enumerate() {
+        for (j, target) in targets.iter().enumerate() {
+            if (local >> (targets.len() - 1 - j)) & 1 == 1 {
+                *offset |= 1 << (num_qubits - 1 - target);
+            }
+        }
+    }
+    let target_mask: usize = offsets[dim - 1];
+    let control_mask: usize = controls.iter().fold(0, |mask, control| mask | 1 << (num_qubits - 1 - control.qubit));
+    let control_value: usize = controls.iter().filter(|control| control.on_one).fold(0, |mask, control| mask | 1 << (num_qubits - 1 - control.qubit));
+
+    let mut group: Vec<Complex<T>> = vec![Complex::zero(); dim];
+    for base in 0..state.len() {
+        if base & target_mask != 0 || base & control_mask != control_value {
+            continue
+        }
+        for (local, offset) in offsets.iter().enumerate() {
+            group[local] = state[base | offset];
+        }
+        for (row, offset) in offsets.iter().enumerate() {
+            let mut new_val: Complex<T> = Complex::zero();
+            for (col, val) in group.iter().enumerate() {
+                new_val += matrix.rows[row][col] * val;
+            }
+            state[base | offset] = new_val;
+        }
+    }
+}
+
+/**
+Basis state index of each of shots draws, index i coming out with probability probabilities[i] / sum(probabilities)
+*/
+pub(crate) fn sample_indices<T: Precision, R: Rng + ?Sized>(probabilities: &[T], shots: usize, rng: &mut R) -> Vec<usize> {
+    let mut cumulative: Vec<T> = Vec::with_capacity(probabilities.len());
+    let mut total: T = T::zero();
+    for &probability in probabilities.iter() {
+        total += probability;
+        cumulative.push(total);
+    }
+
+    let mut indices: Vec<usize> = Vec::with_capacity(shots);
+    for _ in 0..shots {
+        // Scale by the total so rounding error in the norm can never push the draw past the last entry
+        let draw: T = real::<T>(rng.gen_range(0. .. 1.)) * total;
+        let index: usize = cumulative.partition_point(|&probability| probability <= draw);
+        indices.push(index.min(probabilities.len() - 1));
+    }
+    indices
+}
+
+/**
+Pick an outcome at random, outcome i having probability probabilities[i] / sum(probabilities)
+The draw is scaled by the total so rounding error in the norm can never push it past the last outcome
+*/
+pub(crate) fn draw_outcome<T: Precision, R: Rng + ?Sized>(probabilities: &[T], rng: &mut R) -> usize {
+    let total: T = probabilities.iter().fold(T::zero(), |total, &probability| total + probability);
+    let draw: T = real::<T>(rng.gen_range(0. .. 1.)) * total;
+    let mut current_probability: T = T::zero();
+    for (i, &probability) in probabilities.iter().enumerate() {
+        current_probability += probability;
+        if current_probability > draw {
+            return i
+        }
+    }
+    // Only reachable through rounding, the last outcome with a non zero probability is the right answer
+    probabilities.iter().rposition(|probability| !probability.is_zero()).unwrap_or(0)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
 
     #[test]
     fn test_init() {
@@ -171,4 +479,204 @@ mod tests {
 
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bitstring() {
+        let register: QuantumRegister = QuantumRegister::new_from_int(3, 8);
+        assert_eq!("011", register.bitstring(3));
+        assert_eq!("100", register.bitstring(4));
+    }
+
+    #[test]
+    fn test_sample_basis_state() {
+        let register: QuantumRegister = QuantumRegister::new_from_int(5, 8);
+        let counts: BTreeMap<String, usize> = register.sample(100);
+        assert_eq!(Some(&100), counts.get("101"));
+        assert_eq!(1, counts.len());
+        assert_eq!(vec!["101".to_string(); 10], register.sample_memory(10));
+    }
+
+    #[test]
+    fn test_sample_distribution() {
+        // Amplitudes (1, 0, 1, sqrt(2)) / 2 give probabilities 1/4, 0, 1/4, 1/2
+        let register: QuantumRegister<f64> = QuantumRegister::new_from_vec(vec![Complex::one(), Complex::zero(), Complex::one(), Complex::from(2f64.sqrt())]);
+        let shots: usize = 20000;
+        let counts: BTreeMap<String, usize> = register.sample(shots);
+        assert_eq!(None, counts.get("01"));
+        assert_eq!(shots, counts.values().sum::<usize>());
+        // 5 standard deviations of a binomial with 20000 shots is under 0.02
+        for (outcome, expected) in [("00", 0.25), ("10", 0.25), ("11", 0.5)] {
+            let frequency: f64 = counts[outcome] as f64 / shots as f64;
+            assert!((frequency - expected).abs() < 0.02, "{} came up with frequency {}", outcome, frequency);
+        }
+        // Sampling does not collapse the register
+        assert!((register.state[3].re - 2f64.sqrt() / 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_measure_plus_state() {
+        // |+> used to come out as |0> about 71% of the time
+        let plus: QuantumRegister = QuantumRegister::new_from_vec(vec![Complex::one(); 2]);
+        let shots: usize = 20000;
+        let mut zeros: usize = 0;
+        let mut rng: StdRng = StdRng::seed_from_u64(13);
+        for _ in 0..shots {
+            let mut register: QuantumRegister = plus.clone();
+            if register.measure_with_rng(&mut rng) == 0 {
+                zeros += 1;
+                assert_eq!(Complex::one(), register.state[0]);
+            }
+        }
+        assert!((zeros as f32 / shots as f32 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_peak_distribution() {
+        // Probabilities 0.1, 0.2, 0.3, 0.4
+        let register: QuantumRegister = QuantumRegister::new_from_vec(vec![Complex::from(0.1f32.sqrt()), Complex::from(0.2f32.sqrt()), Complex::from(0.3f32.sqrt()), Complex::from(0.4f32.sqrt())]);
+        let shots: usize = 20000;
+        let mut counts: Vec<usize> = vec![0; 4];
+        let mut rng: StdRng = StdRng::seed_from_u64(14);
+        for _ in 0..shots {
+            counts[register.peak_with_rng(&mut rng) as usize] += 1;
+        }
+        for (i, count) in counts.iter().enumerate() {
+            let expected: f32 = 0.1 * (i + 1) as f32;
+            assert!((*count as f32 / shots as f32 - expected).abs() < 0.02, "State {} came up {} times", i, count);
+        }
+    }
+
+    #[test]
+    fn test_qubit_probabilities() {
+        // (|00> + |01> + |11>) / sqrt(3): qubit 0 is 1 with probability 1/3, qubit 1 with probability 2/3
+        let register: QuantumRegister = QuantumRegister::new_from_vec(vec![Complex::one(), Complex::one(), Complex::zero(), Complex::one()]);
+        let probabilities: Vec<f32> = register.qubit_probabilities();
+        assert!((probabilities[0] - 1. / 3.).abs() < 1e-5);
+        assert!((probabilities[1] - 2. / 3.).abs() < 1e-5);
+
+        let qubits: Vec<Qubit> = register.get_qubit_norms();
+        assert!((qubits[0].state.x.norm_sqr() - 2. / 3.).abs() < 1e-5);
+        assert!((qubits[0].state.y.norm_sqr() - 1. / 3.).abs() < 1e-5);
+        assert!((qubits[1].state.y.norm_sqr() - 2. / 3.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_measure_qubit_partial_collapse() {
+        // (|00> + |11>) / sqrt(2) tensored with |+>: measuring qubit 0 fixes qubit 1 but leaves qubit 2 alone
+        let half: Complex<f32> = Complex::from(0.5);
+        let state: Vec<Complex<f32>> = vec![half, half, Complex::zero(), Complex::zero(), Complex::zero(), Complex::zero(), half, half];
+        for _ in 0..20 {
+            let mut register: QuantumRegister = QuantumRegister::new_from_vec(state.clone());
+            let bit: i32 = register.measure_qubit(0).unwrap();
+            let base: usize = if bit == 0 { 0b000 } else { 0b110 };
+            let amplitude: f32 = 1. / 2f32.sqrt();
+            assert!((register.state[base].re - amplitude).abs() < 1e-5);
+            assert!((register.state[base | 1].re - amplitude).abs() < 1e-5);
+            assert_eq!(vec![bit], register.measure_qubits(&[1]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_measure_qubits_statistics() {
+        // Probabilities 0.1, 0.2, 0.3, 0.4 on 2 qubits, qubit 1 alone is 1 with probability 0.6
+        let state: Vec<Complex<f32>> = vec![Complex::from(0.1f32.sqrt()), Complex::from(0.2f32.sqrt()), Complex::from(0.3f32.sqrt()), Complex::from(0.4f32.sqrt())];
+        let shots: usize = 20000;
+        let mut ones: usize = 0;
+        let mut rng: StdRng = StdRng::seed_from_u64(16);
+        for _ in 0..shots {
+            let mut register: QuantumRegister = QuantumRegister::new_from_vec(state.clone());
+            let bits: Vec<i32> = register.measure_qubits_with_rng(&[1], &mut rng).unwrap();
+            ones += bits[0] as usize;
+            // Qubit 0 stays in superposition with the right conditional amplitudes
+            let (zero, one): (f32, f32) = if bits[0] == 1 { (0.2 / 0.6, 0.4 / 0.6) } else { (0.1 / 0.4, 0.3 / 0.4) };
+            assert!((register.state[bits[0] as usize].norm_sqr() - zero).abs() < 1e-5);
+            assert!((register.state[2 | bits[0] as usize].norm_sqr() - one).abs() < 1e-5);
+        }
+        assert!((ones as f32 / shots as f32 - 0.6).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_measure_qubits_order_and_errors() {
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(0b110, 8);
+        assert_eq!(vec![0, 1, 1], register.measure_qubits(&[2, 0, 1]).unwrap());
+        assert_eq!(Err(QuantumError::QubitOutOfRange {qubit: 3, num_qubits: 3}), register.measure_qubit(3));
+        assert_eq!(Err(QuantumError::DuplicateQubit(1)), register.measure_qubits(&[1, 1]));
+    }
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let state: Vec<Complex<f32>> = vec![Complex::one(), Complex::i(), Complex::from(2.), Complex::one(), Complex::zero(), Complex::one(), Complex::from(-1.), Complex::i()];
+        let register: QuantumRegister = QuantumRegister::new_from_vec(state);
+
+        let mut rng1: StdRng = StdRng::seed_from_u64(7);
+        let mut rng2: StdRng = StdRng::seed_from_u64(7);
+        assert_eq!(register.sample_memory_with_rng(200, &mut rng1), register.sample_memory_with_rng(200, &mut rng2));
+        assert_eq!(register.sample_with_rng(500, &mut rng1), register.sample_with_rng(500, &mut rng2));
+
+        let mut results1: Vec<i32> = vec![];
+        let mut results2: Vec<i32> = vec![];
+        for (rng, results) in [(&mut rng1, &mut results1), (&mut rng2, &mut results2)] {
+            for _ in 0..50 {
+                let mut copy: QuantumRegister = register.clone();
+                results.push(copy.peak_with_rng(rng));
+                results.push(copy.measure_qubit_with_rng(1, rng).unwrap());
+                results.extend(copy.measure_qubits_with_rng(&[2, 0], rng).unwrap());
+                results.push(copy.measure_with_rng(rng));
+            }
+        }
+        assert_eq!(results1, results2);
+    }
+
+    #[test]
+    fn test_insert_qubit() {
+        // |01> with a |1> inserted in the middle becomes |011>, inserting at the end matches add
+        let mut register: QuantumRegister = QuantumRegister::new_from_int(1, 4);
+        register.insert(1, Qubit::new_one_state()).unwrap();
+        assert_eq!(3, register.num_qubits());
+        assert!((register.state[3].re - 1.).abs() < 1e-6);
+        register.insert(0, Qubit::new_one_state()).unwrap();
+        assert!((register.state[11].re - 1.).abs() < 1e-6);
+
+        let mut added: QuantumRegister = QuantumRegister::new_from_int(2, 4);
+        let mut inserted: QuantumRegister = added.clone();
+        added.add(Qubit::new_from_vec(vec![Complex::from(1.), Complex::i()]));
+        inserted.insert(2, Qubit::new_from_vec(vec![Complex::from(1.), Complex::i()])).unwrap();
+        for i in 0..added.state.len() {
+            assert!((added.state[i] - inserted.state[i]).norm_sqr() < 1e-10);
+        }
+        assert_eq!(Err(QuantumError::QubitOutOfRange {qubit: 4, num_qubits: 3}), QuantumRegister::<f32>::new_from_int(0, 8).insert(4, Qubit::new_zero_state()));
+    }
+
+    #[test]
+    fn test_reset_qubit() {
+        // Qubit 0 of (|00> + |11>) / sqrt(2) is reset, which leaves qubit 1 in whatever the measurement gave
+        let mut rng: StdRng = StdRng::seed_from_u64(5);
+        for _ in 0..20 {
+            let mut register: QuantumRegister = QuantumRegister::new_from_vec(vec![Complex::one(), Complex::zero(), Complex::zero(), Complex::one()]);
+            register.reset_with_rng(0, &mut rng).unwrap();
+            assert!(register.qubit_probabilities()[0] < 1e-6);
+            assert!((register.state[0].norm_sqr() - 1.).abs() < 1e-6 || (register.state[1].norm_sqr() - 1.).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_remove_unentangled_qubit() {
+        // (|0> + i|1>) / sqrt(2) inserted as qubit 1 of |01> comes back out unchanged
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(1, 4);
+        register.insert(1, Qubit::new_from_vec(vec![Complex::one(), Complex::i()])).unwrap();
+        let qubit: Qubit<f64> = register.remove(1).unwrap();
+        assert_eq!(2, register.num_qubits());
+        assert!((register.state[1].norm_sqr() - 1.).abs() < 1e-9);
+        // Only the relative phase between |0> and |1> is meaningful
+        let relative: Complex<f64> = qubit.state.y / qubit.state.x;
+        assert!((relative - Complex::i()).norm_sqr() < 1e-9);
+
+        // A bell pair can not be split, but after measuring one side it can
+        let mut bell: QuantumRegister<f64> = QuantumRegister::new_from_vec(vec![Complex::one(), Complex::zero(), Complex::zero(), Complex::one()]);
+        assert_eq!(Some(QuantumError::EntangledQubit(0)), bell.remove(0).err());
+        let outcome: i32 = bell.measure_qubit(0).unwrap();
+        let qubit: Qubit<f64> = bell.remove(0).unwrap();
+        assert!((qubit.state[outcome as usize].norm_sqr() - 1.).abs() < 1e-9);
+        assert!((bell.state[outcome as usize].norm_sqr() - 1.).abs() < 1e-9);
+    }
+}
@@ -1,14 +1,15 @@
 use nalgebra::{Complex, Vector2, UnitVector2, Normed};
 use num_traits::identities::One;
 use num_traits::Zero;
+use crate::Precision::{Precision, real};
 use crate::Quaternion::Quaternion;
 use rand::Rng;
 
-pub struct Qubit {
-    pub state: UnitVector2<Complex<f32>>
+pub struct Qubit<T: Precision = f32> {
+    pub state: UnitVector2<Complex<T>>
 }
 
-impl Qubit {
+impl<T: Precision> Qubit<T> {
 
     pub fn new_zero_state() -> Self {
         Self {state: UnitVector2::new_normalize(Vector2::new(Complex::one(), Complex::zero()))}
@@ -18,7 +19,7 @@ impl Qubit {
         Self {state: UnitVector2::new_normalize(Vector2::new(Complex::zero(), Complex::one()))}
     }
 
-    pub fn new_from_vec(vec: Vec<Complex<f32>>) -> Self {
+    pub fn new_from_vec(vec: Vec<Complex<T>>) -> Self {
         Self {state: UnitVector2::new_normalize(Vector2::from_vec(vec))}
     }
 
@@ -35,8 +36,8 @@ impl Qubit {
     |0> => (1, 0), |00> => (1,0,0,0), |00> = |0>⊗|0>, |1> = (0,1)
     |j> = tensor product of all of it's binary digits
     */
-    pub fn get_qubits_from_state(mut j: i32, mut num_qubits: i32) -> Vec<Qubit> {
-        let mut vec: Vec<Qubit> = vec![];
+    pub fn get_qubits_from_state(mut j: i32, mut num_qubits: i32) -> Vec<Qubit<T>> {
+        let mut vec: Vec<Qubit<T>> = vec![];
         let total_qubits: i32 = num_qubits.clone();
         while j > 0 && vec.len() < total_qubits as usize {
             let cur = j & 1;
@@ -57,20 +58,25 @@ impl Qubit {
 
     /**
     Measure individual qubit and collapse quantum state
+    The qubit is 0 with probability |x|^2 (Born rule)
     return a zero or 1 corresponding to it's classical state
     */
-
     pub fn measure(&mut self) -> i32{
-        let rng = rand::thread_rng().gen_range(0. .. 1.);
-        return if rng <= self.state.x.norm() {
+        self.measure_with_rng(&mut rand::thread_rng())
+    }
+
+    /**
+    Same as measure but draws from the given random number generator, so a seeded generator gives reproducible outcomes
+    */
+    pub fn measure_with_rng<R: Rng + ?Sized>(&mut self, rng: &mut R) -> i32 {
+        let rng: T = real(rng.gen_range(0. .. 1.));
+        if rng < self.state.x.norm_sqr() {
             self.state = UnitVector2::new_normalize(Vector2::new(Complex::one(), Complex::zero()));
             0
         } else {
             self.state = UnitVector2::new_normalize(Vector2::new(Complex::zero(), Complex::one()));
             1
         }
-        // Something goofy happened if you get here
-        -1
     }
 
 
@@ -79,6 +85,8 @@ impl Qubit {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
 
     #[test]
     fn test_init() {
@@ -107,4 +115,33 @@ mod tests {
             print!("State: ({}, {})\n", vec[i].state.x, vec[i].state.y);
         }
     }
+
+    #[test]
+    fn test_measure_statistics() {
+        // Amplitudes (sqrt(3), 1) / 2 give |0> with probability 3/4, not sqrt(3)/2
+        let shots: usize = 20000;
+        let mut zeros: usize = 0;
+        let mut rng: StdRng = StdRng::seed_from_u64(15);
+        for _ in 0..shots {
+            let mut qubit: Qubit = Qubit::new_from_vec(vec![Complex::from(3f32.sqrt()), Complex::one()]);
+            if qubit.measure_with_rng(&mut rng) == 0 {
+                zeros += 1;
+            }
+        }
+        assert!((zeros as f32 / shots as f32 - 0.75).abs() < 0.02);
+
+        assert_eq!(0, Qubit::<f32>::new_zero_state().measure());
+        assert_eq!(1, Qubit::<f32>::new_one_state().measure());
+    }
+
+    #[test]
+    fn test_measure_with_seed() {
+        use rand::SeedableRng;
+        let mut rng1: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(3);
+        let mut rng2: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(3);
+        let outcomes1: Vec<i32> = (0..100).map(|_| Qubit::<f32>::new_from_vec(vec![Complex::one(); 2]).measure_with_rng(&mut rng1)).collect();
+        let outcomes2: Vec<i32> = (0..100).map(|_| Qubit::<f32>::new_from_vec(vec![Complex::one(); 2]).measure_with_rng(&mut rng2)).collect();
+        assert_eq!(outcomes1, outcomes2);
+        assert!(outcomes1.contains(&0) && outcomes1.contains(&1));
+    }
 }
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use rand::Rng;
+use crate::Noise::check_probability;
+use crate::Precision::{Precision, real};
+use crate::QuantumCircuit::QuantumCircuit;
+use crate::QuantumError::QuantumError;
+
+/**
+Readout errors of a device, independent for every qubit
+Qubit q is read as 1 when it is |0> with probability p(1|0) and read as 0 when it is |1> with probability p(0|1)
+The confusion matrix M of the whole register (M[measured][prepared]) is the tensor product of the 2x2 matrices
+[[1 - p(1|0), p(0|1)], [p(1|0), 1 - p(0|1)]] of the qubits, so measured counts are M times the counts without readout errors
+Bitstrings are written qubit 0 first like in QuantumRegister::sample
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadoutError<T: Precision = f32> {
+    // (p(1|0), p(0|1)) of each qubit
+    flips: Vec<(T, T)>,
+}
+
+impl<T: Precision> ReadoutError<T> {
+    // Readout error given by (p(1|0), p(0|1)) for each qubit, at least one
+    pub fn new(flips: Vec<(T, T)>) -> Result<Self, QuantumError> {
+        if flips.is_empty() {
+            return Err(QuantumError::DimensionMismatch {expected: 1, found: 0})
+        }
+        for &(zero_to_one, one_to_zero) in flips.iter() {
+            check_probability(zero_to_one)?;
+            check_probability(one_to_zero)?;
+        }
+        Ok(Self {flips})
+    }
+
+    // Every qubit misread with the same probability, whatever its state
+    pub fn new_uniform(num_qubits: usize, probability: T) -> Result<Self, QuantumError> {
+        Self::new(vec![(probability, probability); num_qubits])
+    }
+
+    /**
+    Calibrate from the counts of the two circuits of calibration_circuits, which prepare |0...0> and |1...1> and measure every qubit
+    p(1|0) of a qubit is the fraction of the |0...0> shots where it was read as 1, p(0|1) the fraction of |1...1> shots where it was read as 0
+    */
+    pub fn calibrate(zeros: &BTreeMap<String, usize>, ones: &BTreeMap<String, usize>) -> Result<Self, QuantumError> {
+        let num_qubits: usize = bitstring_length(zeros)?;
+        if bitstring_length(ones)? != num_qubits {
+            return Err(QuantumError::DimensionMismatch {expected: num_qubits, found: bitstring_length(ones)?})
+        }
+        // Fraction of the shots where each qubit was read as 1
+        let fractions = |counts: &BTreeMap<String, usize>| -> Vec<T> {
+            let shots: usize = counts.values().sum();
+            (0..num_qubits).map(|qubit| {
+                let read_one: usize = counts.iter().filter(|(bits, _)| bits.as_bytes()[qubit] == b'1').map(|(_, count)| count).sum();
+                real::<T>(read_one as f64 / shots as f64)
+            }).collect()
+        };
+        let flips: Vec<(T, T)> = fractions(zeros).into_iter().zip(fractions(ones))
+            .map(|(zero_to_one, one)| (zero_to_one, T::one() - one))
+            .collect();
+        Self::new(flips)
+    }
+
+    /**
+    The two calibration circuits on num_qubits qubits: |0...0> and |1...1>, each measuring qubit q into bit q of the register "c"
+    */
+    pub fn calibration_circuits(num_qubits: usize) -> (QuantumCircuit<T>, QuantumCircuit<T>) {
+        let circuit = |prepare_ones: bool| -> QuantumCircuit<T> {
+            let mut circuit: QuantumCircuit<T> = QuantumCircuit::new_with_qubits(num_qubits as i32);
+            circuit.add_classical_register("c", num_qubits).unwrap();
+            for qubit in 0..num_qubits {
+                if prepare_ones {
+                    circuit.x(qubit);
+                }
+                circuit.measure(qubit, qubit);
+            }
+            circuit
+        };
+        (circuit(false), circuit(true))
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.flips.len()
+    }
+
+    // (p(1|0), p(0|1)) of each qubit
+    pub fn flips(&self) -> &[(T, T)] {
+        &self.flips
+    }
+
+    /**
+    The full 2^n x 2^n confusion matrix, row the measured basis state and column the prepared one
+    Every column adds up to 1
+    */
+    pub fn confusion_matrix(&self) -> Result<Vec<Vec<T>>, QuantumError> {
+        let size: usize = self.num_states()?;
+        Ok((0..size).map(|measured| (0..size).map(|prepared| {
+            self.flips.iter().enumerate().fold(T::one(), |total, (qubit, &flip)| {
+                let shift: usize = self.num_qubits() - 1 - qubit;
+                total * qubit_matrix(flip)[(measured >> shift) & 1][(prepared >> shift) & 1]
+            })
+        }).collect()).collect())
+    }
+
+    /**
+    Add readout errors to counts measured without them, every bit of every shot is flipped with its probability
+    */
+    pub fn apply(&self, counts: &BTreeMap<String, usize>) -> Result<BTreeMap<String, usize>, QuantumError> {
+        self.apply_with_rng(counts, &mut rand::thread_rng())
+    }
+
+    pub fn apply_with_rng<R: Rng + ?Sized>(&self, counts: &BTreeMap<String, usize>, rng: &mut R) -> Result<BTreeMap<String, usize>, QuantumError> {
+        self.check_counts(counts)?;
+        let mut noisy: BTreeMap<String, usize> = BTreeMap::new();
+        for (bits, &count) in counts.iter() {
+            for _ in 0..count {
+                let read: String = bits.chars().zip(self.flips.iter()).map(|(bit, &(zero_to_one, one_to_zero))| {
+                    let flip: T = if bit == '0' { zero_to_one } else { one_to_zero };
+                    match (bit, real::<T>(rng.gen_range(0. .. 1.)) < flip) {
+                        ('0', true) | ('1', false) => '1',
+                        _ => '0',
+                    }
+                }).collect();
+                *noisy.entry(read).or_insert(0) += 1;
+            }
+        }
+        Ok(noisy)
+    }
+
+    /**
+    Mitigate measured counts by solving M x = counts with the inverse of the confusion matrix
+    The result keeps the number of shots but entries can come out negative because of shot noise
+    Errors when the matrix of a qubit is singular (p(1|0) + p(0|1) = 1), its readout then says nothing about the state
+    */
+    pub fn mitigate_by_inversion(&self, counts: &BTreeMap<String, usize>) -> Result<BTreeMap<String, T>, QuantumError> {
+        let measured: Vec<T> = self.count_vector(counts)?;
+        let mut inverses: Vec<[[T; 2]; 2]> = vec![];
+        for &flip in self.flips.iter() {
+            let [[a, b], [c, d]] = qubit_matrix(flip);
+            let determinant: T = a * d - b * c;
+            if determinant.abs() < real(1e-12) {
+                return Err(QuantumError::SingularMatrix)
+            }
+            inverses.push([[d / determinant, -b / determinant], [-c / determinant, a / determinant]]);
+        }
+        Ok(self.count_map(&apply_per_qubit(&inverses, measured)))
+    }
+
+    /**
+    Mitigate measured counts with the closest distribution of shots: minimize ||M x - counts||^2 with every x_i >= 0
+    and Σ x_i the number of shots, by projected gradient descent starting from the inverted counts
+    Unlike mitigate_by_inversion the result is always a valid histogram
+    */
+    pub fn mitigate_by_least_squares(&self, counts: &BTreeMap<String, usize>) -> Result<BTreeMap<String, T>, QuantumError> {
+        let measured: Vec<T> = self.count_vector(counts)?;
+        let shots: T = measured.iter().fold(T::zero(), |total, &count| total + count);
+        let matrices: Vec<[[T; 2]; 2]> = self.flips.iter().map(|&flip| qubit_matrix(flip)).collect();
+        let transposed: Vec<[[T; 2]; 2]> = matrices.iter().map(|&[[a, b], [c, d]]| [[a, c], [b, d]]).collect();
+        // Step 1 / L with L = ||M||^2, the largest singular value of a tensor product being the product of the factors' ones
+        let lipschitz: T = matrices.iter().fold(T::one(), |total, matrix| total * largest_singular_value_squared(matrix));
+        let step: T = T::one() / lipschitz;
+
+        let start: Vec<T> = match self.mitigate_by_inversion(counts) {
+            Ok(inverted) => self.count_vector_from(&inverted),
+            Err(_) => measured.clone(),
+        };
+        let mut x: Vec<T> = project_onto_simplex(&start, shots);
+        let tolerance: T = shots * real(1e-10);
+        for _ in 0..10000 {
+            let residual: Vec<T> = apply_per_qubit(&matrices, x.clone()).into_iter().zip(measured.iter()).map(|(fit, &count)| fit - count).collect();
+            let gradient: Vec<T> = apply_per_qubit(&transposed, residual);
+            let next: Vec<T> = project_onto_simplex(&x.iter().zip(gradient.iter()).map(|(&value, &slope)| value - step * slope).collect::<Vec<T>>(), shots);
+            let change: T = next.iter().zip(x.iter()).fold(T::zero(), |total, (&a, &b)| total.max((a - b).abs()));
+            x = next;
+            if change < tolerance {
+                break
+            }
+        }
+        Ok(self.count_map(&x))
+    }
+
+    /**
+    Number of basis states 2^n of the dense vectors and matrices used for mitigation
+    Errors when 2^n does not fit in a usize, applying the error to counts works on any number of qubits
+    */
+    fn num_states(&self) -> Result<usize, QuantumError> {
+        let max: usize = usize::BITS as usize - 1;
+        if self.num_qubits() > max {
+            return Err(QuantumError::TooManyQubits {num_qubits: self.num_qubits(), max})
+        }
+        Ok(1 << self.num_qubits())
+    }
+
+    // Counts as a vector indexed by basis state
+    fn count_vector(&self, counts: &BTreeMap<String, usize>) -> Result<Vec<T>, QuantumError> {
+        self.check_counts(counts)?;
+        let mut vector: Vec<T> = vec![T::zero(); self.num_states()?];
+        for (bits, &count) in counts.iter() {
+            vector[usize::from_str_radix(bits, 2).unwrap()] += real(count as f64);
+        }
+        Ok(vector)
+    }
+
+    // Only used after count_vector, which already checked that 2^n fits in a usize
+    fn count_vector_from(&self, counts: &BTreeMap<String, T>) -> Vec<T> {
+        let mut vector: Vec<T> = vec![T::zero(); 1 << self.num_qubits()];
+        for (bits, &count) in counts.iter() {
+            vector[usize::from_str_radix(bits, 2).unwrap()] = count;
+        }
+        vector
+    }
+
+    // Basis states with a non zero count, as bitstrings
+    fn count_map(&self, vector: &[T]) -> BTreeMap<String, T> {
+        vector.iter().enumerate()
+            .filter(|(_, &count)| count != T::zero())
+            .map(|(index, &count)| (format!("{:0width$b}", index, width = self.num_qubits()), count))
+            .collect()
+    }
+
+    // Every bitstring has to be made of 0 and 1 and hold one bit per qubit
+    fn check_counts(&self, counts: &BTreeMap<String, usize>) -> Result<(), QuantumError> {
+        let num_qubits: usize = bitstring_length(counts)?;
+        if num_qubits != self.num_qubits() {
+            return Err(QuantumError::DimensionMismatch {expected: self.num_qubits(), found: num_qubits})
+        }
+        Ok(())
+    }
+}
+
+/**
+Number of bits of the bitstrings in a histogram
+Errors when there are no shots, when the bitstrings are not all as long or hold anything other than 0 and 1
+*/
+fn bitstring_length(counts: &BTreeMap<String, usize>) -> Result<usize, QuantumError> {
+    if counts.values().sum::<usize>() == 0 {
+        return Err(QuantumError::InvalidCounts("no shots".to_string()))
+    }
+    let length: usize = counts.keys().next().map(|bits| bits.len()).unwrap_or(0);
+    for bits in counts.keys() {
+        if bits.len() != length || !bits.chars().all(|bit| bit == '0' || bit == '1') {
+            return Err(QuantumError::InvalidCounts(format!("unexpected bitstring {}", bits)))
+        }
+    }
+    Ok(length)
+}
+
+// Confusion matrix of one qubit, [measured][prepared]
+fn qubit_matrix<T: Precision>((zero_to_one, one_to_zero): (T, T)) -> [[T; 2]; 2] {
+    [[T::one() - zero_to_one, one_to_zero], [zero_to_one, T::one() - one_to_zero]]
+}
+
+// Square of the largest singular value of a 2x2 matrix, the largest eigenvalue of A^T A
+fn largest_singular_value_squared<T: Precision>(&[[a, b], [c, d]]: &[[T; 2]; 2]) -> T {
+    let p: T = a * a + c * c;
+    let q: T = a * b + c * d;
+    let r: T = b * b + d * d;
+    let half: T = real(0.5);
+    (p + r) * half + (((p - r) * half).powi(2) + q * q).sqrt()
+}
+
+/**
+Multiply a vector of 2^n values by the tensor product of one 2x2 matrix per qubit, one qubit at a time
+Qubit q is bit (n - 1 - q) of the index
+*/
+fn apply_per_qubit<T: Precision>(matrices: &[[[T; 2]; 2]], mut vector: Vec<T>) -> Vec<T> {
+    let num_qubits: usize = matrices.len();
+    for (qubit, matrix) in matrices.iter().enumerate() {
+        let bit: usize = 1 << (num_qubits - 1 - qubit);
+        for index in (0..vector.len()).filter(|index| index & bit == 0) {
+            let (zero, one) = (vector[index], vector[index | bit]);
+            vector[index] = matrix[0][0] * zero + matrix[0][1] * one;
+            vector[index | bit] = matrix[1][0] * zero + matrix[1][1] * one;
+        }
+    }
+    vector
+}
+
+/**
+Closest point (in euclidean distance) with every entry >= 0 and entries adding up to total
+Shifts every entry down by the same amount and cuts off at 0, the shift found from the sorted entries
+*/
+fn project_onto_simplex<T: Precision>(vector: &[T], total: T) -> Vec<T> {
+    let mut sorted: Vec<T> = vector.to_vec();
+    sorted.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    let mut sum: T = T::zero();
+    let mut shift: T = T::zero();
+    for (i, &value) in sorted.iter().enumerate() {
+        sum += value;
+        let candidate: T = (sum - total) / real((i + 1) as f64);
+        if value - candidate > T::zero() {
+            shift = candidate;
+        }
+    }
+    vector.iter().map(|&value| (value - shift).max(T::zero())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Noise::NoiseModel;
+    use crate::QuantumRegister::QuantumRegister;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn counts(entries: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        entries.iter().map(|&(bits, count)| (bits.to_string(), count)).collect()
+    }
+
+    #[test]
+    fn test_confusion_matrix() {
+        let readout: ReadoutError<f64> = ReadoutError::new(vec![(0.1, 0.2), (0.05, 0.3)]).unwrap();
+        let matrix: Vec<Vec<f64>> = readout.confusion_matrix().unwrap();
+        assert!((matrix[0][0] - 0.9 * 0.95).abs() < 1e-12);
+        // Prepared 01 and measured 10: qubit 0 misread as 1 and qubit 1 misread as 0
+        assert!((matrix[2][1] - 0.1 * 0.3).abs() < 1e-12);
+        assert!((matrix[3][1] - 0.1 * 0.7).abs() < 1e-12);
+        for column in 0..4 {
+            assert!(((0..4).map(|row| matrix[row][column]).sum::<f64>() - 1.).abs() < 1e-12);
+        }
+        assert_eq!(Some(QuantumError::InvalidProbability("1.2".to_string())), ReadoutError::new(vec![(1.2, 0.)]).err());
+        assert_eq!(Some(QuantumError::InvalidProbability("NaN".to_string())), ReadoutError::new(vec![(0.1, f64::NAN)]).err());
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 1, found: 0}), ReadoutError::<f64>::new_uniform(0, 0.1).err());
+
+        // Too many qubits for a dense matrix, the error can still be applied to counts
+        let wide: ReadoutError<f64> = ReadoutError::new_uniform(usize::BITS as usize, 0.).unwrap();
+        let max: usize = usize::BITS as usize - 1;
+        assert_eq!(Some(QuantumError::TooManyQubits {num_qubits: max + 1, max}), wide.confusion_matrix().err());
+        let bits: String = "1".repeat(max + 1);
+        assert_eq!(Some(QuantumError::TooManyQubits {num_qubits: max + 1, max}), wide.mitigate_by_inversion(&counts(&[(&bits, 3)])).err());
+        assert_eq!(counts(&[(&bits, 3)]), wide.apply(&counts(&[(&bits, 3)])).unwrap());
+    }
+
+    #[test]
+    fn test_apply_and_calibrate() {
+        let readout: ReadoutError<f64> = ReadoutError::new(vec![(0.05, 0.1), (0.02, 0.2)]).unwrap();
+        let mut rng: StdRng = StdRng::seed_from_u64(11);
+        let zeros: BTreeMap<String, usize> = readout.apply_with_rng(&counts(&[("00", 20000)]), &mut rng).unwrap();
+        let ones: BTreeMap<String, usize> = readout.apply_with_rng(&counts(&[("11", 20000)]), &mut rng).unwrap();
+        assert_eq!(20000, zeros.values().sum::<usize>());
+
+        let calibrated: ReadoutError<f64> = ReadoutError::calibrate(&zeros, &ones).unwrap();
+        for (expected, found) in readout.flips().iter().zip(calibrated.flips()) {
+            assert!((expected.0 - found.0).abs() < 0.01 && (expected.1 - found.1).abs() < 0.01);
+        }
+
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 2, found: 3}), readout.apply(&counts(&[("000", 1)])).err());
+        assert!(matches!(ReadoutError::<f64>::calibrate(&counts(&[("0a", 1)]), &ones), Err(QuantumError::InvalidCounts(_))));
+        assert!(matches!(ReadoutError::<f64>::calibrate(&BTreeMap::new(), &ones), Err(QuantumError::InvalidCounts(_))));
+    }
+
+    #[test]
+    fn test_calibration_circuits_with_noise_model() {
+        // A uniform readout error of the noise model is recovered by the calibration circuits
+        let (mut zero_circuit, mut one_circuit) = ReadoutError::<f64>::calibration_circuits(2);
+        let mut noise_model: NoiseModel<f64> = NoiseModel::new();
+        noise_model.set_readout_error(0.1).unwrap();
+        zero_circuit.set_noise_model(Some(noise_model.clone()));
+        one_circuit.set_noise_model(Some(noise_model));
+
+        let mut rng: StdRng = StdRng::seed_from_u64(5);
+        let mut histogram = |circuit: &QuantumCircuit<f64>| -> BTreeMap<String, usize> {
+            let mut histogram: BTreeMap<String, usize> = BTreeMap::new();
+            for _ in 0..5000 {
+                let bits: Vec<i32> = circuit.run_with_rng(&mut QuantumRegister::new_from_int(0, 4), &mut rng).unwrap();
+                *histogram.entry(bits.iter().map(|bit| bit.to_string()).collect()).or_insert(0) += 1;
+            }
+            histogram
+        };
+        let zeros: BTreeMap<String, usize> = histogram(&zero_circuit);
+        let ones: BTreeMap<String, usize> = histogram(&one_circuit);
+        let calibrated: ReadoutError<f64> = ReadoutError::calibrate(&zeros, &ones).unwrap();
+        for &(zero_to_one, one_to_zero) in calibrated.flips() {
+            assert!((zero_to_one - 0.1).abs() < 0.02 && (one_to_zero - 0.1).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_mitigation() {
+        // Bell pair counts pushed through the confusion matrix exactly, both methods give the ideal counts back
+        let readout: ReadoutError<f64> = ReadoutError::new(vec![(0.1, 0.15), (0.05, 0.2)]).unwrap();
+        let matrix: Vec<Vec<f64>> = readout.confusion_matrix().unwrap();
+        let ideal: [f64; 4] = [500., 0., 0., 500.];
+        let measured: BTreeMap<String, usize> = (0..4)
+            .map(|row| (format!("{:02b}", row), (0..4).map(|column| matrix[row][column] * ideal[column]).sum::<f64>().round() as usize))
+            .collect();
+
+        let inverted: BTreeMap<String, f64> = readout.mitigate_by_inversion(&measured).unwrap();
+        let least_squares: BTreeMap<String, f64> = readout.mitigate_by_least_squares(&measured).unwrap();
+        for (index, &expected) in ideal.iter().enumerate() {
+            let bits: String = format!("{:02b}", index);
+            assert!((inverted.get(&bits).copied().unwrap_or(0.) - expected).abs() < 2., "{:?}", inverted);
+            assert!((least_squares.get(&bits).copied().unwrap_or(0.) - expected).abs() < 2., "{:?}", least_squares);
+        }
+        assert!(least_squares.values().all(|&count| count >= 0.));
+        assert!((least_squares.values().sum::<f64>() - measured.values().sum::<usize>() as f64).abs() < 1e-6);
+
+        // Counts inversion pushes below 0 are cut off by least squares
+        let readout: ReadoutError<f64> = ReadoutError::new_uniform(1, 0.2).unwrap();
+        assert!(readout.mitigate_by_inversion(&counts(&[("0", 100)])).unwrap()["1"] < 0.);
+        let least_squares: BTreeMap<String, f64> = readout.mitigate_by_least_squares(&counts(&[("0", 100)])).unwrap();
+        assert!((least_squares["0"] - 100.).abs() < 1e-6);
+        assert!(least_squares.get("1").copied().unwrap_or(0.) < 1e-6);
+
+        assert_eq!(Some(QuantumError::SingularMatrix), ReadoutError::<f64>::new_uniform(1, 0.5).unwrap().mitigate_by_inversion(&counts(&[("0", 1)])).err());
+    }
+}
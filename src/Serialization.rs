@@ -0,0 +1,260 @@
+use nalgebra::{Complex, DVector, Unit};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::Gates::Gate;
+use crate::Instruction::Instruction;
+use crate::Noise::KrausChannel;
+use crate::Precision::{Precision, real};
+use crate::QuantumCircuit::{ClassicalRegister, QuantumCircuit};
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::QuantumRegister;
+
+/*
+JSON schema of the serialized types (only built with the serde feature)
+Numbers are written with the shortest digits that read back as the exact same f32 / f64, so every type round trips exactly
+as long as the reader parses floats exactly (for serde_json that needs its float_roundtrip feature)
+
+Complex number      [re, im]
+Gate                {"matrix": [[complex, ...], ...]}, the rows of a 2^k x 2^k unitary matrix
+KrausChannel        {"operators": [[[complex, ...], ...], ...]}, the rows of each Kraus operator
+QuantumRegister     {"state": [complex, ...]}, the 2^n amplitudes, qubit 0 being the most significant bit of the index
+Control             {"qubit": 3, "on_one": true}
+Condition           {"register": "c", "value": 3}
+Operation           the operation's name as a string ("h", "rx", "p", "iqft", "measure", ...), {"unitary": Gate} or {"kraus": KrausChannel}
+Instruction         {"operation": Operation, "targets": [0, 2], "controls": [Control, ...], "params": [0.5],
+                     "clbits": [1], "condition": Condition or null}
+ClassicalRegister   {"name": "c", "size": 2, "offset": 0}
+QuantumCircuit      {"instructions": [Instruction, ...], "num_qubits": 3, "classical_registers": [ClassicalRegister, ...]},
+                    the circuit's noise model is not written and is None after reading
+                    the registers have to be listed in order of their offsets, each one starting where the previous one ends
+
+Measurement results are plain standard types: sample gives {"00": 510, "11": 490}, sample_memory gives ["00", "11", ...]
+and run gives the classical bits as [0, 1, ...]
+*/
+
+#[derive(Serialize, Deserialize)]
+struct GateData<T> {
+    matrix: Vec<Vec<(T, T)>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelData<T> {
+    operators: Vec<Vec<Vec<(T, T)>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegisterData<T> {
+    state: Vec<(T, T)>,
+}
+
+#[derive(Deserialize)]
+struct CircuitData<T: Precision> {
+    instructions: Vec<Instruction<T>>,
+    num_qubits: i32,
+    classical_registers: Vec<ClassicalRegister>,
+}
+
+fn to_pairs<T: Precision>(values: &[Complex<T>]) -> Vec<(T, T)> {
+    values.iter().map(|value| (value.re, value.im)).collect()
+}
+
+fn from_pairs<T: Precision>(pairs: Vec<(T, T)>) -> Vec<Complex<T>> {
+    pairs.into_iter().map(|(re, im)| Complex::new(re, im)).collect()
+}
+
+impl<T: Precision + Serialize> Serialize for Gate<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        GateData {matrix: self.rows().iter().map(|row| to_pairs(row)).collect()}.serialize(serializer)
+    }
+}
+
+/**
+Reading a gate goes through Gate::new_from_matrix, so a matrix that is not square, not 2^k x 2^k or not unitary is an error
+*/
+impl<'de, T: Precision + Deserialize<'de>> Deserialize<'de> for Gate<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data: GateData<T> = GateData::deserialize(deserializer)?;
+        Gate::new_from_matrix(data.matrix.into_iter().map(from_pairs).collect()).map_err(D::Error::custom)
+    }
+}
+
+impl<T: Precision + Serialize> Serialize for KrausChannel<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChannelData {operators: self.operators().iter().map(|rows| rows.iter().map(|row| to_pairs(row)).collect()).collect()}.serialize(serializer)
+    }
+}
+
+/**
+Reading a channel goes through KrausChannel::new, so operators of the wrong size or that do not preserve the trace are an error
+*/
+impl<'de, T: Precision + Deserialize<'de>> Deserialize<'de> for KrausChannel<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data: ChannelData<T> = ChannelData::deserialize(deserializer)?;
+        let operators: Vec<Vec<Vec<Complex<T>>>> = data.operators.into_iter().map(|rows| rows.into_iter().map(from_pairs).collect()).collect();
+        KrausChannel::new(operators).map_err(D::Error::custom)
+    }
+}
+
+impl<T: Precision + Serialize> Serialize for QuantumRegister<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RegisterData {state: to_pairs(self.state.as_slice())}.serialize(serializer)
+    }
+}
+
+/**
+The state is kept exactly as written instead of being normalized again, which could change the last digit of the amplitudes
+It has to hold 2^n amplitudes with a norm within 1e-4 of 1
+*/
+impl<'de, T: Precision + Deserialize<'de>> Deserialize<'de> for QuantumRegister<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data: RegisterData<T> = RegisterData::deserialize(deserializer)?;
+        if !data.state.len().is_power_of_two() {
+            return Err(D::Error::custom(format!("a register needs 2^n amplitudes, found {}", data.state.len())))
+        }
+        let state: DVector<Complex<T>> = DVector::from_vec(from_pairs(data.state));
+        let norm: T = state.iter().fold(T::zero(), |total, amplitude| total + amplitude.norm_sqr()).sqrt();
+        if (norm - T::one()).abs() > real(1e-4) {
+            return Err(D::Error::custom(format!("register state has norm {} instead of 1", norm)))
+        }
+        Ok(QuantumRegister::new_from_unit_vec(Unit::new_unchecked(state)))
+    }
+}
+
+/**
+Reading a circuit rebuilds it with add_classical_register and add_instruction, so the registers have to follow each other
+from bit 0 without overlaps or gaps, and every qubit, classical bit and condition of an instruction has to exist in the circuit
+*/
+impl<'de, T: Precision + Deserialize<'de>> Deserialize<'de> for QuantumCircuit<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data: CircuitData<T> = CircuitData::deserialize(deserializer)?;
+        if data.num_qubits < 0 {
+            return Err(D::Error::custom(format!("a circuit can not have {} qubits", data.num_qubits)))
+        }
+        let mut circuit: QuantumCircuit<T> = QuantumCircuit::new_with_qubits(data.num_qubits);
+        for register in data.classical_registers {
+            let offset: usize = circuit.add_classical_register(&register.name, register.size).map_err(D::Error::custom)?;
+            if offset != register.offset {
+                return Err(D::Error::custom(format!("classical register {} starts at bit {} instead of {}", register.name, register.offset, offset)))
+            }
+        }
+        for instruction in data.instructions {
+            check_instruction(&circuit, &instruction).map_err(D::Error::custom)?;
+            circuit.add_instruction(instruction);
+        }
+        Ok(circuit)
+    }
+}
+
+fn check_instruction<T: Precision>(circuit: &QuantumCircuit<T>, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+    let num_qubits: usize = circuit.num_qubits() as usize;
+    if let Some(&qubit) = instruction.qubits().iter().find(|&&qubit| qubit >= num_qubits) {
+        return Err(QuantumError::QubitOutOfRange {qubit: qubit as i32, num_qubits})
+    }
+    if let Some(&bit) = instruction.clbits.iter().find(|&&bit| bit >= circuit.num_clbits()) {
+        return Err(QuantumError::ClassicalBitOutOfRange {bit, num_bits: circuit.num_clbits()})
+    }
+    if let Some(condition) = &instruction.condition {
+        circuit.classical_register(&condition.register)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Instruction::Control;
+    use crate::QuantumCircuit::QuantumCircuit;
+
+    #[test]
+    fn test_register_round_trip() {
+        let register: QuantumRegister<f64> = QuantumRegister::new_from_vec(vec![
+            Complex::new(1. / 3., -0.1), Complex::new(0.7, 1e-17), Complex::new(-0.25, 0.2), Complex::new(0., 0.3)
+        ]);
+        let json: String = serde_json::to_string(&register).unwrap();
+        let read: QuantumRegister<f64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(register.state.as_slice(), read.state.as_slice());
+
+        let register: QuantumRegister<f32> = QuantumRegister::new_from_vec(vec![Complex::new(0.6, 0.), Complex::new(0., 0.8)]);
+        assert_eq!("{\"state\":[[0.6,0.0],[0.0,0.8]]}", serde_json::to_string(&register).unwrap());
+
+        assert!(serde_json::from_str::<QuantumRegister<f64>>("{\"state\":[[1.0,0.0],[0.0,0.0],[0.0,0.0]]}").is_err());
+        assert!(serde_json::from_str::<QuantumRegister<f64>>("{\"state\":[[1.0,0.0],[1.0,0.0]]}").is_err());
+    }
+
+    #[test]
+    fn test_gate_round_trip() {
+        let gate: Gate<f64> = Gate::new_u(0.3, 1. / 7., -2.);
+        let json: String = serde_json::to_string(&gate).unwrap();
+        let read: Gate<f64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(gate.rows(), read.rows());
+
+        let error: String = serde_json::from_str::<Gate<f64>>("{\"matrix\":[[[1.0,0.0],[1.0,0.0]],[[0.0,0.0],[1.0,0.0]]]}").err().unwrap().to_string();
+        assert!(error.contains("matrix is not unitary"));
+    }
+
+    #[test]
+    fn test_circuit_round_trip() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        circuit.add_classical_register("c", 2).unwrap();
+        circuit.h(0).rx(1, 0.1).u(2, 1. / 3., 0.2, -0.3).mcx(&[Control::new_negated(0), Control::new(1)], 2);
+        circuit.iswap(0, 1).inverse_qft(&[0, 1, 2]).unitary(Gate::new_sx(), &[1]).measure(2, 1).x(0).c_if("c", 2).reset(1).barrier(&[]);
+
+        let json: String = serde_json::to_string(&circuit).unwrap();
+        let read: QuantumCircuit<f64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(circuit.to_string(), read.to_string());
+        assert_eq!(json, serde_json::to_string(&read).unwrap());
+        assert_eq!(circuit.classical_registers(), read.classical_registers());
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::json!({"operation": "rx", "targets": [1], "controls": [], "params": [0.1], "clbits": [], "condition": null}), value["instructions"][1]);
+        assert_eq!(serde_json::json!({"register": "c", "value": 2}), value["instructions"][8]["condition"]);
+        assert_eq!(serde_json::json!({"qubit": 0, "on_one": false}), value["instructions"][3]["controls"][0]);
+        assert_eq!("iqft", value["instructions"][5]["operation"]);
+    }
+
+    #[test]
+    fn test_invalid_circuits() {
+        let read = |registers: &str, instruction: &str| -> String {
+            let json: String = format!("{{\"instructions\":[{}],\"num_qubits\":2,\"classical_registers\":[{}]}}", instruction, registers);
+            serde_json::from_str::<QuantumCircuit<f64>>(&json).err().map(|error| error.to_string()).unwrap_or_default()
+        };
+        let c: &str = "{\"name\":\"c\",\"size\":2,\"offset\":0}";
+        let h = |targets: &str, controls: &str| format!("{{\"operation\":\"h\",\"targets\":[{}],\"controls\":[{}],\"params\":[],\"clbits\":[],\"condition\":null}}", targets, controls);
+        let measure = |clbit: usize| format!("{{\"operation\":\"measure\",\"targets\":[0],\"controls\":[],\"params\":[],\"clbits\":[{}],\"condition\":null}}", clbit);
+        assert_eq!("", read(c, &h("1", "")));
+        assert_eq!("", read(c, &measure(1)));
+
+        assert!(read(&format!("{},{{\"name\":\"d\",\"size\":2,\"offset\":1}}", c), "").contains("classical register d starts at bit 1 instead of 2"));
+        assert!(read(&format!("{},{{\"name\":\"d\",\"size\":1,\"offset\":3}}", c), "").contains("starts at bit 3 instead of 2"));
+        assert!(read("{\"name\":\"c\",\"size\":65,\"offset\":0}", "").contains("at most 64"));
+        assert!(read(&format!("{},{}", c, c), "").contains("already exists"));
+        assert!(read(c, &h("2", "")).contains("qubit 2 is out of range"));
+        assert!(read(c, &h("0", "{\"qubit\":5,\"on_one\":true}")).contains("qubit 5 is out of range"));
+        assert!(read(c, &measure(2)).contains("classical bit 2 is out of range"));
+        assert!(read(c, &h("0", "").replace("null", "{\"register\":\"d\",\"value\":1}")).contains("no classical register named d"));
+        assert!(serde_json::from_str::<QuantumCircuit<f64>>("{\"instructions\":[],\"num_qubits\":-1,\"classical_registers\":[]}").is_err());
+    }
+
+    #[test]
+    fn test_channel_round_trip() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.channel(KrausChannel::new_amplitude_damping(0.3).unwrap(), &[1]);
+        let json: String = serde_json::to_string(&circuit).unwrap();
+        let read: QuantumCircuit<f64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(json, serde_json::to_string(&read).unwrap());
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::json!([[[1.0, 0.0], [0.0, 0.0]], [[0.0, 0.0], [0.8366600265340756, 0.0]]]), value["instructions"][0]["operation"]["kraus"]["operators"][0]);
+
+        let error: String = serde_json::from_str::<KrausChannel<f64>>("{\"operators\":[[[[1.0,0.0],[0.0,0.0]],[[0.0,0.0],[0.0,0.0]]]]}").err().unwrap().to_string();
+        assert!(error.contains("do not preserve the trace"));
+    }
+
+    #[test]
+    fn test_results_round_trip() {
+        let register: QuantumRegister = QuantumRegister::new_from_vec(vec![Complex::new(1., 0.), Complex::new(0., 0.), Complex::new(0., 0.), Complex::new(1., 0.)]);
+        let counts: std::collections::BTreeMap<String, usize> = register.sample(100);
+        let json: String = serde_json::to_string(&counts).unwrap();
+        assert_eq!(counts, serde_json::from_str::<std::collections::BTreeMap<String, usize>>(&json).unwrap());
+    }
+}
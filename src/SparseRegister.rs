@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, HashMap};
+use nalgebra::Complex;
+use num_traits::{One, Zero};
+use rand::Rng;
+use crate::Backend::Backend;
+use crate::Instruction::{Control, Instruction};
+use crate::Matrix::Matrix;
+use crate::Noise::{KrausChannel, check_probability};
+use crate::Precision::Precision;
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::{QuantumRegister, draw_outcome, sample_indices};
+
+/**
+State vector that only stores the basis states with a non zero amplitude, keyed by their index (qubit q being bit n - 1 - q like QuantumRegister)
+Memory and time grow with the number of stored amplitudes instead of 2^n, so circuits that stay close to a few basis states
+(reversible arithmetic, oracles) run on as many qubits as fit in an index
+After every operation amplitudes whose magnitude is at most the pruning threshold are dropped, the state is renormalized
+and the dropped probability is added to the pruned probability
+*/
+// Largest register to_register makes a dense copy of, 2^30 amplitudes already take 16 GiB in f64
+const MAX_DENSE_QUBITS: usize = 30;
+
+#[derive(Clone)]
+pub struct SparseRegister<T: Precision = f32> {
+    num_qubits: usize,
+    amplitudes: HashMap<usize, Complex<T>>,
+    threshold: T,
+    pruned_probability: T,
+}
+
+impl<T: Precision> SparseRegister<T> {
+    // |0...0> on num_qubits qubits, at most usize::BITS so that every basis state has an index
+    pub fn new(num_qubits: usize) -> Result<Self, QuantumError> {
+        Self::new_from_int(0, num_qubits)
+    }
+
+    /**
+    Basis state index on num_qubits qubits, only amplitudes that are exactly 0 get pruned until a threshold is set
+    Errors when there are more than usize::BITS qubits or when index does not fit in num_qubits bits
+    */
+    pub fn new_from_int(index: usize, num_qubits: usize) -> Result<Self, QuantumError> {
+        if num_qubits > usize::BITS as usize {
+            return Err(QuantumError::TooManyQubits {num_qubits, max: usize::BITS as usize})
+        }
+        // Number of bits the index needs against the number of qubits
+        let bits: usize = (usize::BITS - index.leading_zeros()) as usize;
+        if bits > num_qubits {
+            return Err(QuantumError::DimensionMismatch {expected: num_qubits, found: bits})
+        }
+        Ok(Self {
+            num_qubits,
+            amplitudes: HashMap::from([(index, Complex::one())]),
+            threshold: T::zero(),
+            pruned_probability: T::zero(),
+        })
+    }
+
+    /**
+    Convert a dense register, keeping only the non zero amplitudes
+    */
+    pub fn new_from_register(register: &QuantumRegister<T>) -> Self {
+        Self {
+            num_qubits: register.num_qubits(),
+            amplitudes: register.state.iter().copied().enumerate().filter(|(_, amplitude)| !amplitude.is_zero()).collect(),
+            threshold: T::zero(),
+            pruned_probability: T::zero(),
+        }
+    }
+
+    /**
+    Amplitudes with a magnitude at most threshold are dropped after every operation from now on, the largest one is always kept
+    The threshold is a magnitude so it has to be between 0 and 1
+    */
+    pub fn set_threshold(&mut self, threshold: T) -> Result<&mut Self, QuantumError> {
+        check_probability(threshold)?;
+        self.threshold = threshold;
+        Ok(self)
+    }
+
+    pub fn threshold(&self) -> T {
+        self.threshold
+    }
+
+    /**
+    Total probability dropped by pruning so far, 1 minus it is roughly the fidelity with the exact state
+    */
+    pub fn pruned_probability(&self) -> T {
+        self.pruned_probability
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    // Number of basis states currently stored
+    pub fn num_nonzero(&self) -> usize {
+        self.amplitudes.len()
+    }
+
+    pub fn amplitudes(&self) -> &HashMap<usize, Complex<T>> {
+        &self.amplitudes
+    }
+
+    /**
+    Amplitude of a basis state given as a bitstring, qubit 0 first (ex "0110")
+    */
+    pub fn amplitude(&self, bits: &str) -> Result<Complex<T>, QuantumError> {
+        if bits.len() != self.num_qubits {
+            return Err(QuantumError::DimensionMismatch {expected: self.num_qubits, found: bits.len()})
+        }
+        if !bits.chars().all(|bit| bit == '0' || bit == '1') {
+            return Err(QuantumError::InvalidBitstring(bits.to_string()))
+        }
+        let index: usize = bits.chars().fold(0, |index, bit| (index << 1) | (bit == '1') as usize);
+        Ok(self.amplitudes.get(&index).copied().unwrap_or_else(Complex::zero))
+    }
+
+    /**
+    Dense copy of the state, only for registers of at most MAX_DENSE_QUBITS qubits since it holds all 2^n amplitudes
+    */
+    pub fn to_register(&self) -> Result<QuantumRegister<T>, QuantumError> {
+        if self.num_qubits > MAX_DENSE_QUBITS {
+            return Err(QuantumError::TooManyQubits {num_qubits: self.num_qubits, max: MAX_DENSE_QUBITS})
+        }
+        let mut state: Vec<Complex<T>> = vec![Complex::zero(); 1 << self.num_qubits];
+        for (&index, &amplitude) in self.amplitudes.iter() {
+            state[index] = amplitude;
+        }
+        Ok(QuantumRegister::new_from_vec(state))
+    }
+
+    /**
+    Bitstring of a basis state index for this register, qubit 0 first
+    */
+    pub fn bitstring(&self, index: usize) -> String {
+        format!("{:0width$b}", index, width = self.num_qubits)
+    }
+
+    /**
+    Measure the whole register shots many times without collapsing it, like QuantumRegister::sample
+    */
+    pub fn sample(&self, shots: usize) -> BTreeMap<String, usize> {
+        self.sample_with_rng(shots, &mut rand::thread_rng())
+    }
+
+    pub fn sample_with_rng<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+        // Sorted so a seeded rng gives the same counts whatever order the hash map is in
+        let entries: Vec<(usize, Complex<T>)> = self.sorted_amplitudes();
+        let probabilities: Vec<T> = entries.iter().map(|(_, amplitude)| amplitude.norm_sqr()).collect();
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for i in sample_indices(&probabilities, shots, rng) {
+            *counts.entry(self.bitstring(entries[i].0)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /**
+    Measure the given qubits together and collapse the register onto the outcome, like QuantumRegister::measure_qubits
+    */
+    pub fn measure_qubits(&mut self, qubits: &[usize]) -> Result<Vec<i32>, QuantumError> {
+        self.measure_qubits_with_rng(qubits, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        let targets: Vec<i32> = qubits.iter().map(|&qubit| qubit as i32).collect();
+        crate::Gates::Gate::<T>::check_distinct_qubits(&[], &targets, self.num_qubits as i32)?;
+
+        let num_qubits: usize = self.num_qubits;
+        let outcome_of = |j: usize| -> usize {
+            qubits.iter().fold(0, |outcome, qubit| (outcome << 1) | ((j >> (num_qubits - 1 - qubit)) & 1))
+        };
+        // Only outcomes of stored basis states can come out, so their probabilities are gathered from the amplitudes
+        // and sorted so a seeded rng gives the same outcome whatever order the hash map is in
+        let mut outcomes: HashMap<usize, T> = HashMap::new();
+        for (&index, amplitude) in self.amplitudes.iter() {
+            *outcomes.entry(outcome_of(index)).or_insert_with(T::zero) += amplitude.norm_sqr();
+        }
+        let mut outcomes: Vec<(usize, T)> = outcomes.into_iter().collect();
+        outcomes.sort_by_key(|&(outcome, _)| outcome);
+        let probabilities: Vec<T> = outcomes.iter().map(|&(_, probability)| probability).collect();
+        let outcome: usize = outcomes[draw_outcome(&probabilities, rng)].0;
+        self.amplitudes.retain(|&index, _| outcome_of(index) == outcome);
+        self.normalize();
+
+        Ok((0..qubits.len()).map(|i| ((outcome >> (qubits.len() - 1 - i)) & 1) as i32).collect())
+    }
+
+    pub fn measure_qubit_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<i32, QuantumError> {
+        Ok(self.measure_qubits_with_rng(&[qubit], rng)?[0])
+    }
+
+    // Put a qubit back into |0> by measuring it and flipping it if it came out as 1
+    pub fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        if self.measure_qubit_with_rng(qubit, rng)? == 1 {
+            let bit: usize = 1 << (self.num_qubits - 1 - qubit);
+            self.amplitudes = self.amplitudes.drain().map(|(index, amplitude)| (index ^ bit, amplitude)).collect();
+        }
+        Ok(())
+    }
+
+    /**
+    Apply a noise channel as one Monte-Carlo trajectory, like QuantumRegister::apply_channel
+    */
+    pub fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        channel.check_placement(targets, self.num_qubits)?;
+        let branches: Vec<HashMap<usize, Complex<T>>> = channel.matrices().iter().map(|operator| {
+            let mut branch: HashMap<usize, Complex<T>> = self.amplitudes.clone();
+            apply_local_matrix(&mut branch, self.num_qubits, operator, targets, &[]);
+            branch
+        }).collect();
+        let probabilities: Vec<T> = branches.iter()
+            .map(|branch| branch.values().fold(T::zero(), |total, amplitude| total + amplitude.norm_sqr()))
+            .collect();
+        let outcome: usize = draw_outcome(&probabilities, rng);
+        self.amplitudes = branches.into_iter().nth(outcome).unwrap_or_default();
+        self.normalize();
+        self.prune();
+        Ok(())
+    }
+
+    /**
+    Apply a small 2^k x 2^k matrix to the k target qubits, the first target being the most significant bit of the local matrix index
+    */
+    pub(crate) fn apply_local_matrix(&mut self, matrix: &Matrix<T>, targets: &[usize], controls: &[Control]) {
+        apply_local_matrix(&mut self.amplitudes, self.num_qubits, matrix, targets, controls);
+        self.prune();
+    }
+
+    /**
+    Drop the amplitudes at or below the threshold and renormalize if any probability was lost
+    The largest amplitude (the lowest index among equal ones) is always kept so the state never becomes empty
+    */
+    fn prune(&mut self) {
+        let limit: T = self.threshold * self.threshold;
+        let largest: Option<usize> = self.amplitudes.iter()
+            .max_by(|(i, a), (j, b)| a.norm_sqr().partial_cmp(&b.norm_sqr()).unwrap_or(std::cmp::Ordering::Equal).then(j.cmp(i)))
+            .map(|(&index, _)| index);
+        let mut dropped: T = T::zero();
+        self.amplitudes.retain(|&index, amplitude| {
+            let probability: T = amplitude.norm_sqr();
+            if probability > limit || Some(index) == largest {
+                return true
+            }
+            dropped += probability;
+            false
+        });
+        if dropped > T::zero() {
+            self.pruned_probability += dropped;
+            self.normalize();
+        }
+    }
+
+    fn normalize(&mut self) {
+        let norm: T = self.amplitudes.values().fold(T::zero(), |total, amplitude| total + amplitude.norm_sqr()).sqrt();
+        if norm > T::zero() {
+            let scale: Complex<T> = Complex::from(T::one() / norm);
+            for amplitude in self.amplitudes.values_mut() {
+                *amplitude *= scale;
+            }
+        }
+    }
+
+    fn sorted_amplitudes(&self) -> Vec<(usize, Complex<T>)> {
+        let mut entries: Vec<(usize, Complex<T>)> = self.amplitudes.iter().map(|(&index, &amplitude)| (index, amplitude)).collect();
+        entries.sort_by_key(|&(index, _)| index);
+        entries
+    }
+}
+
+/**
+Sparse version of QuantumRegister's kernel: every stored basis state whose controls match names the group of 2^k amplitudes it belongs to,
+each group is gathered, multiplied by the matrix and written back, amplitudes that become exactly 0 are removed
+*/
+fn apply_local_matrix<T: Precision>(amplitudes: &mut HashMap<usize, Complex<T>>, num_qubits: usize, matrix: &Matrix<T>, targets: &[usize], controls: &[Control]) {
+    let dim: usize = matrix.rows.len();
+    let offsets: Vec<usize> = (0..dim).map(|local| {
+        targets.iter().enumerate()
+            .filter(|(j, _)| (local >> (targets.len() - 1 - j)) & 1 == 1)
+            .fold(0, |offset, (_, target)| offset | 1 << (num_qubits - 1 - target))
+    }).collect();
+    let target_mask: usize = offsets[dim - 1];
+    let control_mask: usize = controls.iter().fold(0, |mask, control| mask | 1 << (num_qubits - 1 - control.qubit));
+    let control_value: usize = controls.iter().filter(|control| control.on_one).fold(0, |mask, control| mask | 1 << (num_qubits - 1 - control.qubit));
+
+    let mut bases: Vec<usize> = amplitudes.keys()
+        .filter(|&&index| index & control_mask == control_value)
+        .map(|&index| index & !target_mask)
+        .collect();
+    bases.sort_unstable();
+    bases.dedup();
+
+    let mut group: Vec<Complex<T>> = vec![Complex::zero(); dim];
+    for base in bases {
+        for (local, offset) in offsets.iter().enumerate() {
+            group[local] = amplitudes.remove(&(base | offset)).unwrap_or_else(Complex::zero);
+        }
+        for (row, offset) in offsets.iter().enumerate() {
+            let new_val: Complex<T> = group.iter().enumerate()
+                .fold(Complex::zero(), |total, (col, val)| total + matrix.rows[row][col] * val);
+            if !new_val.is_zero() {
+                amplitudes.insert(base | offset, new_val);
+            }
+        }
+    }
+}
+
+impl<T: Precision> Backend<T> for SparseRegister<T> {
+    fn num_qubits(&self) -> usize {
+        SparseRegister::num_qubits(self)
+    }
+
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+        let gate = instruction.gate()?;
+        gate.check_placement(&instruction.controls, &instruction.targets, self.num_qubits)?;
+        self.apply_local_matrix(gate.matrix(), &instruction.targets, &instruction.controls);
+        Ok(())
+    }
+
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        SparseRegister::measure_qubits_with_rng(self, qubits, rng)
+    }
+
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        SparseRegister::apply_channel_with_rng(self, channel, targets, rng)
+    }
+
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        SparseRegister::reset_with_rng(self, qubit, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QuantumCircuit::QuantumCircuit;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_matches_state_vector() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(5);
+        circuit.h(0).rx(1, 0.3).cx(0, 4).u(3, 0.2, 0.5, -0.4).ccx(4, 1, 2).mcx(&[Control::new_negated(4)], 1);
+        circuit.iswap(4, 0).qft(&[2, 4, 0]).cp(3, 1, 0.7).sqrt_swap(4, 2).t(1);
+
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 32);
+        circuit.run(&mut register).unwrap();
+        let mut sparse: SparseRegister<f64> = SparseRegister::new(5).unwrap();
+        circuit.run(&mut sparse).unwrap();
+        let dense: QuantumRegister<f64> = sparse.to_register().unwrap();
+        for index in 0..32 {
+            assert!((dense.state[index] - register.state[index]).norm_sqr() < 1e-18);
+        }
+        assert_eq!(0., sparse.pruned_probability());
+        assert_eq!(Err(QuantumError::InvalidBitstring("01201".to_string())), sparse.amplitude("01201"));
+    }
+
+    #[test]
+    fn test_register_size() {
+        // Every qubit of a full usize register can be flipped and measured
+        let num_qubits: usize = usize::BITS as usize;
+        let mut sparse: SparseRegister<f64> = SparseRegister::new_from_int(1, num_qubits).unwrap();
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(num_qubits as i32);
+        circuit.x(0).h(num_qubits - 1);
+        circuit.run(&mut sparse).unwrap();
+        let mut rng: StdRng = StdRng::seed_from_u64(4);
+        let qubits: Vec<usize> = (0..num_qubits).collect();
+        let bits: Vec<i32> = sparse.measure_qubits_with_rng(&qubits, &mut rng).unwrap();
+        assert_eq!((1, 0), (bits[0], bits[1]));
+        assert_eq!(1, sparse.num_nonzero());
+
+        assert_eq!(Some(QuantumError::TooManyQubits {num_qubits: num_qubits + 1, max: num_qubits}), SparseRegister::<f64>::new(num_qubits + 1).err());
+        assert_eq!(Some(QuantumError::DimensionMismatch {expected: 3, found: 4}), SparseRegister::<f64>::new_from_int(8, 3).err());
+        assert!(SparseRegister::<f64>::new_from_int(7, 3).is_ok());
+        assert!(SparseRegister::<f64>::new(0).is_ok());
+        assert_eq!(Some(QuantumError::TooManyQubits {num_qubits, max: 30}), sparse.to_register().err());
+    }
+
+    #[test]
+    fn test_arithmetic_on_many_qubits() {
+        // Increment a 44 bit counter (qubit 44 least significant) controlled by qubit 0 in superposition
+        let num_qubits: usize = 45;
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(num_qubits as i32);
+        circuit.h(0).x(44).x(43).x(40).x(1);
+        for j in 1..num_qubits - 1 {
+            let controls: Vec<Control> = (0..1).chain(j + 1..num_qubits).map(Control::new).collect();
+            circuit.mcx(&controls, j);
+        }
+        circuit.cx(0, 44);
+
+        let mut sparse: SparseRegister<f64> = SparseRegister::new(num_qubits).unwrap();
+        circuit.run(&mut sparse).unwrap();
+        assert_eq!(2, sparse.num_nonzero());
+        let before: String = "01".to_string() + &"0".repeat(38) + "10011";
+        let after: String = "11".to_string() + &"0".repeat(38) + "10100";
+        assert!((sparse.amplitude(&before).unwrap().norm_sqr() - 0.5).abs() < 1e-12);
+        assert!((sparse.amplitude(&after).unwrap().norm_sqr() - 0.5).abs() < 1e-12);
+
+        let counts: BTreeMap<String, usize> = sparse.sample_with_rng(100, &mut StdRng::seed_from_u64(5));
+        assert_eq!(vec![before.clone(), after], counts.keys().cloned().collect::<Vec<String>>());
+        let mut rng: StdRng = StdRng::seed_from_u64(1);
+        if sparse.measure_qubits_with_rng(&[0], &mut rng).unwrap() == vec![0] {
+            assert!((sparse.amplitude(&before).unwrap().norm_sqr() - 1.).abs() < 1e-12);
+        }
+        assert_eq!(1, sparse.num_nonzero());
+    }
+
+    #[test]
+    fn test_pruning() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.ry(0, 0.01).cx(0, 1);
+        let mut sparse: SparseRegister<f64> = SparseRegister::new(2).unwrap();
+        sparse.set_threshold(0.01).unwrap();
+        circuit.run(&mut sparse).unwrap();
+        assert_eq!(1, sparse.num_nonzero());
+        assert!((sparse.pruned_probability() - 0.005f64.sin().powi(2)).abs() < 1e-15);
+        assert!((sparse.amplitude("00").unwrap().norm_sqr() - 1.).abs() < 1e-12);
+        assert_eq!(0.01, sparse.threshold());
+
+        // A threshold above every amplitude keeps the largest one, the first of equal ones
+        let mut sparse: SparseRegister<f64> = SparseRegister::new(2).unwrap();
+        sparse.set_threshold(0.8).unwrap();
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).ry(1, 1.);
+        circuit.run(&mut sparse).unwrap();
+        assert_eq!(1, sparse.num_nonzero());
+        assert!((sparse.amplitude("00").unwrap().norm_sqr() - 1.).abs() < 1e-12);
+        assert_eq!(vec![0, 0], sparse.measure_qubits(&[0, 1]).unwrap());
+        assert_eq!(vec!["00".to_string()], sparse.sample(10).into_keys().collect::<Vec<String>>());
+
+        assert_eq!(Some(QuantumError::InvalidProbability("NaN".to_string())), sparse.set_threshold(f64::NAN).err());
+        assert_eq!(Some(QuantumError::InvalidProbability("-0.1".to_string())), sparse.set_threshold(-0.1).err());
+        assert_eq!(0.8, sparse.threshold());
+    }
+
+    #[test]
+    fn test_measurement_and_reset() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        circuit.add_classical_register("c", 2).unwrap();
+        circuit.h(0).cx(0, 3).measure(3, 0).x(1).c_if("c", 1).reset(0).reset(3).measure(1, 1);
+        let mut rng: StdRng = StdRng::seed_from_u64(12);
+        for _ in 0..10 {
+            let mut sparse: SparseRegister<f64> = SparseRegister::new(4).unwrap();
+            let bits: Vec<i32> = circuit.run_with_rng(&mut sparse, &mut rng).unwrap();
+            assert_eq!(bits[0], bits[1]);
+            assert!((sparse.amplitude(&format!("0{}00", bits[0])).unwrap().norm_sqr() - 1.).abs() < 1e-12);
+        }
+
+        let mut sparse: SparseRegister<f64> = SparseRegister::new_from_register(&QuantumRegister::new_from_int(3, 4));
+        sparse.apply_channel_with_rng(&KrausChannel::new_amplitude_damping(1.).unwrap(), &[1], &mut rng).unwrap();
+        assert_eq!(1., sparse.amplitude("10").unwrap().norm_sqr());
+    }
+}
@@ -0,0 +1,656 @@
+use std::collections::BTreeMap;
+use nalgebra::Complex;
+use num_traits::{One, Zero};
+use rand::Rng;
+use crate::Backend::Backend;
+use crate::Gates::Gate;
+use crate::Instruction::{Control, Instruction, Operation};
+use crate::Noise::KrausChannel;
+use crate::Precision::{Precision, real};
+use crate::QuantumError::QuantumError;
+use crate::QuantumRegister::draw_outcome;
+
+/**
+Stabilizer state of n qubits stored as an Aaronson-Gottesman tableau ("Improved simulation of stabilizer circuits", 2004)
+Instead of 2^n amplitudes the state is described by the n Pauli products that leave it unchanged (its stabilizers)
+plus n destabilizers, each row holding one bit x and one bit z per qubit and a sign bit
+Clifford gates (Paulis, H, S, CX and everything built from them) and measurements take O(n) and O(n^2) time,
+so circuits on thousands of qubits run fine, any other gate gives a NonCliffordGate error
+Gates given as a matrix are Clifford when they map every Pauli product to a Pauli product, which is checked on the matrix
+*/
+#[derive(Clone)]
+pub struct StabilizerState {
+    num_qubits: usize,
+    // Number of u64 words in the x or z bits of one row
+    words: usize,
+    // Rows 0..n are the destabilizers, n..2n the stabilizers and 2n a scratch row used by measurements
+    x: Vec<u64>,
+    z: Vec<u64>,
+    // Sign of each row, true for -1
+    r: Vec<bool>,
+}
+
+// Gates the tableau updates for directly, every Clifford gate of a circuit is broken down into these
+#[derive(Clone, Debug, PartialEq)]
+enum Clifford {
+    H(usize),
+    S(usize),
+    X(usize),
+    Y(usize),
+    Z(usize),
+    // Control then target
+    Cx(usize, usize),
+    // Any other Clifford gate U on the given qubits, given by U X_j U^† and U Z_j U^† for qubit j of the gate
+    Conjugation(Vec<usize>, Vec<[Pauli; 2]>),
+}
+
+/**
+Pauli product i^phase X^x Z^z on the qubits of one gate, bit j of x and z standing for qubit j of the gate
+Y is written as i X Z
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Pauli {
+    x: u64,
+    z: u64,
+    phase: u32,
+}
+
+impl Pauli {
+    // (i^a X^x1 Z^z1) (i^b X^x2 Z^z2) = i^(a + b) (-1)^|z1 & x2| X^(x1 ^ x2) Z^(z1 ^ z2)
+    fn times(self, other: Pauli) -> Pauli {
+        let phase: u32 = self.phase + other.phase + 2 * (self.z & other.x).count_ones();
+        Pauli {x: self.x ^ other.x, z: self.z ^ other.z, phase: phase % 4}
+    }
+}
+
+// Largest number of qubits, controls included, a named gate is expanded to when checking its matrix
+const MAX_CONJUGATION_QUBITS: usize = 4;
+
+impl StabilizerState {
+    // |0...0>, stabilized by Z on every qubit
+    pub fn new(num_qubits: usize) -> Self {
+        let words: usize = num_qubits.div_ceil(64);
+        let rows: usize = 2 * num_qubits + 1;
+        let mut state: Self = Self {num_qubits, words, x: vec![0; rows * words], z: vec![0; rows * words], r: vec![false; rows]};
+        for qubit in 0..num_qubits {
+            state.set(qubit, qubit, true, false);
+            state.set(num_qubits + qubit, qubit, false, true);
+        }
+        state
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    /**
+    Stabilizers of the state as signed Pauli strings, qubit 0 first (ex ["+XX", "+ZZ"] for a bell pair)
+    */
+    pub fn stabilizers(&self) -> Vec<String> {
+        (self.num_qubits..2 * self.num_qubits).map(|row| {
+            let sign: char = if self.r[row] { '-' } else { '+' };
+            std::iter::once(sign).chain((0..self.num_qubits).map(|qubit| match (self.x_bit(row, qubit), self.z_bit(row, qubit)) {
+                (false, false) => 'I',
+                (true, false) => 'X',
+                (true, true) => 'Y',
+                (false, true) => 'Z',
+            })).collect()
+        }).collect()
+    }
+
+    /**
+    Measure a qubit in the computational basis and collapse the state onto the outcome
+    The outcome is random when some stabilizer anticommutes with Z on the qubit and fixed by the stabilizers otherwise
+    */
+    pub fn measure_qubit(&mut self, qubit: usize) -> Result<i32, QuantumError> {
+        self.measure_qubit_with_rng(qubit, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubit_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<i32, QuantumError> {
+        self.check_qubit(qubit)?;
+        let n: usize = self.num_qubits;
+        if let Some(p) = (n..2 * n).find(|&row| self.x_bit(row, qubit)) {
+            for row in 0..2 * n {
+                if row != p && self.x_bit(row, qubit) {
+                    self.rowsum(row, p);
+                }
+            }
+            self.copy_row(p - n, p);
+            self.clear_row(p);
+            self.set(p, qubit, false, true);
+            self.r[p] = rng.gen::<bool>();
+            return Ok(self.r[p] as i32)
+        }
+        let scratch: usize = 2 * n;
+        self.clear_row(scratch);
+        for row in 0..n {
+            if self.x_bit(row, qubit) {
+                self.rowsum(scratch, row + n);
+            }
+        }
+        Ok(self.r[scratch] as i32)
+    }
+
+    // Measure qubits one after the other and return their bits in the order given
+    pub fn measure_qubits(&mut self, qubits: &[usize]) -> Result<Vec<i32>, QuantumError> {
+        self.measure_qubits_with_rng(qubits, &mut rand::thread_rng())
+    }
+
+    pub fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        qubits.iter().map(|&qubit| self.measure_qubit_with_rng(qubit, rng)).collect()
+    }
+
+    /**
+    Measure every qubit shots times on copies of the state, like QuantumRegister::sample
+    */
+    pub fn sample(&self, shots: usize) -> BTreeMap<String, usize> {
+        self.sample_with_rng(shots, &mut rand::thread_rng())
+    }
+
+    pub fn sample_with_rng<R: Rng + ?Sized>(&self, shots: usize, rng: &mut R) -> BTreeMap<String, usize> {
+        let qubits: Vec<usize> = (0..self.num_qubits).collect();
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for _ in 0..shots {
+            let bits: Vec<i32> = self.clone().measure_qubits_with_rng(&qubits, rng).unwrap();
+            *counts.entry(bits.iter().map(|bit| bit.to_string()).collect()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // Put a qubit back into |0> by measuring it and flipping it if it came out as 1
+    pub fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        if self.measure_qubit_with_rng(qubit, rng)? == 1 {
+            self.apply(&Clifford::X(qubit));
+        }
+        Ok(())
+    }
+
+    /**
+    Apply a noise channel whose Kraus operators are each a multiple of a Pauli product (bit flip, phase flip, depolarizing, ...)
+    by drawing one of the Pauli products with its probability, other channels can not be simulated on a tableau
+    */
+    pub fn apply_channel_with_rng<T: Precision, R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        channel.check_placement(targets, self.num_qubits)?;
+        let paulis: Vec<(T, Vec<usize>)> = channel.pauli_decomposition()
+            .ok_or(QuantumError::NonCliffordGate("kraus".to_string()))?;
+        let probabilities: Vec<T> = paulis.iter().map(|(probability, _)| *probability).collect();
+        let (_, pauli) = &paulis[draw_outcome(&probabilities, rng)];
+        for (&qubit, &index) in targets.iter().zip(pauli.iter()) {
+            match index {
+                1 => self.apply(&Clifford::X(qubit)),
+                2 => self.apply(&Clifford::Y(qubit)),
+                3 => self.apply(&Clifford::Z(qubit)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, gate: &Clifford) {
+        for row in 0..2 * self.num_qubits {
+            match *gate {
+                Clifford::H(a) => {
+                    let (x, z) = (self.x_bit(row, a), self.z_bit(row, a));
+                    self.r[row] ^= x && z;
+                    self.set(row, a, z, x);
+                }
+                Clifford::S(a) => {
+                    let (x, z) = (self.x_bit(row, a), self.z_bit(row, a));
+                    self.r[row] ^= x && z;
+                    self.set(row, a, x, z ^ x);
+                }
+                Clifford::X(a) => self.r[row] ^= self.z_bit(row, a),
+                Clifford::Y(a) => self.r[row] ^= self.x_bit(row, a) ^ self.z_bit(row, a),
+                Clifford::Z(a) => self.r[row] ^= self.x_bit(row, a),
+                Clifford::Cx(a, b) => {
+                    let (xa, za, xb, zb) = (self.x_bit(row, a), self.z_bit(row, a), self.x_bit(row, b), self.z_bit(row, b));
+                    self.r[row] ^= xa && zb && (xb == za);
+                    self.set(row, b, xb ^ xa, zb);
+                    self.set(row, a, xa, za ^ zb);
+                }
+                Clifford::Conjugation(ref qubits, ref images) => self.conjugate_row(row, qubits, images),
+            }
+        }
+    }
+
+    /**
+    Row P becomes U P U^†, U given by the images of X_j and Z_j of every qubit j it acts on
+    The row is i^(number of Y) times the product of X_j and Z_j over its qubits, so its image is the same product of the images
+    */
+    fn conjugate_row(&mut self, row: usize, qubits: &[usize], images: &[[Pauli; 2]]) {
+        let mut product: Pauli = Pauli {x: 0, z: 0, phase: 0};
+        for (&qubit, [x_image, z_image]) in qubits.iter().zip(images.iter()) {
+            let (x, z) = (self.x_bit(row, qubit), self.z_bit(row, qubit));
+            if x && z {
+                product.phase += 1;
+            }
+            if x {
+                product = product.times(*x_image);
+            }
+            if z {
+                product = product.times(*z_image);
+            }
+        }
+        // Back to one of I, X, Y, Z per qubit, the image being Hermitian the phase left is +1 or -1
+        let phase: u32 = (product.phase + 4 - (product.x & product.z).count_ones() % 4) % 4;
+        self.r[row] ^= phase == 2;
+        for (j, &qubit) in qubits.iter().enumerate() {
+            self.set(row, qubit, (product.x >> j) & 1 == 1, (product.z >> j) & 1 == 1);
+        }
+    }
+
+    /**
+    Row h becomes the product of rows i and h, keeping track of the sign
+    Each qubit adds 0, 1 or -1 to the power of i in the product of the two Paulis, the total being 0 or 2 mod 4
+    */
+    fn rowsum(&mut self, h: usize, i: usize) {
+        let mut phase: i64 = 2 * (self.r[h] as i64 + self.r[i] as i64);
+        for word in 0..self.words {
+            let (x1, z1) = (self.x[i * self.words + word], self.z[i * self.words + word]);
+            let (x2, z2) = (self.x[h * self.words + word], self.z[h * self.words + word]);
+            let plus: u64 = (x1 & z1 & z2 & !x2) | (x1 & !z1 & z2 & x2) | (!x1 & z1 & x2 & !z2);
+            let minus: u64 = (x1 & z1 & x2 & !z2) | (x1 & !z1 & z2 & !x2) | (!x1 & z1 & x2 & z2);
+            phase += plus.count_ones() as i64 - minus.count_ones() as i64;
+            self.x[h * self.words + word] = x1 ^ x2;
+            self.z[h * self.words + word] = z1 ^ z2;
+        }
+        self.r[h] = phase.rem_euclid(4) == 2;
+    }
+
+    fn x_bit(&self, row: usize, qubit: usize) -> bool {
+        (self.x[row * self.words + qubit / 64] >> (qubit % 64)) & 1 == 1
+    }
+
+    fn z_bit(&self, row: usize, qubit: usize) -> bool {
+        (self.z[row * self.words + qubit / 64] >> (qubit % 64)) & 1 == 1
+    }
+
+    fn set(&mut self, row: usize, qubit: usize, x: bool, z: bool) {
+        let (index, mask) = (row * self.words + qubit / 64, 1u64 << (qubit % 64));
+        self.x[index] = (self.x[index] & !mask) | if x { mask } else { 0 };
+        self.z[index] = (self.z[index] & !mask) | if z { mask } else { 0 };
+    }
+
+    fn copy_row(&mut self, to: usize, from: usize) {
+        let words: usize = self.words;
+        self.x.copy_within(from * words..(from + 1) * words, to * words);
+        self.z.copy_within(from * words..(from + 1) * words, to * words);
+        self.r[to] = self.r[from];
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        let words: usize = self.words;
+        self.x[row * words..(row + 1) * words].fill(0);
+        self.z[row * words..(row + 1) * words].fill(0);
+        self.r[row] = false;
+    }
+
+    fn check_qubit(&self, qubit: usize) -> Result<(), QuantumError> {
+        if qubit >= self.num_qubits {
+            return Err(QuantumError::QubitOutOfRange {qubit: qubit as i32, num_qubits: self.num_qubits})
+        }
+        Ok(())
+    }
+}
+
+/**
+Break a gate instruction down into tableau gates (up to a global phase)
+Rotations and phase gates are Clifford when their angle is a multiple of π/2, controls on X, Y and Z are broken down into CX
+Every other gate (ex a matrix or a controlled phase of π) is checked and applied through its matrix, controls included
+*/
+fn decompose<T: Precision>(instruction: &Instruction<T>) -> Result<Vec<Clifford>, QuantumError> {
+    let non_clifford = || QuantumError::NonCliffordGate(instruction.name());
+    if instruction.params.len() != instruction.operation.num_params() {
+        return Err(QuantumError::DimensionMismatch {expected: instruction.operation.num_params(), found: instruction.params.len()})
+    }
+    let targets: &[usize] = &instruction.targets;
+    let quarter_turns = || -> Result<usize, QuantumError> {
+        let turns: T = instruction.params[0] / T::frac_pi_2();
+        if (turns - turns.round()).abs() > real(1e-6) {
+            return Err(non_clifford())
+        }
+        let turns: T = turns.round() - (turns.round() / real(4.)).floor() * real(4.);
+        Ok((0..4).find(|&k| (turns - real(k as f64)).abs() < real(0.5)).unwrap_or(0))
+    };
+    let single = |gates: Vec<Clifford>| -> Result<Vec<Clifford>, QuantumError> {
+        if targets.len() != 1 {
+            return Err(QuantumError::DimensionMismatch {expected: 1, found: targets.len()})
+        }
+        Ok(gates)
+    };
+
+    let gates: Vec<Clifford> = match (&instruction.operation, instruction.controls.as_slice()) {
+        (Operation::I, _) => vec![],
+        (Operation::X, []) => single(vec![Clifford::X(targets[0])])?,
+        (Operation::Y, []) => single(vec![Clifford::Y(targets[0])])?,
+        (Operation::Z, []) => single(vec![Clifford::Z(targets[0])])?,
+        (Operation::H, []) => single(vec![Clifford::H(targets[0])])?,
+        (Operation::S, []) => single(vec![Clifford::S(targets[0])])?,
+        (Operation::Sdg, []) => single(vec![Clifford::S(targets[0]); 3])?,
+        (Operation::Sx, []) => single(vec![Clifford::H(targets[0]), Clifford::S(targets[0]), Clifford::H(targets[0])])?,
+        (Operation::Sxdg, []) => single(vec![Clifford::H(targets[0]), Clifford::S(targets[0]), Clifford::S(targets[0]), Clifford::S(targets[0]), Clifford::H(targets[0])])?,
+        (Operation::Phase | Operation::Rz, []) => single(vec![Clifford::S(targets[0]); quarter_turns()?])?,
+        // Rx(θ) = H Rz(θ) H and Ry(θ) = S Rx(θ) S^†
+        (Operation::Rx, []) => {
+            let a: usize = targets[0];
+            single([vec![Clifford::H(a)], vec![Clifford::S(a); quarter_turns()?], vec![Clifford::H(a)]].concat())?
+        }
+        (Operation::Ry, []) => {
+            let a: usize = targets[0];
+            single([vec![Clifford::S(a); 3], vec![Clifford::H(a)], vec![Clifford::S(a); quarter_turns()?], vec![Clifford::H(a), Clifford::S(a)]].concat())?
+        }
+        (Operation::Swap, []) if targets.len() == 2 => {
+            let (a, b) = (targets[0], targets[1]);
+            vec![Clifford::Cx(a, b), Clifford::Cx(b, a), Clifford::Cx(a, b)]
+        }
+        (Operation::ISwap, []) if targets.len() == 2 => {
+            let (a, b) = (targets[0], targets[1]);
+            vec![Clifford::S(a), Clifford::S(b), Clifford::H(a), Clifford::Cx(a, b), Clifford::Cx(b, a), Clifford::H(b)]
+        }
+        (Operation::X | Operation::Y | Operation::Z, &[control]) if targets.len() == 1 => {
+            let (c, t) = (control.qubit, targets[0]);
+            // CZ = (I ⊗ H) CX (I ⊗ H) and CY = (I ⊗ S) CX (I ⊗ S^†)
+            let core: Vec<Clifford> = match instruction.operation {
+                Operation::X => vec![Clifford::Cx(c, t)],
+                Operation::Y => vec![Clifford::S(t), Clifford::S(t), Clifford::S(t), Clifford::Cx(c, t), Clifford::S(t)],
+                _ => vec![Clifford::H(t), Clifford::Cx(c, t), Clifford::H(t)],
+            };
+            // A control firing on |0> is an ordinary control between two X gates
+            let flip: Vec<Clifford> = if control.on_one { vec![] } else { vec![Clifford::X(c)] };
+            [flip.clone(), core, flip].concat()
+        }
+        _ => vec![conjugation(instruction)?],
+    };
+    Ok(gates)
+}
+
+/**
+Conjugation by the matrix of the instruction, controls included, or NonCliffordGate naming the gate when it is not Clifford
+Named gates on more than MAX_CONJUGATION_QUBITS qubits are not expanded to a matrix and taken as not Clifford,
+which only misses corner cases like a multi controlled global phase
+*/
+fn conjugation<T: Precision>(instruction: &Instruction<T>) -> Result<Clifford, QuantumError> {
+    let qubits: Vec<usize> = instruction.qubits();
+    let given_matrix: bool = matches!(instruction.operation, Operation::Unitary(_)) && instruction.controls.is_empty();
+    if qubits.len() > MAX_CONJUGATION_QUBITS && !given_matrix {
+        return Err(QuantumError::NonCliffordGate(instruction.name()))
+    }
+    let gate: Gate<T> = instruction.gate()?;
+    if gate.rows().len() != 1 << instruction.targets.len() {
+        return Err(QuantumError::DimensionMismatch {expected: 1 << instruction.targets.len(), found: gate.rows().len()})
+    }
+    let control_values: Vec<bool> = instruction.controls.iter().map(|control| control.on_one).collect();
+    let images: Vec<[Pauli; 2]> = pauli_images(&Gate::new_controlled(&gate, &control_values))
+        .ok_or_else(|| QuantumError::NonCliffordGate(gate_name(instruction, &gate)))?;
+    Ok(Clifford::Conjugation(qubits, images))
+}
+
+/**
+U X_j U^† and U Z_j U^† for every qubit j of a gate, None when one of them is not a Pauli product (the gate is then not Clifford)
+Qubit j is bit (k - 1 - j) of the matrix index
+*/
+fn pauli_images<T: Precision>(gate: &Gate<T>) -> Option<Vec<[Pauli; 2]>> {
+    let num_qubits: usize = gate.num_qubits();
+    (0..num_qubits).map(|j| {
+        let bit: usize = 1 << (num_qubits - 1 - j);
+        // X_j sends |a> to |a ^ bit>, Z_j multiplies |a> by -1 when the bit is set
+        let x_image: Pauli = conjugate_pauli(gate.rows(), |a| (a ^ bit, T::one()))?;
+        let z_image: Pauli = conjugate_pauli(gate.rows(), |a| (a, if a & bit == 0 { T::one() } else { -T::one() }))?;
+        Some([x_image, z_image])
+    }).collect()
+}
+
+/**
+U P U^† for the Pauli product P sending |a> to sign |b> with (b, sign) = pauli(a), None when the result is not a Pauli product
+A Pauli product i^p X^x Z^z has a single entry per column c, i^p (-1)^|c & z| in row c ^ x
+*/
+fn conjugate_pauli<T: Precision>(u: &[Vec<Complex<T>>], pauli: impl Fn(usize) -> (usize, T)) -> Option<Pauli> {
+    let size: usize = u.len();
+    let num_qubits: usize = size.trailing_zeros() as usize;
+    let image: Vec<Vec<Complex<T>>> = (0..size).map(|row| (0..size).map(|column| {
+        (0..size).fold(Complex::zero(), |total: Complex<T>, a| {
+            let (b, sign) = pauli(a);
+            total + u[row][b] * u[column][a].conj() * sign
+        })
+    }).collect()).collect();
+
+    let tolerance: T = real(1e-8);
+    let x: usize = (0..size).find(|&row| image[row][0].norm_sqr() > real(0.5))?;
+    let phases: [Complex<T>; 4] = [Complex::one(), Complex::i(), -Complex::one(), -Complex::i()];
+    let phase: usize = phases.iter().position(|&phase| (image[x][0] - phase).norm_sqr() < tolerance)?;
+    let z: usize = (0..num_qubits).map(|j| 1 << j).filter(|&bit| (image[bit ^ x][bit] + phases[phase]).norm_sqr() < tolerance).fold(0, |z, bit| z | bit);
+    let matches: bool = (0..size).all(|row| (0..size).all(|column| {
+        let expected: Complex<T> = if row == column ^ x {
+            if (column & z).count_ones().is_multiple_of(2) { phases[phase] } else { -phases[phase] }
+        } else {
+            Complex::zero()
+        };
+        (image[row][column] - expected).norm_sqr() < tolerance
+    }));
+    // Index bit (k - 1 - j) is qubit j of the gate
+    let local = |mask: usize| (0..num_qubits).filter(|&j| (mask >> (num_qubits - 1 - j)) & 1 == 1).fold(0u64, |local, j| local | 1 << j);
+    if matches { Some(Pauli {x: local(x), z: local(z), phase: phase as u32}) } else { None }
+}
+
+/**
+Name of a gate that is not Clifford for the error: the instruction's name, or for a matrix the standard gate it matches
+up to a global phase (ex "t") and otherwise the qubits it acts on
+*/
+fn gate_name<T: Precision>(instruction: &Instruction<T>, gate: &Gate<T>) -> String {
+    if !matches!(instruction.operation, Operation::Unitary(_)) {
+        return instruction.name()
+    }
+    let standard: [(&str, Gate<T>); 5] = [
+        ("t", Gate::new_t()), ("tdg", Gate::new_tdg()), ("sqrt_swap", Gate::new_sqrt_swap()), ("ccx", Gate::new_toffoli()), ("cswap", Gate::new_fredkin()),
+    ];
+    match standard.iter().find(|(_, candidate)| equal_up_to_phase(candidate, gate)) {
+        Some((name, _)) => "c".repeat(instruction.controls.len()) + name,
+        None => format!("{} on qubits {:?}", instruction.name(), instruction.qubits()),
+    }
+}
+
+// Same matrix up to a global phase e^(iφ), which is read from the overlap tr(A^† B) / 2^k
+fn equal_up_to_phase<T: Precision>(a: &Gate<T>, b: &Gate<T>) -> bool {
+    if a.rows().len() != b.rows().len() {
+        return false
+    }
+    let entries = || a.rows().iter().flatten().zip(b.rows().iter().flatten());
+    let overlap: Complex<T> = entries().fold(Complex::zero(), |total: Complex<T>, (x, y)| total + x.conj() * y) / real::<T>(a.rows().len() as f64);
+    entries().all(|(x, y)| (*x * overlap - *y).norm_sqr() < real(1e-8))
+}
+
+impl<T: Precision> Backend<T> for StabilizerState {
+    fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+        let controls: Vec<i32> = instruction.controls.iter().map(|control: &Control| control.qubit as i32).collect();
+        let targets: Vec<i32> = instruction.targets.iter().map(|&qubit| qubit as i32).collect();
+        Gate::<T>::check_distinct_qubits(&controls, &targets, self.num_qubits as i32)?;
+        for gate in decompose(instruction)?.iter() {
+            self.apply(gate);
+        }
+        Ok(())
+    }
+
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, qubits: &[usize], rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        StabilizerState::measure_qubits_with_rng(self, qubits, rng)
+    }
+
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, channel: &KrausChannel<T>, targets: &[usize], rng: &mut R) -> Result<(), QuantumError> {
+        StabilizerState::apply_channel_with_rng(self, channel, targets, rng)
+    }
+
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, qubit: usize, rng: &mut R) -> Result<(), QuantumError> {
+        StabilizerState::reset_with_rng(self, qubit, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QuantumCircuit::QuantumCircuit;
+    use crate::QuantumRegister::QuantumRegister;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_bell_pair() {
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).cx(0, 1);
+        let mut state: StabilizerState = StabilizerState::new(2);
+        circuit.run(&mut state).unwrap();
+        assert_eq!(vec!["+XX", "+ZZ"], state.stabilizers());
+
+        let counts: BTreeMap<String, usize> = state.sample_with_rng(1000, &mut StdRng::seed_from_u64(2));
+        assert_eq!(vec!["00", "11"], counts.keys().collect::<Vec<&String>>());
+        assert!((counts["00"] as f64 / 1000. - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_matches_state_vector() {
+        // Every Clifford gate the tableau knows, checked against the probabilities of the state vector
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        circuit.h(0).s(0).cx(0, 1).sx(2).cz(1, 2).y(3).mcx(&[Control::new_negated(2)], 3).iswap(0, 3).sdg(1).swap(1, 2);
+        circuit.rx(0, std::f64::consts::FRAC_PI_2).ry(3, -std::f64::consts::PI).p(2, 3. * std::f64::consts::FRAC_PI_2).sxdg(1).h(1);
+        circuit.add_instruction(Instruction::new(Operation::Y, vec![2], vec![Control::new(0)], vec![]));
+        circuit.rz(1, std::f64::consts::PI).h(2).z(0);
+
+        let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(0, 16);
+        circuit.run(&mut register).unwrap();
+        let mut state: StabilizerState = StabilizerState::new(4);
+        circuit.run(&mut state).unwrap();
+
+        // Stabilizer states have 2^k equally likely outcomes, so sampling gives the exact support
+        let shots: usize = 4000;
+        let counts: BTreeMap<String, usize> = state.sample_with_rng(shots, &mut StdRng::seed_from_u64(9));
+        for (index, probability) in register.probabilities().into_iter().enumerate() {
+            let found: f64 = counts.get(&format!("{:04b}", index)).copied().unwrap_or(0) as f64 / shots as f64;
+            if probability < 1e-9 {
+                assert_eq!(0., found);
+            } else {
+                assert!((probability - found).abs() < 0.05, "{} {} {}", index, probability, found);
+            }
+        }
+    }
+
+    #[test]
+    fn test_thousands_of_qubits() {
+        // GHZ state on 1000 qubits: the first measurement is random and fixes all the others
+        let num_qubits: usize = 1000;
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(num_qubits as i32);
+        // Classical registers hold at most 64 bits, so the outcomes are spread over several of them
+        for register in 0..num_qubits.div_ceil(64) {
+            circuit.add_classical_register(&format!("c{}", register), 64.min(num_qubits - 64 * register)).unwrap();
+        }
+        circuit.h(0);
+        for qubit in 1..num_qubits {
+            circuit.cx(qubit - 1, qubit);
+        }
+        for qubit in 0..num_qubits {
+            circuit.measure(qubit, qubit);
+        }
+        let bits: Vec<i32> = circuit.run_with_rng(&mut StabilizerState::new(num_qubits), &mut StdRng::seed_from_u64(1)).unwrap();
+        assert!(bits.iter().all(|&bit| bit == bits[0]));
+    }
+
+    #[test]
+    fn test_reset_and_conditions() {
+        // Teleport |1> with classical corrections, then reset every qubit
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.add_classical_register("m0", 1).unwrap();
+        circuit.add_classical_register("m1", 1).unwrap();
+        circuit.add_classical_register("out", 1).unwrap();
+        circuit.x(0).h(1).cx(1, 2).cx(0, 1).h(0).measure(0, 0).measure(1, 1);
+        circuit.x(2).c_if("m1", 1).z(2).c_if("m0", 1).measure(2, 2);
+        let mut rng: StdRng = StdRng::seed_from_u64(6);
+        for _ in 0..10 {
+            let mut state: StabilizerState = StabilizerState::new(3);
+            assert_eq!(1, circuit.run_with_rng(&mut state, &mut rng).unwrap()[2]);
+            for qubit in 0..3 {
+                state.reset_with_rng(qubit, &mut rng).unwrap();
+            }
+            assert_eq!(vec![0, 0, 0], state.measure_qubits_with_rng(&[0, 1, 2], &mut rng).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_gates_given_as_matrices() {
+        // Every named gate given as its matrix instead ends on the exact same tableau, signs included
+        let mut prepare: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        prepare.h(0).cx(0, 1).s(1).h(2).cz(2, 0).sx(1);
+        let mut gates: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        gates.h(0).s(1).sdg(2).sx(0).sxdg(1).y(2).rx(0, std::f64::consts::FRAC_PI_2).ry(1, -std::f64::consts::FRAC_PI_2);
+        gates.cx(2, 0).cz(0, 1).swap(1, 2).iswap(2, 0).mcx(&[Control::new_negated(1)], 0);
+        gates.add_instruction(Instruction::new(Operation::Y, vec![0], vec![Control::new(2)], vec![]));
+        for instruction in gates.instructions() {
+            let mut named: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+            named.add_instruction(instruction.clone());
+            let mut matrix: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+            let operation: Operation<f64> = Operation::Unitary(instruction.gate().unwrap());
+            matrix.add_instruction(Instruction::new(operation, instruction.targets.clone(), instruction.controls.clone(), vec![]));
+
+            let mut expected: StabilizerState = StabilizerState::new(3);
+            prepare.run(&mut expected).unwrap();
+            named.run(&mut expected).unwrap();
+            let mut state: StabilizerState = StabilizerState::new(3);
+            prepare.run(&mut state).unwrap();
+            matrix.run(&mut state).unwrap();
+            assert_eq!(expected.stabilizers(), state.stabilizers(), "{}", instruction.name());
+        }
+
+        // Controlled phase of π is CZ and u(π/2, 0, π) is H, together with the first two H they make a bell pair
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).h(1).cp(0, 1, std::f64::consts::PI).u(1, std::f64::consts::FRAC_PI_2, 0., std::f64::consts::PI);
+        let mut state: StabilizerState = StabilizerState::new(2);
+        circuit.run(&mut state).unwrap();
+        assert_eq!(vec!["+XX", "+ZZ"], state.stabilizers());
+    }
+
+    #[test]
+    fn test_non_clifford_gates() {
+        let mut state: StabilizerState = StabilizerState::new(2);
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        circuit.h(0).unitary(Gate::new_t(), &[1]);
+        assert_eq!(Err(QuantumError::NonCliffordGate("t".to_string())), circuit.run(&mut state));
+
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.unitary(Gate::new_toffoli(), &[2, 0, 1]);
+        assert_eq!(Err(QuantumError::NonCliffordGate("ccx".to_string())), circuit.run(&mut StabilizerState::new(3)));
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.unitary(Gate::new_rx(0.3), &[2]);
+        assert_eq!(Err(QuantumError::NonCliffordGate("unitary on qubits [2]".to_string())), circuit.run(&mut StabilizerState::new(3)));
+
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+        circuit.t(1);
+        circuit.rz(0, 0.3);
+        circuit.cp(0, 1, std::f32::consts::FRAC_PI_2);
+        circuit.ccx(0, 1, 2);
+        circuit.add_instruction(Instruction::new(Operation::H, vec![1], vec![Control::new(0)], vec![]));
+        for instruction in circuit.instructions() {
+            let mut single: QuantumCircuit = QuantumCircuit::new_with_qubits(3);
+            single.add_instruction(instruction.clone());
+            assert_eq!(Err(QuantumError::NonCliffordGate(instruction.name())), single.run(&mut StabilizerState::new(3)));
+        }
+    }
+
+    #[test]
+    fn test_pauli_noise() {
+        // A certain bit flip is applied every time, amplitude damping is not a Pauli channel
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 2).unwrap();
+        circuit.channel(KrausChannel::new_bit_flip(1.).unwrap(), &[1]).channel(KrausChannel::new_depolarizing(0.3, 2).unwrap(), &[0, 1]);
+        circuit.channel(KrausChannel::new_phase_flip(0.5).unwrap(), &[0]);
+        let mut state: StabilizerState = StabilizerState::new(2);
+        circuit.run(&mut state).unwrap();
+
+        let mut circuit: QuantumCircuit = QuantumCircuit::new_with_qubits(1);
+        circuit.channel(KrausChannel::new_bit_flip(1.).unwrap(), &[0]).measure(0, 0);
+        circuit.add_classical_register("c", 1).unwrap();
+        assert_eq!(vec![1], circuit.run(&mut StabilizerState::new(1)).unwrap());
+
+        circuit.channel(KrausChannel::new_amplitude_damping(0.1).unwrap(), &[0]);
+        assert_eq!(Err(QuantumError::NonCliffordGate("kraus".to_string())), circuit.run(&mut StabilizerState::new(1)));
+    }
+}
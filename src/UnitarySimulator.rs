@@ -0,0 +1,152 @@
+use rand::Rng;
+use crate::Backend::Backend;
+use crate::Gates::Gate;
+use crate::Instruction::Instruction;
+use crate::Matrix::Matrix;
+use crate::Noise::KrausChannel;
+use crate::Precision::Precision;
+use crate::QuantumError::QuantumError;
+
+/**
+Backend that accumulates the 2^n x 2^n unitary of the gates run on it instead of a state
+Each gate is expanded to the whole register and multiplied onto the product so far (U = G_m ... G_2 G_1), which costs O(8^n) per gate,
+so it is meant for checking decompositions and comparing small circuits
+Measurements, resets and noise channels have no unitary and are rejected with NonUnitaryOperation
+*/
+pub struct UnitarySimulator<T: Precision = f32> {
+    num_qubits: usize,
+    unitary: Matrix<T>,
+}
+
+impl<T: Precision> UnitarySimulator<T> {
+    // Starts from the identity on num_qubits qubits
+    pub fn new(num_qubits: usize) -> Self {
+        Self {num_qubits, unitary: Matrix::new_identity(1 << num_qubits)}
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    /**
+    Unitary of every gate applied so far, the first qubit being the left most factor like Gate::expand
+    Rounding in the products can drift away from unitarity, so the result is checked first: every entry of U * U^† may be off
+    from the identity by at most tolerance, otherwise NonUnitaryMatrix is returned
+    */
+    pub fn unitary(&self, tolerance: T) -> Result<Gate<T>, QuantumError> {
+        if !self.unitary.is_unitary(tolerance) {
+            return Err(QuantumError::NonUnitaryMatrix)
+        }
+        Ok(Gate::new_unchecked(self.unitary.clone()))
+    }
+
+    // Back to the identity
+    pub fn reset_unitary(&mut self) {
+        self.unitary = Matrix::new_identity(1 << self.num_qubits);
+    }
+}
+
+impl<T: Precision> Backend<T> for UnitarySimulator<T> {
+    fn num_qubits(&self) -> usize {
+        UnitarySimulator::num_qubits(self)
+    }
+
+    fn apply_instruction(&mut self, instruction: &Instruction<T>) -> Result<(), QuantumError> {
+        let gate: Gate<T> = instruction.gate()?;
+        gate.check_placement(&instruction.controls, &instruction.targets, self.num_qubits)?;
+        let control_values: Vec<bool> = instruction.controls.iter().map(|control| control.on_one).collect();
+        let qubits: Vec<i32> = instruction.qubits().iter().map(|&qubit| qubit as i32).collect();
+        let mut full: Matrix<T> = Gate::new_controlled(&gate, &control_values).expand(&qubits, self.num_qubits as i32)?.matrix().clone();
+        let current: Matrix<T> = std::mem::replace(&mut self.unitary, Matrix::new_zero(0));
+        self.unitary = full.matrix_multiplication(current);
+        Ok(())
+    }
+
+    fn measure_qubits_with_rng<R: Rng + ?Sized>(&mut self, _qubits: &[usize], _rng: &mut R) -> Result<Vec<i32>, QuantumError> {
+        Err(QuantumError::NonUnitaryOperation("measure".to_string()))
+    }
+
+    fn apply_channel_with_rng<R: Rng + ?Sized>(&mut self, _channel: &KrausChannel<T>, _targets: &[usize], _rng: &mut R) -> Result<(), QuantumError> {
+        Err(QuantumError::NonUnitaryOperation("kraus".to_string()))
+    }
+
+    fn reset_with_rng<R: Rng + ?Sized>(&mut self, _qubit: usize, _rng: &mut R) -> Result<(), QuantumError> {
+        Err(QuantumError::NonUnitaryOperation("reset".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Complex;
+    use crate::Instruction::Control;
+    use crate::QuantumCircuit::QuantumCircuit;
+    use crate::QuantumRegister::QuantumRegister;
+
+    fn assert_gates_close(g1: &Gate<f64>, g2: &Gate<f64>) {
+        for (row1, row2) in g1.rows().iter().zip(g2.rows().iter()) {
+            for (a, b) in row1.iter().zip(row2.iter()) {
+                assert!((a - b).norm_sqr() < 1e-18, "{} {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_state_vector() {
+        // Column j of the unitary is the circuit run on |j>
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(4);
+        circuit.h(0).rx(1, 0.3).cx(0, 3).u(2, 0.2, 0.5, -0.4).ccx(3, 1, 2).mcx(&[Control::new_negated(3)], 1);
+        circuit.iswap(3, 0).qft(&[2, 3, 0]).cp(2, 1, 0.7).barrier(&[0, 1]).sdg(1);
+        let unitary: Gate<f64> = circuit.to_unitary(1e-9).unwrap();
+        assert_eq!(4, unitary.num_qubits());
+        for column in 0..16 {
+            let mut register: QuantumRegister<f64> = QuantumRegister::new_from_int(column, 16);
+            circuit.run(&mut register).unwrap();
+            for row in 0..16 {
+                let amplitude: Complex<f64> = unitary.rows()[row][column];
+                assert!((amplitude - register.state[row]).norm_sqr() < 1e-18);
+            }
+        }
+
+        // Running the circuit on the backend directly gives the same unitary
+        let mut simulator: UnitarySimulator<f64> = UnitarySimulator::new(4);
+        circuit.run(&mut simulator).unwrap();
+        assert_gates_close(&unitary, &simulator.unitary(1e-9).unwrap());
+        simulator.reset_unitary();
+        assert_gates_close(&Gate::new_identity().expand(&[2], 4).unwrap(), &simulator.unitary(1e-9).unwrap());
+    }
+
+    #[test]
+    fn test_decompositions() {
+        let mut swap: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        swap.cx(0, 1).cx(1, 0).cx(0, 1);
+        assert_gates_close(&Gate::new_swap(), &swap.to_unitary(1e-9).unwrap());
+
+        let mut cz: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        cz.h(1).cx(0, 1).h(1);
+        assert_gates_close(&Gate::new_cz(), &cz.to_unitary(1e-9).unwrap());
+
+        // Controls are placed on their own qubits, not next to the targets
+        let mut toffoli: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(3);
+        toffoli.ccx(2, 0, 1);
+        assert_gates_close(&Gate::new_toffoli().expand(&[2, 0, 1], 3).unwrap(), &toffoli.to_unitary(1e-9).unwrap());
+    }
+
+    #[test]
+    fn test_non_unitary_circuits() {
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 1).unwrap();
+        circuit.h(0).measure(0, 0);
+        assert_eq!(Err(QuantumError::NonUnitaryOperation("measure".to_string())), circuit.to_unitary(1e-9).map(|_| ()));
+
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.add_classical_register("c", 1).unwrap();
+        circuit.x(1).c_if("c", 0);
+        assert_eq!(Err(QuantumError::NonUnitaryOperation("x".to_string())), circuit.to_unitary(1e-9).map(|_| ()));
+
+        let mut circuit: QuantumCircuit<f64> = QuantumCircuit::new_with_qubits(2);
+        circuit.reset(1);
+        assert_eq!(Err(QuantumError::NonUnitaryOperation("reset".to_string())), circuit.to_unitary(1e-9).map(|_| ()));
+        assert_eq!(Err(QuantumError::NonUnitaryOperation("reset".to_string())), circuit.run(&mut UnitarySimulator::new(2)));
+    }
+}
@@ -4,7 +4,21 @@ pub mod Gates;
 pub mod Matrix;
 pub mod QuantumRegister;
 pub mod QuantumCircuit;
+pub mod Instruction;
 pub mod Euclid;
+pub mod QuantumError;
+pub mod Precision;
+pub mod OpenQasm;
+pub mod Backend;
+pub mod DensityMatrix;
+pub mod Noise;
+pub mod ReadoutError;
+pub mod StabilizerState;
+pub mod MatrixProductState;
+pub mod SparseRegister;
+pub mod UnitarySimulator;
+#[cfg(feature = "serde")]
+pub mod Serialization;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right